@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::{print_command, NdsError};
+
+/// A source location of an address, as resolved by `arm-none-eabi-addr2line`.
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    pub address: u64,
+    /// The function and `file:line`, or `None` if the address isn't in the
+    /// debug info.
+    pub resolved: Option<(String, String)>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.resolved {
+            Some((function, line)) => write!(f, "{:#010x} -> {function} at {line}", self.address),
+            None => write!(f, "{:#010x} -> ??", self.address),
+        }
+    }
+}
+
+/// Parse the output of `addr2line -f`: two lines (function, then `file:line`)
+/// for each address.
+fn parse_output(addresses: &[u64], output: &str) -> Vec<Location> {
+    let mut lines = output.lines();
+
+    addresses
+        .iter()
+        .map(|&address| {
+            let function = lines.next().unwrap_or("??");
+            let line = lines.next().unwrap_or("??:0");
+            let resolved = if function == "??" && line.starts_with("??") {
+                None
+            } else {
+                Some((function.to_string(), line.to_string()))
+            };
+            Location { address, resolved }
+        })
+        .collect()
+}
+
+/// Resolve the addresses to source locations with the debug info of the ELF
+/// file, using `arm-none-eabi-addr2line`.
+pub fn resolve(elf: &Path, addresses: &[u64]) -> Result<Vec<Location>, NdsError> {
+    let mut command = Command::new("arm-none-eabi-addr2line");
+    command
+        .args(["-f", "-C", "-e"])
+        .arg(elf)
+        .args(addresses.iter().map(|address| format!("{address:#x}")));
+
+    print_command(&command);
+
+    let output = command
+        .output()
+        .map_err(|e| NdsError::spawn("arm-none-eabi-addr2line", e))?;
+    if !output.status.success() {
+        return Err(NdsError::Failed {
+            tool: String::from("arm-none-eabi-addr2line"),
+            status: output.status,
+            details: Some(
+                String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_string(),
+            ),
+        });
+    }
+
+    Ok(parse_output(
+        addresses,
+        &String::from_utf8_lossy(&output.stdout),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_locations() {
+        let output = "game::update\n/game/src/main.rs:42\n??\n??:0\n";
+        let locations = parse_output(&[0x0200_1234, 0x10], output);
+
+        assert_eq!(
+            locations[0].to_string(),
+            "0x02001234 -> game::update at /game/src/main.rs:42"
+        );
+        assert_eq!(locations[1].to_string(), "0x00000010 -> ??");
+    }
+}
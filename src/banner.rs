@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{AnimationStep, BannerText, LANGUAGE_COUNT};
+use crate::icon::ICON_SIZE;
+
+/// Banner version with the animated DSi icon.
+const DSI_BANNER_VERSION: u16 = 0x0103;
+/// Size of a banner with the animated DSi icon.
+const DSI_BANNER_SIZE: usize = 0x23c0;
+
+/// Maximum number of different frames (bitmaps) in the animated icon.
+pub const MAX_FRAMES: usize = 8;
+/// Maximum number of steps in the animation sequence.
+pub const MAX_STEPS: usize = 64;
+
+/// Size of a 32×32 icon bitmap at 4 bits per pixel.
+const BITMAP_SIZE: usize = 0x200;
+/// Size of a title, in UTF-16 code units.
+const TITLE_LENGTH: usize = 0x80;
+
+// Offsets of the banner fields
+const ICON_BITMAP: usize = 0x20;
+const ICON_PALETTE: usize = 0x220;
+const TITLES: usize = 0x240;
+const ANIMATED_BITMAPS: usize = 0x1240;
+const ANIMATED_PALETTES: usize = 0x2240;
+const ANIMATION_SEQUENCE: usize = 0x2340;
+
+/// An icon as stored in the banner: 8×8 tiles of 4 bit palette indices, and
+/// the 16 color palette in BGR555.
+struct Icon {
+    bitmap: [u8; BITMAP_SIZE],
+    palette: [u16; 16],
+}
+
+/// Build a banner with an animated DSi icon, which ndstool can embed with `-t`.
+///
+/// `frames` are 16 color BMP images, each with its own palette. They're shown
+/// in the order given by `sequence`, which loops.
+pub fn build_dsi_banner(
+    ds_icon: &Path,
+    frames: &[PathBuf],
+    sequence: &[AnimationStep],
+    text: &[BannerText; LANGUAGE_COUNT],
+) -> Result<Vec<u8>, String> {
+    if frames.is_empty() || frames.len() > MAX_FRAMES {
+        return Err(format!(
+            "the animated icon must have between 1 and {MAX_FRAMES} frames, not {}",
+            frames.len()
+        ));
+    }
+    if sequence.is_empty() || sequence.len() > MAX_STEPS {
+        return Err(format!(
+            "the icon animation must have between 1 and {MAX_STEPS} steps, not {}",
+            sequence.len()
+        ));
+    }
+
+    let mut banner = vec![0; DSI_BANNER_SIZE];
+    banner[..2].copy_from_slice(&DSI_BANNER_VERSION.to_le_bytes());
+
+    write_icon(&mut banner, ICON_BITMAP, ICON_PALETTE, &read_icon(ds_icon)?);
+
+    // The Chinese and Korean titles, which follow the others, use the English text
+    let english = std::iter::repeat_n(&text[1], 2);
+    for (index, text) in text.iter().chain(english).enumerate() {
+        let offset = TITLES + index * TITLE_LENGTH * 2;
+        write_title(&mut banner[offset..offset + TITLE_LENGTH * 2], text);
+    }
+
+    for (index, frame) in frames.iter().enumerate() {
+        write_icon(
+            &mut banner,
+            ANIMATED_BITMAPS + index * BITMAP_SIZE,
+            ANIMATED_PALETTES + index * 0x20,
+            &read_icon(frame)?,
+        );
+    }
+
+    for (index, step) in sequence.iter().enumerate() {
+        if step.frame >= frames.len() {
+            return Err(format!(
+                "step {index} of the icon animation shows frame {}, but there are only {} frames",
+                step.frame,
+                frames.len()
+            ));
+        }
+        if step.duration == 0 {
+            return Err(format!(
+                "step {index} of the icon animation has no duration"
+            ));
+        }
+
+        // Duration in 1/60 s, then the bitmap and palette indices and flips
+        let token = u16::from(step.duration)
+            | (step.frame as u16) << 8
+            | (step.frame as u16) << 11
+            | u16::from(step.flip_horizontal) << 14
+            | u16::from(step.flip_vertical) << 15;
+        let offset = ANIMATION_SEQUENCE + index * 2;
+        banner[offset..offset + 2].copy_from_slice(&token.to_le_bytes());
+    }
+
+    // One CRC for each version of the banner, then one for the DSi icon
+    for (index, range) in [
+        (0, 0x20..0x840),
+        (1, 0x20..0x940),
+        (2, 0x20..0xa40),
+        (3, ANIMATED_BITMAPS..DSI_BANNER_SIZE),
+    ] {
+        let crc = crc16(&banner[range]);
+        banner[2 + index * 2..4 + index * 2].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    Ok(banner)
+}
+
+fn write_icon(banner: &mut [u8], bitmap: usize, palette: usize, icon: &Icon) {
+    banner[bitmap..bitmap + BITMAP_SIZE].copy_from_slice(&icon.bitmap);
+    for (index, color) in icon.palette.iter().enumerate() {
+        let offset = palette + index * 2;
+        banner[offset..offset + 2].copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+/// Names of the languages of the banner titles, in order. Banner version 1 has
+/// the first 6, version 2 adds Chinese and version 3 adds Korean.
+pub const LANGUAGE_NAMES: [&str; 8] = [
+    "japanese", "english", "french", "german", "italian", "spanish", "chinese", "korean",
+];
+
+/// Read the titles of a banner, by language, as written by [`write_title`].
+pub fn read_titles(banner: &[u8]) -> Result<Vec<(&'static str, String)>, String> {
+    let version = banner
+        .get(..2)
+        .map(|version| u16::from_le_bytes([version[0], version[1]]))
+        .ok_or("the banner is truncated")?;
+    let count = match version & 0xff {
+        1 => LANGUAGE_COUNT,
+        2 => LANGUAGE_COUNT + 1,
+        3 => LANGUAGE_COUNT + 2,
+        _ => return Err(format!("unknown banner version {version:#06x}")),
+    };
+
+    (0..count)
+        .map(|index| {
+            let offset = TITLES + index * TITLE_LENGTH * 2;
+            let title = banner
+                .get(offset..offset + TITLE_LENGTH * 2)
+                .ok_or("the banner is truncated")?;
+            let units: Vec<u16> = title
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            Ok((LANGUAGE_NAMES[index], String::from_utf16_lossy(&units)))
+        })
+        .collect()
+}
+
+/// Write the banner text as UTF-16, with a line for each non-empty field.
+fn write_title(dst: &mut [u8], text: &BannerText) {
+    let lines: Vec<&str> = [&text.title, &text.subtitle, &text.manufacturer]
+        .into_iter()
+        .filter_map(|line| line.as_deref())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    // The last code unit is kept as the null terminator
+    let units = lines
+        .join("\n")
+        .encode_utf16()
+        .take(TITLE_LENGTH - 1)
+        .collect::<Vec<_>>();
+    for (index, unit) in units.into_iter().enumerate() {
+        dst[index * 2..index * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+    }
+}
+
+/// Read a 32×32 BMP with a 16 color palette.
+fn read_icon(path: &Path) -> Result<Icon, String> {
+    let bmp = fs::read(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    parse_bmp(&bmp).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn parse_bmp(bmp: &[u8]) -> Result<Icon, String> {
+    let u16_at = |offset: usize| -> Result<u16, String> {
+        bmp.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| String::from("truncated BMP"))
+    };
+    let u32_at = |offset: usize| -> Result<u32, String> {
+        Ok(u32::from(u16_at(offset)?) | u32::from(u16_at(offset + 2)?) << 16)
+    };
+
+    if bmp.get(..2) != Some(b"BM") {
+        return Err(String::from("not a BMP image"));
+    }
+
+    let data_offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bits_per_pixel = u16_at(28)?;
+    let compression = u32_at(30)?;
+
+    if width != ICON_SIZE as i32 || height.unsigned_abs() != ICON_SIZE {
+        return Err(format!(
+            "the icon is {width}×{}, but must be {ICON_SIZE}×{ICON_SIZE}",
+            height.abs()
+        ));
+    }
+    if bits_per_pixel != 4 || compression != 0 {
+        return Err(String::from(
+            "animated icons must be uncompressed 16 color BMPs (or PNGs, which are converted)",
+        ));
+    }
+
+    let mut palette = [0; 16];
+    for (index, color) in palette.iter_mut().enumerate() {
+        let offset = 14 + header_size + index * 4;
+        let Some(&[b, g, r, _]) = bmp.get(offset..offset + 4) else {
+            break;
+        };
+        *color = u16::from(r >> 3) | u16::from(g >> 3) << 5 | u16::from(b >> 3) << 10;
+    }
+
+    let row_size = ICON_SIZE as usize / 2;
+    let data = bmp
+        .get(data_offset..data_offset + row_size * ICON_SIZE as usize)
+        .ok_or_else(|| String::from("truncated BMP"))?;
+
+    let mut bitmap = [0; BITMAP_SIZE];
+    for y in 0..ICON_SIZE as usize {
+        // Rows are stored bottom-up unless the height is negative
+        let row = if height > 0 {
+            ICON_SIZE as usize - 1 - y
+        } else {
+            y
+        };
+        for x in 0..ICON_SIZE as usize {
+            let byte = data[row * row_size + x / 2];
+            let index = if x % 2 == 0 { byte >> 4 } else { byte & 0xf };
+
+            // Tiles of 8×8 pixels, with the left pixel in the low nibble
+            let tile = (y / 8) * 4 + x / 8;
+            let offset = tile * 32 + (y % 8) * 4 + (x % 8) / 2;
+            bitmap[offset] |= if x % 2 == 0 { index } else { index << 4 };
+        }
+    }
+
+    Ok(Icon { bitmap, palette })
+}
+
+/// The CRC-16 (Modbus) used by the banner checksums.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    /// A 16 color BMP where every pixel has the color `index`.
+    fn solid_bmp(index: u8) -> Vec<u8> {
+        let mut bmp = b"BM".to_vec();
+        bmp.extend((14 + 40 + 64 + 512u32).to_le_bytes());
+        bmp.extend(0u32.to_le_bytes());
+        bmp.extend((14 + 40 + 64u32).to_le_bytes());
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(32i32.to_le_bytes());
+        bmp.extend(32i32.to_le_bytes());
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(4u16.to_le_bytes());
+        bmp.extend([0; 24]);
+        for color in 0..16u8 {
+            bmp.extend([color * 16, 0, 0xff, 0]);
+        }
+        bmp.extend([index << 4 | index; 512]);
+        bmp
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x4b37);
+    }
+
+    #[test]
+    fn parse_icon() {
+        let icon = parse_bmp(&solid_bmp(3)).unwrap();
+        assert!(icon.bitmap.iter().all(|&byte| byte == 0x33));
+        // Red, with 3 × 16 of blue
+        assert_eq!(icon.palette[3], 0x1f | (48 >> 3) << 10);
+
+        let mut bmp = solid_bmp(0);
+        bmp[28] = 8;
+        assert!(parse_bmp(&bmp).is_err());
+    }
+
+    #[test]
+    fn animated_banner() {
+        let dir = TempDir::new("banner");
+        let frames: Vec<PathBuf> = (1..=2)
+            .map(|index| {
+                let path = dir.join(format!("frame{index}.bmp"));
+                fs::write(&path, solid_bmp(index)).unwrap();
+                path
+            })
+            .collect();
+
+        let step = |frame, duration| AnimationStep {
+            frame,
+            duration,
+            flip_horizontal: false,
+            flip_vertical: frame == 1,
+        };
+        let text = std::array::from_fn(|_| BannerText {
+            title: Some(String::from("Game")),
+            subtitle: None,
+            manufacturer: Some(String::from("Me")),
+        });
+
+        let banner =
+            build_dsi_banner(&frames[0], &frames, &[step(0, 10), step(1, 20)], &text).unwrap();
+        assert_eq!(banner.len(), DSI_BANNER_SIZE);
+        assert_eq!(banner[..2], [0x03, 0x01]);
+        let titles = read_titles(&banner).unwrap();
+        assert_eq!(titles.len(), 8);
+        assert_eq!(titles[1], ("english", String::from("Game\nMe")));
+        assert_eq!(
+            u16::from_le_bytes([banner[8], banner[9]]),
+            crc16(&banner[0x1240..])
+        );
+
+        // Frame 1 uses bitmap and palette 1, flipped vertically
+        assert_eq!(
+            banner[ANIMATION_SEQUENCE..ANIMATION_SEQUENCE + 6],
+            [10, 0, 20, 0x89, 0, 0]
+        );
+        assert!(banner[ANIMATED_BITMAPS + BITMAP_SIZE..][..BITMAP_SIZE]
+            .iter()
+            .all(|&byte| byte == 0x22));
+
+        // "Game\nMe" in UTF-16, in every language including Korean
+        let title: Vec<u8> = "Game\nMe"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(banner[TITLES..TITLES + title.len()], title);
+        assert_eq!(banner[0x940..0x940 + title.len()], title);
+
+        assert!(build_dsi_banner(&frames[0], &frames, &[step(2, 10)], &text).is_err());
+        assert!(build_dsi_banner(&frames[0], &frames, &[step(0, 0)], &text).is_err());
+        assert!(build_dsi_banner(&frames[0], &frames, &[], &text).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Zip archives of a built ROM and its assets, made by `cargo nds bundle`.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+use crate::config::Config;
+use crate::{absolute_path, NDSConfig, NdsError};
+
+/// The files of an archive: their name in the archive, and their source path.
+pub type Entries = Vec<(String, PathBuf)>;
+
+/// The default name of the archive: the ROM's name and the package version.
+pub fn default_archive_name(config: &NDSConfig) -> String {
+    let name = config
+        .path_nds()
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    match &config.version {
+        Some(version) => format!("{name}-{version}.zip"),
+        None => format!("{name}.zip"),
+    }
+}
+
+/// The files to bundle: the ROM and its icon at the root of the archive, then
+/// the `bundle` files of `nds.toml` at their path in the package.
+pub fn entries(config: &NDSConfig, nds_config: &Config) -> Result<Entries, NdsError> {
+    let package_dir = config.cargo_manifest_path.parent().unwrap();
+    let icon = match nds_config.ds_icon() {
+        Some(icon) => package_dir.join(icon),
+        None => PathBuf::from(&config.icon),
+    };
+
+    let mut entries = Vec::new();
+    for path in [config.path_nds(), icon] {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        entries.push((name, path));
+    }
+
+    for file in &nds_config.bundle {
+        let path = package_dir.join(file);
+        if !path.is_file() {
+            return Err(NdsError::Input(format!(
+                "Could not find bundle file: {}",
+                absolute_path(&path).display()
+            )));
+        }
+        entries.push((entry_name(file), path));
+    }
+
+    Ok(entries)
+}
+
+/// The name in the archive of a path relative to the package, which always
+/// uses `/` as the separator.
+fn entry_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write a zip archive of the `entries` to `path`, deflating them. Their date
+/// is 1980-01-01 (the earliest one), so that bundling the same files always
+/// gives the same archive.
+pub fn write_zip(path: &Path, entries: &[(String, PathBuf)]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut archive = ZipWriter::new(File::create(path)?);
+
+    for (name, source) in entries {
+        let source_err =
+            |e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", source.display()));
+        let mut file = File::open(source).map_err(source_err)?;
+        let size = file.metadata().map_err(source_err)?.len();
+
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(9))
+            .last_modified_time(DateTime::default())
+            .large_file(size >= u64::from(u32::MAX));
+        archive.start_file(name.as_str(), options)?;
+        io::copy(&mut file, &mut archive).map_err(source_err)?;
+    }
+
+    archive.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn zip_archive() {
+        let dir = TempDir::new("bundle");
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("game.nds"), [0x2e; 1000]).unwrap();
+        fs::write(dir.join("docs/README.md"), "# My game\n").unwrap();
+
+        let entries = vec![
+            ("game.nds".to_string(), dir.join("game.nds")),
+            (
+                entry_name(Path::new("docs/README.md")),
+                dir.join("docs/README.md"),
+            ),
+        ];
+        let archive = dir.join("out/game-1.0.0.zip");
+        write_zip(&archive, &entries).unwrap();
+        let first = fs::read(&archive).unwrap();
+
+        let mut zip = ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        assert_eq!(zip.len(), 2);
+        for (index, (name, source)) in entries.iter().enumerate() {
+            let mut file = zip.by_index(index).unwrap();
+            assert_eq!(file.name(), name);
+            assert_eq!(file.compression(), CompressionMethod::Deflated);
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, fs::read(source).unwrap());
+        }
+
+        // The same files give the same archive
+        write_zip(&archive, &entries).unwrap();
+        assert_eq!(fs::read(&archive).unwrap(), first);
+    }
+}
@@ -0,0 +1,95 @@
+//! Checksums of the built ROM, for `--checksum` and `--checksum-file`.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The checksums of a file.
+#[derive(Debug, PartialEq)]
+pub struct Checksums {
+    /// SHA-256, as lowercase hex.
+    pub sha256: String,
+    /// CRC32, as used by ROM databases and the like.
+    pub crc32: u32,
+}
+
+impl Checksums {
+    /// Compute the checksums of the file at `path`.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut sha256 = Sha256::new();
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            sha256.update(&buffer[..read]);
+            crc32.update(&buffer[..read]);
+        }
+
+        Ok(Self {
+            sha256: hex(&sha256.finalize()),
+            crc32: crc32.finalize(),
+        })
+    }
+
+    /// Write [`sha256_path`] next to the file, in the format of `sha256sum`,
+    /// so that it can be checked with `sha256sum -c`.
+    pub fn write_sha256_file(&self, path: &Path) -> io::Result<PathBuf> {
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let sha256_path = sha256_path(path);
+        fs::write(&sha256_path, format!("{}  {file_name}\n", self.sha256))?;
+        Ok(sha256_path)
+    }
+}
+
+/// Where the SHA-256 of the file at `path` is written: `<file name>.sha256`.
+pub fn sha256_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(format!("{file_name}.sha256"))
+}
+
+/// The start of the SHA-256 of `bytes`, as 16 lowercase hex digits. Unlike
+/// [`DefaultHasher`](std::collections::hash_map::DefaultHasher), it's the same
+/// across Rust releases, so it can name files which are kept between builds.
+pub fn short_sha256(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes)[..8])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn checksum_file() {
+        let dir = TempDir::new("checksum");
+        let rom = dir.join("game.nds");
+        fs::write(&rom, "123456789").unwrap();
+
+        let checksums = Checksums::of_file(&rom).unwrap();
+        assert_eq!(checksums.crc32, 0xcbf43926);
+        assert_eq!(
+            checksums.sha256,
+            "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225"
+        );
+
+        let sha256_path = checksums.write_sha256_file(&rom).unwrap();
+        assert_eq!(sha256_path, dir.join("game.nds.sha256"));
+        assert_eq!(super::sha256_path(&rom), sha256_path);
+        assert_eq!(
+            fs::read_to_string(sha256_path).unwrap(),
+            format!("{}  game.nds\n", checksums.sha256)
+        );
+    }
+}
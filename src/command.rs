@@ -1,12 +1,33 @@
-use std::{env, fs};
-use std::io::Read;
-use std::process::Stdio;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::sync::OnceLock;
-
-use cargo_metadata::Message;
-use clap::{Args, Parser, Subcommand};
-
-use crate::{build_nds, cargo, get_metadata, link, print_command, NDSConfig};
+use std::{env, fs, io};
+
+use cargo_metadata::{Message, MetadataCommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use toml_edit::DocumentMut;
+
+use crate::addr2line;
+use crate::bundle;
+use crate::checksum::{self, Checksums};
+use crate::config::{parse_size, Host, RunConfig};
+use crate::diff;
+use crate::disasm::{disassemble, parse_address, DisasmOptions};
+use crate::info::RomInfo;
+use crate::log::{debug, info, Level};
+use crate::prepare::{Prepared, Preparing};
+use crate::runner::runner_configured;
+use crate::size::SizeReport;
+use crate::target::SCAFFOLD_FILES;
+use crate::template::{merge_manifest, parse_template, Template};
+use crate::{
+    absolute_path, build_arm7_crate, build_nds_with_assets, check_rom_size, get_metadata, link,
+    load_build_cache, load_config, load_run_config, loader_command, output_path, require_emulator,
+    reset_command, reset_device, resolve_arm7, run_emulator, run_tests_in_emulator, target_dir,
+    target_name, target_spec, write_build_cache, NDSConfig, NdsError, KNOWN_EMULATORS,
+    KNOWN_TEST_EMULATORS, LINKER_MAP, RENDERED_FORMATS,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "cargo", bin_name = "cargo")]
@@ -27,10 +48,225 @@ pub struct Input {
     #[arg(long, short = 'v', global = true)]
     pub verbose: bool,
 
+    /// Don't print cargo-nds' progress messages, only errors and warnings.
+    /// Cargo's own output is not affected.
+    #[arg(long, short = 'q', global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
     /// Set cargo configuration on the command line. This is equivalent to
     /// cargo's `--config` option.
     #[arg(long, global = true)]
     pub config: Vec<String>,
+
+    /// Use a different target instead of `armv5te-nintendo-ds.json`: either the
+    /// path to a target spec JSON file, or the name of a target known to rustc.
+    /// ndstool still expects the built executable to be DS-compatible.
+    #[arg(long, global = true, value_name = "SPEC")]
+    pub target: Option<String>,
+
+    /// Path of the target spec JSON file to use. Overrides `target_spec` in
+    /// `nds.toml` and the `armv5te-nintendo-ds.json` found in the package
+    /// directory or one of its parents.
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "target")]
+    pub target_spec: Option<PathBuf>,
+
+    /// Comma-separated standard library crates to build with `-Z build-std`,
+    /// e.g. `core,alloc,std`. Overrides `build_std` in `nds.toml`.
+    #[arg(long, global = true, value_name = "CRATES", value_delimiter = ',')]
+    pub build_std: Option<Vec<String>>,
+
+    /// Comma-separated features of the standard library to enable with
+    /// `-Z build-std-features`. Overrides `build_std_features` in `nds.toml`.
+    #[arg(long, global = true, value_name = "FEATURES", value_delimiter = ',')]
+    pub build_std_features: Option<Vec<String>>,
+
+    /// Write the compiler's diagnostics to a SARIF file, for use with code
+    /// scanning tools.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub emit_sarif: Option<PathBuf>,
+
+    /// Print the target spec that would be used, why it was chosen, and its
+    /// key settings, then exit without building.
+    #[arg(long, global = true)]
+    pub print_target: bool,
+
+    /// Print the commands that would be run (cargo, ndstool and the loader or
+    /// emulator) without running them. Arguments which depend on the output
+    /// of the build are predicted, with a note.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub cargo_flags: CargoFlags,
+}
+
+/// Cargo options which apply to every cargo command cargo-nds runs, including
+/// `cargo metadata` and the build of the ARM7 package. They are also moved here
+/// from the cargo arguments of the subcommand, see [`Input::extract_cargo_flags`].
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+pub struct CargoFlags {
+    /// Directory for all generated artifacts, as with cargo's `--target-dir`.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub target_dir: Option<PathBuf>,
+
+    /// Path to the `Cargo.toml` of the package, as with cargo's `--manifest-path`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Run without accessing the network, as with cargo's `--offline`.
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Require `Cargo.lock` to be up to date, as with cargo's `--locked`.
+    #[arg(long, global = true)]
+    pub locked: bool,
+
+    /// Equivalent to `--locked --offline`, as with cargo's `--frozen`.
+    #[arg(long, global = true)]
+    pub frozen: bool,
+
+    /// When to color the output of cargo, and the diagnostics cargo-nds
+    /// renders for `--message-format human|short`. Defaults to coloring
+    /// when stderr is a terminal.
+    #[arg(long, global = true, value_name = "WHEN")]
+    pub color: Option<ColorChoice>,
+}
+
+/// The values of `--color`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Never => "never",
+        }
+    }
+}
+
+impl CargoFlags {
+    /// The cargo options to pass to commands which work on an existing package.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(dir) = &self.target_dir {
+            args.extend([
+                "--target-dir".to_string(),
+                dir.to_string_lossy().into_owned(),
+            ]);
+        }
+        if let Some(path) = &self.manifest_path {
+            args.extend([
+                "--manifest-path".to_string(),
+                path.to_string_lossy().into_owned(),
+            ]);
+        }
+        args.extend(self.network_args());
+        if let Some(color) = self.color {
+            args.extend(["--color".to_string(), color.as_str().to_string()]);
+        }
+        args
+    }
+
+    /// Whether to color the diagnostics rendered by cargo-nds: as chosen with
+    /// `--color`, or for a terminal unless `NO_COLOR` is set.
+    pub fn use_color(&self) -> bool {
+        match self.color {
+            Some(ColorChoice::Always) => true,
+            Some(ColorChoice::Never) => false,
+            Some(ColorChoice::Auto) | None => {
+                env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && io::stderr().is_terminal()
+            }
+        }
+    }
+
+    /// Whether cargo must not access the network: with `--offline` or
+    /// `--frozen`, or `CARGO_NET_OFFLINE=true`.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+            || self.frozen
+            || env::var("CARGO_NET_OFFLINE").is_ok_and(|value| value == "true")
+    }
+
+    /// The options about the network and `Cargo.lock`, which every cargo
+    /// command accepts, including `cargo new`.
+    pub fn network_args(&self) -> Vec<String> {
+        [
+            ("--offline", self.offline),
+            ("--locked", self.locked),
+            ("--frozen", self.frozen),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(flag, _)| flag.to_string())
+        .collect()
+    }
+
+    /// A `cargo metadata` command with these options. Its target directory is
+    /// the `--target-dir`, if set.
+    pub fn metadata_command(&self) -> MetadataCommand {
+        let mut command = MetadataCommand::new();
+        command.no_deps().other_options(self.network_args());
+        if let Some(path) = &self.manifest_path {
+            command.manifest_path(path);
+        }
+        if let Some(dir) = &self.target_dir {
+            command.env("CARGO_TARGET_DIR", absolute_path(dir));
+        }
+        command
+    }
+
+    /// Move the options from cargo arguments (before any `--`) into `self`.
+    fn extract_from(&mut self, cargo_args: &mut Vec<String>) {
+        let end = cargo_args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(cargo_args.len());
+        let mut rest = cargo_args.split_off(end);
+        let mut args = std::mem::take(cargo_args).into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let value_slot = match flag {
+                "--target-dir" => Some(&mut self.target_dir),
+                "--manifest-path" => Some(&mut self.manifest_path),
+                _ => None,
+            };
+            if let Some(slot) = value_slot {
+                if let Some(value) = inline_value.or_else(|| args.next()) {
+                    *slot = Some(PathBuf::from(value));
+                }
+                continue;
+            }
+            if flag == "--color" {
+                // An invalid value is left for cargo to report
+                let value = inline_value.or_else(|| args.next()).unwrap_or_default();
+                match ColorChoice::from_str(&value, false) {
+                    Ok(color) => self.color = Some(color),
+                    Err(_) => cargo_args.extend(["--color".to_string(), value]),
+                }
+                continue;
+            }
+
+            match arg.as_str() {
+                "--offline" => self.offline = true,
+                "--locked" => self.locked = true,
+                "--frozen" => self.frozen = true,
+                _ => cargo_args.push(arg),
+            }
+        }
+
+        cargo_args.append(&mut rest);
+    }
 }
 
 /// Run a cargo command. COMMAND will be forwarded to the real
@@ -58,6 +294,33 @@ pub enum CargoCmd {
 
     Init(Init),
 
+    /// Removes generated artifacts, including the `.nds` files and linker map.
+    Clean(Clean),
+
+    /// Builds an executable and reports the size of its sections and of the `.nds` file.
+    Size(Size),
+
+    /// Builds the `.nds` file and prints its header fields and banner titles.
+    Info(Info),
+
+    /// Builds the `.nds` file and zips it for distribution, with its icon and
+    /// the files listed in `bundle` in `nds.toml`.
+    Bundle(Bundle),
+
+    /// Builds an executable and disassembles it with `arm-none-eabi-objdump`.
+    Disasm(Disasm),
+
+    /// Builds an executable and resolves addresses, such as the ones printed
+    /// by a crash handler, to functions and source lines.
+    Addr2line(Addr2line),
+
+    /// Checks that the toolchain and tools needed to build and run DS apps are set up.
+    Doctor,
+
+    /// Rebuilds the `.nds` file every time a source file or asset changes,
+    /// optionally running it after each successful build.
+    Watch(Watch),
+
     // NOTE: it seems docstring + name for external subcommands are not rendered
     // in help, but we might as well set them here in case a future version of clap
     // does include them in help text.
@@ -87,8 +350,46 @@ pub struct RemainingArgs {
 
 #[derive(Args, Debug)]
 pub struct Build {
-    #[arg(from_global)]
-    pub verbose: bool,
+    /// Fail if the `.nds` file is bigger than this size, e.g. `512K` or `8M`.
+    /// Overrides `max_rom_size` in `nds.toml`.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Build a DSi-enhanced ROM, with the `.dsi` extension. Also enabled by
+    /// `dsi = true` in `nds.toml`.
+    #[arg(long)]
+    pub dsi: bool,
+
+    /// Also write a copy of the ROM with the package version in its name, e.g.
+    /// `game-1.2.0.nds`, next to it. Also enabled by `versioned_copy = true`
+    /// in `nds.toml`.
+    #[arg(long)]
+    pub versioned_copy: bool,
+
+    /// Write the ROM to this directory instead of next to the executable.
+    /// Keeps the file name of `output` in `nds.toml`, if set.
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// After building, print a JSON object to stdout with the paths of the
+    /// ROM, the executables, the icon, banner and RomFS it was built from,
+    /// and the banner text.
+    #[arg(long)]
+    pub emit_build_json: bool,
+
+    /// Use the ROM of the last build instead of running cargo, e.g. to send
+    /// it again with `cargo nds run`, or to inspect it with `cargo nds info`.
+    #[arg(long, conflicts_with = "emit_build_json")]
+    pub no_build: bool,
+
+    /// After building, print the SHA-256 and CRC32 of the ROM.
+    #[arg(long, conflicts_with = "emit_build_json")]
+    pub checksum: bool,
+
+    /// Write the SHA-256 of the ROM to `<rom>.sha256` next to it, in the
+    /// format of `sha256sum`.
+    #[arg(long)]
+    pub checksum_file: bool,
 
     // Passthrough cargo options.
     #[command(flatten)]
@@ -97,12 +398,19 @@ pub struct Build {
 
 #[derive(Args, Debug)]
 pub struct Run {
-    /// Specify the IP address of the device to send the executable to.
+    /// Specify the IP address or hostname of the device to send the executable to.
     ///
     /// Corresponds to ndslink's `--address` arg, which defaults to automatically
-    /// finding the device.
+    /// finding the device. Defaults to the `--device` profile's address,
+    /// `CARGO_NDS_ADDRESS` or `address` in `nds.toml`.
     #[arg(long, short = 'a')]
-    pub address: Option<std::net::Ipv4Addr>,
+    pub address: Option<Host>,
+
+    /// Send the executable to a device defined in a `[devices.<NAME>]` table
+    /// of `nds.toml`, using its address, argv0 and server settings unless they
+    /// are given on the command line.
+    #[arg(long, value_name = "NAME")]
+    pub device: Option<String>,
 
     /// Set the 0th argument of the executable when running it. Corresponds to
     /// ndslink's `--argv0` argument.
@@ -114,18 +422,34 @@ pub struct Run {
     #[arg(long, short = 's', default_value_t = false)]
     pub server: bool,
 
-    /// Set the number of tries when connecting to the device to send the executable.
-    /// Corresponds to ndslink's `--retries` argument.
+    /// Set the number of times to run ndslink again when it fails to send the
+    /// executable, e.g. because the device wasn't found in time.
     // Can't use `short = 'r'` because that would conflict with cargo's `--release/-r`
     #[arg(long)]
     pub retries: Option<usize>,
 
+    /// Reset the device before sending the executable, using the `loader.reset`
+    /// command configured in `nds.toml`.
+    #[arg(long)]
+    pub reset: bool,
+
+    /// Run the executable in an emulator instead of sending it to a device.
+    ///
+    /// The emulator can be given by name or path, otherwise it's taken from the
+    /// `CARGO_NDS_EMULATOR` environment variable, the `emulator` key of `nds.toml`,
+    /// or the first of melonDS, DeSmuME and no$gba found in $PATH.
+    #[arg(long, value_name = "EMULATOR", num_args = 0..=1, require_equals = true)]
+    pub emulator: Option<Option<String>>,
+
     // Passthrough `cargo build` options.
     #[command(flatten)]
     pub build_args: Build,
 
     #[arg(from_global)]
     config: Vec<String>,
+
+    #[arg(from_global)]
+    target: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -135,11 +459,16 @@ pub struct Test {
     pub no_run: bool,
 
     /// If set, documentation tests will be built instead of unit tests.
-    /// This implies `--no-run`, unless Cargo's `target.armv6k-nintendo-nds.runner`
+    /// This implies `--no-run`, unless Cargo's `target.armv5te-nintendo-ds.runner`
     /// is configured.
-    #[arg(long)]
+    #[arg(long, conflicts_with = "emulator")]
     pub doc: bool,
 
+    /// Re-build and re-run the tests every time a file in the workspace changes,
+    /// until interrupted with Ctrl-C.
+    #[arg(long)]
+    pub watch: bool,
+
     // The test command uses a superset of the same arguments as Run.
     #[command(flatten)]
     pub run_args: Run,
@@ -151,6 +480,28 @@ pub struct New {
     #[arg(required = true)]
     pub path: String,
 
+    /// Scaffold to start from: one of the built-in templates (`console`,
+    /// `graphics` or `audio`), or the path of a template directory.
+    #[arg(long, value_name = "NAME|PATH", value_parser = parse_template, default_value = "console")]
+    pub template: Template,
+
+    /// Overwrite existing files (such as `src/main.rs` or `.cargo/config.toml`)
+    /// with the nds templates, after showing the changes and asking for
+    /// confirmation.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Don't ask for confirmation before overwriting files with `--force`.
+    #[arg(long, short = 'y', requires = "force")]
+    pub yes: bool,
+
+    /// Don't create or add the build outputs to the `.gitignore`.
+    #[arg(long)]
+    pub no_gitignore: bool,
+
+    #[command(flatten)]
+    pub libnds_sys: LibndsSys,
+
     // The test command uses a superset of the same arguments as Run.
     #[command(flatten)]
     pub cargo_args: RemainingArgs,
@@ -162,16 +513,188 @@ pub struct Init {
     #[arg(required = false)]
     pub path: String,
 
+    /// Overwrite existing files (such as `src/main.rs` or `.cargo/config.toml`)
+    /// with the nds templates, after showing the changes and asking for
+    /// confirmation.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Don't ask for confirmation before overwriting files with `--force`.
+    #[arg(long, short = 'y', requires = "force")]
+    pub yes: bool,
+
+    /// Don't create or add the build outputs to the `.gitignore`.
+    #[arg(long)]
+    pub no_gitignore: bool,
+
+    #[command(flatten)]
+    pub libnds_sys: LibndsSys,
+
     // The test command uses a superset of the same arguments as Run.
     #[command(flatten)]
     pub cargo_args: RemainingArgs,
 }
 
+/// Where the `libnds_sys` dependency added by `new` and `init` comes from.
+#[derive(Args, Debug, Default)]
+pub struct LibndsSys {
+    /// Depend on this version of `libnds_sys` from crates.io instead of its git
+    /// repository. Defaults to `CARGO_NDS_LIBNDS_SYS_VERSION`.
+    #[arg(long, value_name = "VERSION", conflicts_with = "libnds_sys_git")]
+    pub libnds_sys_version: Option<String>,
+
+    /// Depend on `libnds_sys` from this git repository, e.g. a fork. Defaults
+    /// to `CARGO_NDS_LIBNDS_SYS_GIT`, then the upstream repository.
+    #[arg(long, value_name = "URL")]
+    pub libnds_sys_git: Option<String>,
+
+    /// Pin the git dependency to this commit, tag or branch name. Defaults to
+    /// `CARGO_NDS_LIBNDS_SYS_REV`.
+    #[arg(long, value_name = "REV", conflicts_with = "libnds_sys_version")]
+    pub libnds_sys_rev: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct Clean {
+    #[arg(from_global)]
+    target: Option<String>,
+
+    // Passthrough `cargo clean` options.
+    #[command(flatten)]
+    pub passthrough: RemainingArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct Size {
+    /// Print the sizes as JSON instead of a table.
+    #[arg(long)]
+    pub json: bool,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub build_args: Build,
+}
+
+#[derive(Args, Debug)]
+pub struct Info {
+    /// Print the fields as JSON instead of text.
+    #[arg(long)]
+    pub json: bool,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub build_args: Build,
+}
+
+#[derive(Args, Debug)]
+pub struct Bundle {
+    /// Path of the archive. Defaults to `<name>-<version>.zip` in the target
+    /// directory.
+    #[arg(long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub build_args: Build,
+}
+
+#[derive(Args, Debug)]
+pub struct Watch {
+    /// Send the ROM to the device after each successful build, as with
+    /// `cargo nds run`.
+    #[arg(long)]
+    pub run: bool,
+
+    /// Run the ROM in an emulator after each successful build, as with
+    /// `cargo nds run --emulator`.
+    #[arg(long, value_name = "EMULATOR", num_args = 0..=1, require_equals = true)]
+    pub emulator: Option<Option<String>>,
+
+    /// Clear the terminal before each build.
+    #[arg(long)]
+    pub clear: bool,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub passthrough: RemainingArgs,
+}
+
+impl Watch {
+    /// Whether the ROM is run after each build, which needs `cargo nds run`.
+    pub fn runs(&self) -> bool {
+        self.run || self.emulator.is_some()
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct Disasm {
+    /// Only disassemble this function.
+    #[arg(long, value_name = "NAME")]
+    pub symbol: Option<String>,
+
+    /// Only disassemble around this address, e.g. `0x02001234`.
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    pub address: Option<u64>,
+
+    /// Number of bytes to disassemble before and after `--address`.
+    #[arg(long, value_name = "BYTES", default_value_t = 64, requires = "address")]
+    pub context: u64,
+
+    /// Don't interleave the source code, which is shown by default when the
+    /// executable has debug info.
+    #[arg(long)]
+    pub no_source: bool,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub build_args: Build,
+}
+
+#[derive(Args, Debug)]
+pub struct Addr2line {
+    /// Addresses in the ARM9 executable, e.g. `-a 0x02001234,0x02005678`.
+    /// Can be repeated.
+    #[arg(
+        long = "address",
+        short = 'a',
+        required = true,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        value_delimiter = ','
+    )]
+    pub addresses: Vec<u64>,
+
+    // Passthrough `cargo build` options.
+    #[command(flatten)]
+    pub build_args: Build,
+}
+
+impl Input {
+    /// Move the [`CargoFlags`] given among the subcommand's cargo arguments to
+    /// [`Input::cargo_flags`], so they are passed to every cargo command.
+    pub fn extract_cargo_flags(&mut self) {
+        if let Some(cargo_args) = self.cmd.cargo_args_mut() {
+            self.cargo_flags.extract_from(cargo_args);
+        }
+    }
+
+    /// The verbosity chosen with `--quiet` or `--verbose`.
+    pub fn log_level(&self) -> Level {
+        if self.quiet {
+            Level::Quiet
+        } else if self.verbose {
+            Level::Verbose
+        } else {
+            Level::Normal
+        }
+    }
+}
+
 impl CargoCmd {
     /// Returns the additional arguments run by the "official" cargo subcommand.
     pub fn cargo_args(&self) -> Vec<String> {
         match self {
-            CargoCmd::Build(build) =>build.passthrough.cargo_args(),
+            CargoCmd::Build(build) => build.passthrough.cargo_args(),
             CargoCmd::Run(run) => run.build_args.passthrough.cargo_args(),
             CargoCmd::Test(test) => test.cargo_args(),
             CargoCmd::New(new) => {
@@ -180,14 +703,22 @@ impl CargoCmd {
                 cargo_args.push(new.path.clone());
 
                 cargo_args
-            },
+            }
             CargoCmd::Init(init) => {
                 // We push the original path in the init command (we captured it in [`Init`] to learn about the context)
                 let mut cargo_args = init.cargo_args.cargo_args();
                 cargo_args.push(init.path.clone());
 
                 cargo_args
-            },
+            }
+            CargoCmd::Clean(clean) => clean.passthrough.cargo_args(),
+            CargoCmd::Size(size) => size.build_args.passthrough.cargo_args(),
+            CargoCmd::Info(info) => info.build_args.passthrough.cargo_args(),
+            CargoCmd::Bundle(bundle) => bundle.build_args.passthrough.cargo_args(),
+            CargoCmd::Disasm(disasm) => disasm.build_args.passthrough.cargo_args(),
+            CargoCmd::Addr2line(addr2line) => addr2line.build_args.passthrough.cargo_args(),
+            CargoCmd::Doctor => Vec::new(),
+            CargoCmd::Watch(watch) => watch.passthrough.cargo_args(),
             CargoCmd::Passthrough(other) => other.clone().split_off(1),
         }
     }
@@ -201,9 +732,14 @@ impl CargoCmd {
     /// if no custom runner is configured).
     pub fn subcommand_name(&self) -> &str {
         match self {
-            CargoCmd::Build(_) => "build",
+            CargoCmd::Build(_)
+            | CargoCmd::Size(_)
+            | CargoCmd::Info(_)
+            | CargoCmd::Bundle(_)
+            | CargoCmd::Disasm(_)
+            | CargoCmd::Addr2line(_) => "build",
             CargoCmd::Run(run) => {
-                if run.use_custom_runner() {
+                if run.use_cargo_runner() {
                     "run"
                 } else {
                     "build"
@@ -212,25 +748,73 @@ impl CargoCmd {
             CargoCmd::Test(_) => "test",
             CargoCmd::New(_) => "new",
             CargoCmd::Init(_) => "init",
+            CargoCmd::Clean(_) => "clean",
+            CargoCmd::Doctor => "doctor",
+            CargoCmd::Watch(_) => "watch",
             CargoCmd::Passthrough(cmd) => &cmd[0],
         }
     }
 
+    /// Whether cargo is asked to build as many targets as it can with
+    /// `--keep-going`, rather than stopping at the first that fails.
+    pub fn keeps_going(&self) -> bool {
+        self.cargo_args().iter().any(|arg| arg == "--keep-going")
+    }
+
     /// Whether or not this command should compile any code, and thus needs import the custom environment configuration (e.g. target spec).
     pub fn should_compile(&self) -> bool {
         matches!(
             self,
-            Self::Build(_) | Self::Run(_) | Self::Test(_) | Self::Passthrough(_)
+            Self::Build(_)
+                | Self::Run(_)
+                | Self::Test(_)
+                | Self::Size(_)
+                | Self::Info(_)
+                | Self::Bundle(_)
+                | Self::Disasm(_)
+                | Self::Addr2line(_)
+                | Self::Passthrough(_)
         )
     }
 
+    /// The build options of a command which builds a ROM.
+    fn build_args(&self) -> Option<&Build> {
+        match self {
+            Self::Build(build) => Some(build),
+            Self::Run(run) => Some(&run.build_args),
+            Self::Test(test) => Some(&test.run_args.build_args),
+            Self::Size(size) => Some(&size.build_args),
+            Self::Info(info) => Some(&info.build_args),
+            Self::Bundle(bundle) => Some(&bundle.build_args),
+            Self::Disasm(disasm) => Some(&disasm.build_args),
+            Self::Addr2line(addr2line) => Some(&addr2line.build_args),
+            _ => None,
+        }
+    }
+
+    /// Whether `--dsi` was passed to a command which builds a ROM.
+    fn dsi(&self) -> bool {
+        self.build_args().is_some_and(|build| build.dsi)
+    }
+
+    /// Whether `--versioned-copy` was passed.
+    fn versioned_copy(&self) -> bool {
+        self.build_args().is_some_and(|build| build.versioned_copy)
+    }
+
     /// Whether or not this command should build a ndsX executable file.
     pub fn should_build_ndsx(&self) -> bool {
         match self {
-            Self::Build(_) | CargoCmd::Run(_) => true,
+            Self::Build(_)
+            | CargoCmd::Run(_)
+            | Self::Size(_)
+            | Self::Info(_)
+            | Self::Bundle(_)
+            | Self::Disasm(_)
+            | Self::Addr2line(_) => true,
             &Self::Test(Test { doc, .. }) => {
                 if doc {
-                    eprintln!("Documentation tests requested, no ndsx will be built");
+                    info!("Documentation tests requested, no ndsx will be built");
                     false
                 } else {
                     true
@@ -240,26 +824,61 @@ impl CargoCmd {
         }
     }
 
+    /// Whether the command builds a ROM, as [`Self::should_build_ndsx`] but
+    /// without saying why doc tests don't.
+    pub fn builds_rom(&self) -> bool {
+        self.build_args().is_some() && !matches!(self, Self::Test(Test { doc: true, .. }))
+    }
+
     /// Whether or not the resulting executable should be sent to the nds with
     /// `ndslink`.
     pub fn should_link_to_device(&self) -> bool {
         match self {
             Self::Test(Test { no_run: true, .. }) => false,
-            Self::Run(run) | Self::Test(Test { run_args: run, .. }) => !run.use_custom_runner(),
+            Self::Run(run) | Self::Test(Test { run_args: run, .. }) => {
+                !run.use_cargo_runner() && run.emulator.is_none()
+            }
             _ => false,
         }
     }
 
+    /// Set the target resolved by [`crate::resolve_target`] on the subcommands
+    /// which read it.
+    pub fn set_target(&mut self, target: &str) {
+        match self {
+            Self::Run(run) | Self::Test(Test { run_args: run, .. }) => {
+                run.target = Some(target.to_string());
+            }
+            Self::Clean(clean) => clean.target = Some(target.to_string()),
+            _ => {}
+        }
+    }
+
     pub const DEFAULT_MESSAGE_FORMAT: &'static str = "json-render-diagnostics";
 
+    /// The arguments passed through to cargo, if the command has any.
+    fn cargo_args_mut(&mut self) -> Option<&mut Vec<String>> {
+        match self {
+            Self::Build(build) => Some(&mut build.passthrough.args),
+            Self::Run(run) => Some(&mut run.build_args.passthrough.args),
+            Self::New(new) => Some(&mut new.cargo_args.args),
+            Self::Init(init) => Some(&mut init.cargo_args.args),
+            Self::Clean(clean) => Some(&mut clean.passthrough.args),
+            Self::Size(size) => Some(&mut size.build_args.passthrough.args),
+            Self::Info(info) => Some(&mut info.build_args.passthrough.args),
+            Self::Bundle(bundle) => Some(&mut bundle.build_args.passthrough.args),
+            Self::Disasm(disasm) => Some(&mut disasm.build_args.passthrough.args),
+            Self::Addr2line(addr2line) => Some(&mut addr2line.build_args.passthrough.args),
+            Self::Doctor => None,
+            Self::Watch(watch) => Some(&mut watch.passthrough.args),
+            Self::Test(test) => Some(&mut test.run_args.build_args.passthrough.args),
+            Self::Passthrough(args) => Some(args),
+        }
+    }
+
     pub fn extract_message_format(&mut self) -> Result<Option<String>, String> {
-        let cargo_args = match self {
-            Self::Build(build) => &mut build.passthrough.args,
-            Self::Run(run) => &mut run.build_args.passthrough.args,
-            Self::New(new) => &mut new.cargo_args.args,
-            Self::Init(init) => &mut init.cargo_args.args,
-            Self::Test(test) => &mut test.run_args.build_args.passthrough.args,
-            Self::Passthrough(args) => args,
+        let Some(cargo_args) = self.cargo_args_mut() else {
+            return Ok(None);
         };
 
         let format = Self::extract_message_format_from_args(cargo_args)?;
@@ -280,8 +899,11 @@ impl CargoCmd {
     fn extract_message_format_from_args(
         cargo_args: &mut Vec<String>,
     ) -> Result<Option<String>, String> {
+        // Only cargo's own arguments are looked at, not the executable's after `--`
+        let cargo_end = |args: &[String]| args.iter().position(|s| s == "--").unwrap_or(args.len());
+
         // Checks for a position within the args where '--message-format' is located
-        if let Some(pos) = cargo_args
+        if let Some(pos) = cargo_args[..cargo_end(cargo_args)]
             .iter()
             .position(|s| s.starts_with("--message-format"))
         {
@@ -293,21 +915,81 @@ impl CargoCmd {
             // otherwise remove next arg which is now at the same position as the original flag.
             let format = if let Some((_, format)) = arg.split_once('=') {
                 format.to_string()
-            } else {
+            } else if pos < cargo_end(cargo_args) {
                 // Also need to remove the argument to the --message-format option
                 cargo_args.remove(pos)
+            } else {
+                return Err(String::from("`--message-format` requires a value"));
             };
 
-            // Non-json formats are not supported so the executable exits.
-            if format.starts_with("json") {
-                Ok(Some(format))
+            // A second one would still be passed to cargo, and conflict with
+            // the format cargo-nds uses
+            if cargo_args[..cargo_end(cargo_args)]
+                .iter()
+                .any(|s| s.starts_with("--message-format"))
+            {
+                return Err(String::from(
+                    "`--message-format` was passed more than once, specify it once",
+                ));
+            }
+
+            Self::canonical_message_format(&format).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Validate a `--message-format` value, which like for cargo may be a
+    /// comma-separated list of options, and normalize it so that each option
+    /// is given once, in the same order. The `json-` prefix of the JSON
+    /// options may be left out, as in `json,diagnostic-rendered-ansi`.
+    fn canonical_message_format(format: &str) -> Result<String, String> {
+        const JSON_OPTIONS: &[&str] = &[
+            "diagnostic-short",
+            "diagnostic-rendered-ansi",
+            "render-diagnostics",
+        ];
+
+        let mut rendered = Vec::new();
+        let mut json = false;
+        let mut json_options = Vec::new();
+        for option in format.split(',').map(str::trim) {
+            let json_option = option.strip_prefix("json-").unwrap_or(option);
+            if RENDERED_FORMATS.contains(&option) {
+                rendered.push(option);
+            } else if option == "json" {
+                json = true;
+            } else if JSON_OPTIONS.contains(&json_option) {
+                json_options.push(json_option);
             } else {
-                Err(String::from(
-                    "error: non-JSON `message-format` is not supported",
+                // Other formats can't be rendered from JSON, so the executable exits.
+                return Err(format!(
+                    "unsupported `message-format` option `{option}`, expected one of `{}`, `json` or `json-{}`",
+                    RENDERED_FORMATS.join("`, `"),
+                    JSON_OPTIONS.join("`, `json-")
+                ));
+            }
+        }
+
+        match rendered[..] {
+            [] => {}
+            [format] if !json && json_options.is_empty() => return Ok(format.to_string()),
+            _ => {
+                return Err(format!(
+                    "`message-format` {format} combines options which exclude each other"
                 ))
             }
+        }
+
+        let options: Vec<String> = JSON_OPTIONS
+            .iter()
+            .filter(|option| json_options.contains(option))
+            .map(|option| format!("json-{option}"))
+            .collect();
+        if options.is_empty() {
+            Ok(String::from("json"))
         } else {
-            Ok(None)
+            Ok(options.join(","))
         }
     }
 
@@ -317,24 +999,129 @@ impl CargoCmd {
     ///
     /// - `cargo nds build` and other "build" commands will use their callbacks to build the final `.ndsx` file and link it.
     /// - `cargo nds new` and other generic commands will use their callbacks to make nds-specific changes to the environment.
-    pub fn run_callback(&self, messages: &[Message]) {
+    ///
+    /// The assets `preparing` in the background while cargo was building (see
+    /// [`start`](crate::prepare::start)) are used to build the ROM if they're
+    /// still up to date.
+    pub fn run_callback(
+        &self,
+        messages: &[Message],
+        cargo_flags: &CargoFlags,
+        preparing: Option<Preparing>,
+    ) -> Result<(), NdsError> {
         // Process the metadata only for commands that have it/use it
         let config = if self.should_build_ndsx() {
-            eprintln!("Getting metadata");
+            info!("Getting metadata");
 
-            Some(get_metadata(messages))
+            let config = self.nds_config(messages, cargo_flags)?;
+            // Test executables aren't what `run --no-build` should send
+            if !matches!(self, Self::Test(_)) {
+                write_build_cache(&config, cargo_flags);
+            }
+            Some(config)
         } else {
             None
         };
 
+        let prepared = match (preparing, &config) {
+            (Some(preparing), Some(config)) => preparing.take(config).unwrap_or_default(),
+            _ => Prepared::default(),
+        };
+        self.callback(config, prepared, cargo_flags)
+    }
+
+    /// The [`NDSConfig`] of the executable cargo built, with the options of the
+    /// command and `nds.toml` applied, and the ARM7 executable resolved.
+    fn nds_config(
+        &self,
+        messages: &[Message],
+        cargo_flags: &CargoFlags,
+    ) -> Result<NDSConfig, NdsError> {
+        let config = get_metadata(messages, &self.cargo_args(), cargo_flags)?;
+        self.with_options(config, cargo_flags, true)
+    }
+
+    /// Apply the options of the command and `nds.toml` to the [`NDSConfig`] of
+    /// an executable. The `arm7_crate` package is only built if `build_arm7` is
+    /// set, otherwise `arm7` is left unset for the caller to fill in.
+    pub(crate) fn with_options(
+        &self,
+        mut config: NDSConfig,
+        cargo_flags: &CargoFlags,
+        build_arm7: bool,
+    ) -> Result<NDSConfig, NdsError> {
+        let nds_config = load_config(&config)?;
+        config.dsi = self.dsi() || nds_config.dsi;
+        config.versioned_copy = self.versioned_copy() || nds_config.versioned_copy;
+        config.arm7 = match (&nds_config.arm7, &nds_config.arm7_crate) {
+            (Some(arm7), _) => Some(resolve_arm7(&config, arm7)?),
+            (None, Some(_)) if !build_arm7 => None,
+            (None, Some(arm7_crate)) => Some(build_arm7_crate(
+                &config,
+                arm7_crate,
+                &self.cargo_args(),
+                cargo_flags,
+            )?),
+            (None, None) => None,
+        };
+        let out_dir = self.build_args().and_then(|build| build.out_dir.as_deref());
+        config.output = output_path(&config, nds_config.output.as_deref(), out_dir);
+        Ok(config)
+    }
+
+    /// Whether the command was given `--no-build`, to use the ROM of the last
+    /// build instead of running cargo.
+    pub fn no_build(&self) -> bool {
+        self.build_args().is_some_and(|build| build.no_build)
+    }
+
+    /// Run the callback of a command given `--no-build`, with the ROM of the
+    /// last build, see [`load_build_cache`].
+    pub fn run_cached_callback(&self, cargo_flags: &CargoFlags) -> Result<(), NdsError> {
+        let config =
+            load_build_cache(cargo_flags, &self.cargo_args()).map_err(NdsError::Executable)?;
+        info!(
+            "Using the last build: {}",
+            absolute_path(&config.path_nds()).display()
+        );
+        self.callback(Some(config), Prepared::default(), cargo_flags)
+    }
+
+    /// The commands the callback runs once the ROM is built, to run it in an
+    /// emulator or send it to the device, for `--dry-run`.
+    pub(crate) fn run_commands(&self, config: &NDSConfig) -> Result<Vec<Command>, NdsError> {
+        match self {
+            Self::Run(run) => run.run_commands(config),
+            Self::Test(test) if test.run_in_emulator() => {
+                let run_config = load_run_config(config, &test.run_args)?;
+                let emulator = require_emulator(&run_config, KNOWN_TEST_EMULATORS)?;
+                Ok(vec![emulator.command_for(&config.path_nds())])
+            }
+            Self::Test(test) if !test.no_run => test.run_args.run_commands(config),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn callback(
+        &self,
+        config: Option<NDSConfig>,
+        prepared: Prepared,
+        cargo_flags: &CargoFlags,
+    ) -> Result<(), NdsError> {
         // Run callback only for commands that use it
         match self {
-            Self::Build(cmd) => cmd.callback(&config),
-            Self::Run(cmd) => cmd.callback(&config),
-            Self::Test(cmd) => cmd.callback(&config),
+            Self::Build(cmd) => cmd.callback(&config, prepared),
+            Self::Run(cmd) => cmd.callback(&config, prepared),
+            Self::Test(cmd) => cmd.callback(&config, prepared),
             Self::New(cmd) => cmd.callback(),
             Self::Init(cmd) => cmd.callback(),
-            _ => (),
+            Self::Clean(cmd) => cmd.callback(cargo_flags),
+            Self::Size(cmd) => cmd.callback(&config, prepared),
+            Self::Info(cmd) => cmd.callback(&config, prepared),
+            Self::Bundle(cmd) => cmd.callback(&config, prepared, cargo_flags),
+            Self::Disasm(cmd) => cmd.callback(&config, prepared),
+            Self::Addr2line(cmd) => cmd.callback(&config, prepared),
+            _ => Ok(()),
         }
     }
 }
@@ -369,42 +1156,316 @@ impl Build {
     /// Callback for `cargo nds build`.
     ///
     /// This callback handles building the application as a `.ndsx` file.
-    fn callback(&self, config: &Option<NDSConfig>) {
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
         if let Some(config) = config {
-            eprintln!("Building nds: {}", config.path_nds().display());
-            build_nds(config, self.verbose);
+            if !self.no_build {
+                info!(
+                    "Building nds: {}",
+                    absolute_path(&config.path_nds()).display()
+                );
+                let output = build_nds_with_assets(config, prepared)?;
+                check_rom_size(config, self.max_size)?;
+                if self.emit_build_json {
+                    println!("{}", serde_json::to_string(&output).unwrap());
+                }
+            }
+            if self.checksum || self.checksum_file {
+                self.write_checksums(&config.path_nds())?;
+            }
         }
+        Ok(())
     }
-}
 
-impl Run {
-    /// Get the args to pass to `ndslink` based on these options.
-    pub fn get_dslink_args(&self) -> Vec<String> {
-        let mut args = Vec::new();
+    /// Print and/or write the checksums of the ROM, as asked with `--checksum`
+    /// and `--checksum-file`.
+    fn write_checksums(&self, path_nds: &Path) -> Result<(), NdsError> {
+        let checksums =
+            Checksums::of_file(path_nds).map_err(|e| NdsError::Read(path_nds.to_path_buf(), e))?;
 
-        if let Some(address) = self.address {
-            args.extend(["-a".to_string(), address.to_string()]);
+        if self.checksum {
+            println!("SHA-256: {}", checksums.sha256);
+            println!("CRC32:   {:08x}", checksums.crc32);
         }
-
+        if self.checksum_file {
+            let path = checksums
+                .write_sha256_file(path_nds)
+                .map_err(|e| NdsError::Write(checksum::sha256_path(path_nds), e))?;
+            info!("Wrote the checksum to {}", absolute_path(&path).display());
+        }
+        Ok(())
+    }
+}
+
+impl Size {
+    /// Callback for `cargo nds size`.
+    ///
+    /// This callback builds the `.nds` file, then reports the size of its components.
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
+        self.build_args.callback(config, prepared)?;
+
+        if let Some(config) = config {
+            let report = SizeReport::new(config)?;
+
+            if self.json {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            } else {
+                report.print();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Info {
+    /// Callback for `cargo nds info`.
+    ///
+    /// This callback builds the `.nds` file, then prints what its header and banner contain.
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
+        self.build_args.callback(config, prepared)?;
+
+        if let Some(config) = config {
+            let info = RomInfo::read(&config.path_nds()).map_err(NdsError::Input)?;
+
+            if self.json {
+                println!("{}", serde_json::to_string(&info).unwrap());
+            } else {
+                info.print();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Bundle {
+    /// Callback for `cargo nds bundle`.
+    ///
+    /// This callback builds the `.nds` file, then zips it with its icon and the
+    /// extra files from `nds.toml`.
+    fn callback(
+        &self,
+        config: &Option<NDSConfig>,
+        prepared: Prepared,
+        cargo_flags: &CargoFlags,
+    ) -> Result<(), NdsError> {
+        self.build_args.callback(config, prepared)?;
+
+        if let Some(config) = config {
+            let output = self.output.clone().unwrap_or_else(|| {
+                target_dir(cargo_flags).join(bundle::default_archive_name(config))
+            });
+            let entries = bundle::entries(config, &load_config(config)?)?;
+            bundle::write_zip(&output, &entries).map_err(|e| NdsError::Write(output.clone(), e))?;
+            info!(
+                "Bundled {} files into {}",
+                entries.len(),
+                absolute_path(&output).display()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Disasm {
+    /// Callback for `cargo nds disasm`.
+    ///
+    /// This callback builds the `.nds` file, then disassembles the ARM9 executable.
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
+        self.build_args.callback(config, prepared)?;
+
+        if let Some(config) = config {
+            let options = DisasmOptions {
+                symbol: self.symbol.clone(),
+                address: self.address,
+                context: self.context,
+                source: !self.no_source,
+            };
+            disassemble(&config.path_arm9(), options)?;
+        }
+        Ok(())
+    }
+}
+
+impl Addr2line {
+    /// Callback for `cargo nds addr2line`.
+    ///
+    /// This callback builds the `.nds` file, then prints the source location of each address.
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
+        self.build_args.callback(config, prepared)?;
+
+        if let Some(config) = config {
+            for location in addr2line::resolve(&config.path_arm9(), &self.addresses)? {
+                println!("{location}");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Clean {
+    /// Callback for `cargo nds clean`.
+    ///
+    /// `cargo clean` doesn't know about the `.nds` files and linker map we generate,
+    /// so this callback removes them too.
+    fn callback(&self, cargo_flags: &CargoFlags) -> Result<(), NdsError> {
+        let metadata = cargo_flags.metadata_command().exec()?;
+
+        let mut nds_dir = metadata
+            .target_directory
+            .join(target_name(target_spec(&self.target)))
+            .into_std_path_buf();
+
+        // Only clean the `.nds` files of the requested profile, like `cargo clean` does
+        if let Some(profile) = profile_dir(&self.passthrough.cargo_args()) {
+            nds_dir.push(profile);
+        }
+
+        for path in find_nds_files(&nds_dir) {
+            remove_file(&path)?;
+        }
+
+        // The linker writes its map in the target dir (see `make_cargo_command`)
+        remove_file(metadata.target_directory.join(LINKER_MAP).as_std_path())
+    }
+}
+
+/// Get the value of a `--flag value` or `--flag=value` style argument.
+pub(crate) fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == flag {
+            return iter.next().map(String::as_str);
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|s| s.strip_prefix('=')) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The directory of the profile selected by `--release` or `--profile` in the
+/// target directory, if any.
+pub(crate) fn profile_dir(args: &[String]) -> Option<&str> {
+    if args.iter().any(|arg| arg == "--release" || arg == "-r") {
+        return Some("release");
+    }
+    flag_value(args, "--profile").map(|profile| if profile == "dev" { "debug" } else { profile })
+}
+
+/// Recursively find all `.nds` and `.dsi` files in a directory. A missing
+/// directory has none.
+fn find_nds_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(find_nds_files(&path));
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "nds" || ext == "dsi")
+        {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Remove a file, ignoring it if it's already gone.
+fn remove_file(path: &Path) -> Result<(), NdsError> {
+    match fs::remove_file(path) {
+        Ok(()) => {
+            debug!("Removed {}", absolute_path(path).display());
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(NdsError::Write(absolute_path(path), e)),
+    }
+}
+
+impl Run {
+    /// Get the args to pass to `ndslink` based on these options, and the
+    /// settings resolved from the environment and config files.
+    pub fn get_dslink_args(&self, run_config: &RunConfig) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(address) = &run_config.address {
+            args.extend(["-a".to_string(), address.to_string()]);
+        }
+        if let Some(argv0) = &run_config.argv0 {
+            args.extend(["--argv0".to_string(), argv0.clone()]);
+        }
+        if run_config.server {
+            args.push("--server".to_string());
+        }
+
         args
     }
 
     /// Callback for `cargo nds run`.
     ///
     /// This callback handles launching the application via `dslink`.
-    fn callback(&self, config: &Option<NDSConfig>) {
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
         // Run the normal "build" callback
-        self.build_args.callback(config);
+        self.build_args.callback(config, prepared)?;
 
-        if !self.use_custom_runner() {
+        // With `--no-build`, cargo didn't run the executable
+        if self.build_args.no_build || !self.use_cargo_runner() {
             if let Some(cfg) = config {
-                eprintln!("Running dslink");
-                link(cfg, self, self.build_args.verbose);
+                self.send(cfg)?;
             }
         }
+        Ok(())
     }
 
-    /// Returns whether the cargo environment has `target.armv6k-nintendo-nds.runner`
+    /// The commands [`Self::callback`] runs, see [`CargoCmd::run_commands`].
+    fn run_commands(&self, cfg: &NDSConfig) -> Result<Vec<Command>, NdsError> {
+        // cargo runs the executable with the runner itself
+        if !self.build_args.no_build && self.use_cargo_runner() {
+            return Ok(Vec::new());
+        }
+
+        let run_config = load_run_config(cfg, self)?;
+        if self.emulator.is_some() {
+            let emulator = require_emulator(&run_config, KNOWN_EMULATORS)?;
+            return Ok(vec![emulator.command_for(&cfg.path_nds())]);
+        }
+
+        let mut commands = Vec::new();
+        if self.reset {
+            commands.push(reset_command(&run_config)?);
+        }
+        commands.push(loader_command(cfg, self, &run_config));
+        Ok(commands)
+    }
+
+    /// Run the built ROM, in an emulator or on the device.
+    fn send(&self, cfg: &NDSConfig) -> Result<(), NdsError> {
+        let run_config = load_run_config(cfg, self)?;
+
+        if self.emulator.is_some() {
+            info!("Running emulator");
+            return run_emulator(cfg, &run_config);
+        }
+
+        if self.reset {
+            info!("Resetting device");
+            reset_device(&run_config)?;
+        }
+
+        info!("Running {}", run_config.loader);
+        link(cfg, self, &run_config)
+    }
+
+    /// Returns whether the executable is run by cargo itself, using the custom runner.
+    /// Running in an emulator with `--emulator` takes precedence over the runner.
+    pub fn use_cargo_runner(&self) -> bool {
+        self.emulator.is_none() && self.use_custom_runner()
+    }
+
+    /// Returns whether the cargo environment has `target.<target>.runner`
     /// configured. This will only be checked once during the lifetime of the program,
     /// and takes into account the usual ways Cargo looks for its
     /// [configuration](https://doc.rust-lang.org/cargo/reference/config.html):
@@ -412,38 +1473,20 @@ impl Run {
     /// - `.cargo/config.toml`
     /// - Environment variables
     /// - Command-line `--config` overrides
+    ///
+    /// These are read directly, without running cargo.
     pub fn use_custom_runner(&self) -> bool {
         static HAS_RUNNER: OnceLock<bool> = OnceLock::new();
 
         let &custom_runner_configured = HAS_RUNNER.get_or_init(|| {
-            let blocksds = env::var("BLOCKSDS").unwrap_or("/opt/wonderful/thirdparty/blocksds/core".to_owned());
-            env::set_var("RUSTFLAGS", format!("-C link-args=-specs={blocksds}/sys/crts/ds_arm9.specs"));
-
-            let mut cmd = cargo(&self.config);
-            cmd.args([
-                // https://github.com/rust-lang/cargo/issues/9301
-                "-Z",
-                "build-std=core,alloc",
-                "--target",
-                "./armv5te-nintendo-ds.json"
-            ])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
-
-            if self.build_args.verbose {
-                print_command(&cmd);
-            }
-
-            // `cargo config get` exits zero if the config exists, or nonzero otherwise
-            cmd.status().map_or(false, |status| status.success())
+            let cwd = env::current_dir().unwrap_or_default();
+            runner_configured(&cwd, &self.config, &target_name(target_spec(&self.target)))
         });
 
-        if self.build_args.verbose {
-            eprintln!(
-                "Custom runner is {}configured",
-                if custom_runner_configured { "" } else { "not " }
-            );
-        }
+        debug!(
+            "Custom runner is {}configured",
+            if custom_runner_configured { "" } else { "not " }
+        );
 
         custom_runner_configured
     }
@@ -453,18 +1496,33 @@ impl Test {
     /// Callback for `cargo nds test`.
     ///
     /// This callback handles launching the application via `ndslink`.
-    fn callback(&self, config: &Option<NDSConfig>) {
+    fn callback(&self, config: &Option<NDSConfig>, prepared: Prepared) -> Result<(), NdsError> {
         if self.no_run {
             // If the tests don't have to run, use the "build" callback
-            self.run_args.build_args.callback(config);
+            self.run_args.build_args.callback(config, prepared)
+        } else if self.run_in_emulator() {
+            self.run_args.build_args.callback(config, prepared)?;
+            if let Some(cfg) = config {
+                let run_config = load_run_config(cfg, &self.run_args)?;
+                info!("Running tests in emulator");
+                run_tests_in_emulator(cfg, &run_config)?;
+            }
+            Ok(())
         } else {
             // If the tests have to run, use the "run" callback
-            self.run_args.callback(config);
+            self.run_args.callback(config, prepared)
         }
     }
 
+    /// Whether cargo runs the tests itself, with the custom runner.
     fn should_run(&self) -> bool {
-        self.run_args.use_custom_runner() && !self.no_run
+        self.run_args.use_cargo_runner() && !self.no_run
+    }
+
+    /// Whether the tests are run in an emulator once cargo has built them, and
+    /// their result is taken from the emulator's output.
+    fn run_in_emulator(&self) -> bool {
+        self.run_args.emulator.is_some() && !self.no_run
     }
 
     /// The args to pass to the underlying `cargo test` command.
@@ -472,7 +1530,8 @@ impl Test {
         let mut cargo_args = self.run_args.build_args.passthrough.cargo_args();
 
         // We can't run nds executables on the host, but we want to respect
-        // the user's "runner" configuration if set.
+        // the user's "runner" configuration if set. Tests run in an emulator
+        // are built with `--no-run` too, and run by cargo-nds afterwards.
         //
         // If doctests were requested, `--no-run` will be rejected on the
         // command line and must be set with RUSTDOCFLAGS instead:
@@ -504,193 +1563,353 @@ impl Test {
     }
 }
 
-const TOML_CHANGES: &str = r#"libnds_sys = { git = "https://github.com/SeleDreams/libnds-sys.git" }
+/// The git repository of `libnds_sys`, used unless another source is chosen.
+const LIBNDS_SYS_GIT: &str = "https://github.com/SeleDreams/libnds-sys.git";
 
+const NDS_METADATA: &str = r#"
 [package.metadata.nds]
-romfs_dir = "romfs"
+romfs = "romfs"
 "#;
 
-const TARGET_JSON: &str = r#"{
-    "abi": "eabi",
-    "arch": "arm",
-    "data-layout": "e-m:e-p:32:32-Fi8-i64:64-v128:64:128-a:0:32-n32-S64",
-    "env" : "picolibc",
-    "exe-suffix" : ".arm9.elf",
-    "is-builtin": false,
-    "linker": "arm-none-eabi-gcc",
-    "llvm-target": "armv5te-none-gnu",
-    "relocation-model": "static",
-    "target-endian": "little",
-    "target-pointer-width": "32",
-    "target-c-int-width": "32",
-    "executables": true,
-    "linker-flavor": "gcc",
-    "max-atomic-width": 32,
-    "disable-redzone": true,
-    "emit-debug-gdb-scripts": false,
-    "features" : "+soft-float,+strict-align,+atomics-32",
-    "panic-strategy" : "abort",
-    "linker-is-gnu": true,
-    "target-family": [
-        "unix"
-      ],
-    "no-default-libraries": false,
-    "main-needs-argc-argv":"false",
-    "pre-link-args": {
-        "gcc": [
-          "--data-sections",
-          "-march=armv5te",
-          "-mthumb",
-          "-mcpu=arm946e-s+nofp",
-          "-mthumb-interwork",
-          "-Wl,-Map,target/arm9.map",
-          "-Wl,--gc-sections"
-        ]
-      },
-      "post-link-args" : {
-        "gcc": [
-          "-Wl,--no-warn-rwx-segments",
-          "-Wl,--allow-multiple-definition"
-        ]
-      },
-      "late-link-args": {
-        "gcc": [
-            "-lgcc"
-        ]
-    },
-    "vendor" : "nintendo",
-    "os" : "nintendo_ds_arm9"
-  }  
-"#;
+impl LibndsSys {
+    /// The `libnds_sys` line to add to the `[dependencies]` table, from the
+    /// arguments, then the environment variables (looked up with `env`), then
+    /// [`LIBNDS_SYS_GIT`].
+    pub fn dependency(&self, env: impl Fn(&str) -> Option<String>) -> String {
+        let quote = |value: String| toml::Value::String(value).to_string();
+        let version = self.libnds_sys_version.clone();
+        let git = self.libnds_sys_git.clone();
+        let rev = self.libnds_sys_rev.clone();
+
+        // An explicit source on the command line ignores the environment
+        let (version, git, rev) = if version.is_some() || git.is_some() || rev.is_some() {
+            (version, git, rev)
+        } else {
+            (
+                env("CARGO_NDS_LIBNDS_SYS_VERSION"),
+                env("CARGO_NDS_LIBNDS_SYS_GIT"),
+                env("CARGO_NDS_LIBNDS_SYS_REV"),
+            )
+        };
 
-const CUSTOM_MAIN_RS: &str = r#"#![no_main]
-#![no_std]
-extern crate alloc;
-use libnds_sys::arm9_bindings::*;
-use libnds_sys::*;
-use core::ffi::*;
-#[no_mangle]
-extern "C" fn main() -> c_int
-{
-    unsafe
-    {
-        consoleDemoInit();       
-        println!("Hello World!");
-        loop {
-            swiWaitForVBlank();
-            scanKeys();
-            let keys = keysHeld();
-            if (keys & KEY_START) > 0
-            {
-                break;
+        match (version, git, rev) {
+            (Some(version), _, _) => format!("libnds_sys = {}\n", quote(version)),
+            (None, git, rev) => {
+                let git = quote(git.unwrap_or_else(|| LIBNDS_SYS_GIT.to_string()));
+                match rev {
+                    Some(rev) => format!("libnds_sys = {{ git = {git}, rev = {} }}\n", quote(rev)),
+                    None => format!("libnds_sys = {{ git = {git} }}\n"),
+                }
             }
         }
     }
-    return 0;
 }
-"#;
-
-const CUSTOM_CARGO_CONFIG : &str = r#"[profile.release]
-codegen-units = 1
-opt-level=3
-debug-assertions=false
-strip = "debuginfo"
-lto = true
-overflow-checks=false
-
-[profile.dev]
-codegen-units = 1
-debug=2
-opt-level=3
-debug-assertions=false
-lto = true
-overflow-checks=false
-strip = false
-"#;
 
 impl New {
     /// Callback for `cargo nds new`.
     ///
     /// This callback handles the custom environment modifications when creating a new nds project.
-    fn callback(&self) {
-        // Commmit changes to the project only if is meant to be a binary
-        if self.cargo_args.args.contains(&"--lib".to_string()) {
-            return;
-        }
-
-        // Attain a canonicalised path for the new project and it's TOML manifest
-        let project_path = fs::canonicalize(&self.path).unwrap();
-        let toml_path = project_path.join("Cargo.toml");
-        let romfs_path = project_path.join("romfs");
-        let main_rs_path = project_path.join("src/main.rs");
-        let target_json_path = project_path.join("armv5te-nintendo-ds.json");
-        let config_path = project_path.join(".cargo/config.toml");
-        
-        // Create the "romfs" directory
-        fs::create_dir(romfs_path).unwrap();
-
-        // Read the contents of `Cargo.toml` to a string
-        let mut buf = String::new();
-        fs::File::open(&toml_path)
-            .unwrap()
-            .read_to_string(&mut buf)
-            .unwrap();
+    fn callback(&self) -> Result<(), NdsError> {
+        let options = ScaffoldOptions {
+            force: self.force,
+            yes: self.yes,
+            gitignore: !self.no_gitignore,
+            template: self.template.clone(),
+            libnds_dependency: self.libnds_sys.dependency(|var| env::var(var).ok()),
+        };
+        scaffold_project(Path::new(&self.path), &self.cargo_args, &options)
+    }
+}
 
-        // Add the custom changes to the TOML
-        let buf = buf + TOML_CHANGES;
-        fs::write(&toml_path, buf).unwrap();
+impl Init {
+    /// Callback for `cargo nds init`.
+    ///
+    /// This callback handles the custom environment modifications when initializing an nds project.
+    fn callback(&self) -> Result<(), NdsError> {
+        let options = ScaffoldOptions {
+            force: self.force,
+            yes: self.yes,
+            gitignore: !self.no_gitignore,
+            libnds_dependency: self.libnds_sys.dependency(|var| env::var(var).ok()),
+            ..Default::default()
+        };
+        scaffold_project(Path::new(&self.path), &self.cargo_args, &options)
+    }
+}
 
-        // Add the custom changes to the main.rs file
-        fs::write(main_rs_path, CUSTOM_MAIN_RS).unwrap();
+/// Whether the project created by `cargo new` or `cargo init` is a binary.
+///
+/// An explicit `--bin` or `--lib` argument decides, otherwise the project
+/// is checked for a binary target, since `cargo init` picks the kind from the
+/// existing sources.
+fn is_binary_project(project_path: &Path, cargo_args: &[String]) -> bool {
+    let has_flag = |flag: &str| {
+        cargo_args
+            .iter()
+            .any(|arg| arg == flag || arg.starts_with(&format!("{flag}=")))
+    };
 
-        fs::write(target_json_path,TARGET_JSON).unwrap();
-        fs::create_dir(project_path.join(".cargo")).unwrap();
-        fs::write(config_path, CUSTOM_CARGO_CONFIG).unwrap();
+    if has_flag("--bin") {
+        return true;
+    }
+    if has_flag("--lib") {
+        return false;
+    }
 
+    if project_path.join("src/main.rs").exists() {
+        return true;
     }
+
+    fs::read_to_string(project_path.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .is_some_and(|manifest| manifest.contains_key("bin"))
 }
 
+/// The `src/main.rs` generated by `cargo new` and `cargo init`, which can be
+/// replaced without losing anything.
+const DEFAULT_MAIN_RS: &str = "fn main() {\n    println!(\"Hello, world!\");\n}\n";
+
+/// How [`scaffold_project`] sets up a project.
+struct ScaffoldOptions {
+    /// Overwrite the files which already have user content, after showing the
+    /// changes and confirming.
+    force: bool,
+    /// Overwrite without asking for confirmation.
+    yes: bool,
+    /// Add the build outputs to the `.gitignore`.
+    gitignore: bool,
+    /// The template the files come from.
+    template: Template,
+    /// The `libnds_sys` line added to the `[dependencies]` table.
+    libnds_dependency: String,
+}
 
-impl Init {
-    /// Callback for `cargo nds new`.
-    ///
-    /// This callback handles the custom environment modifications when creating a new nds project.
-    fn callback(&self) {
-        // Commmit changes to the project only if is meant to be a binary
-        if self.cargo_args.args.contains(&"--lib".to_string()) {
-            return;
-        }
-
-        // Attain a canonicalised path for the new project and it's TOML manifest
-        let project_path = fs::canonicalize(&self.path).unwrap();
-        let toml_path = project_path.join("Cargo.toml");
-        let romfs_path = project_path.join("romfs");
-        let main_rs_path = project_path.join("src/main.rs");
-        let target_json_path = project_path.join("armv5te-nintendo-ds.json");
-        let config_path = project_path.join(".cargo/config.toml");
-        
-        // Create the "romfs" directory
-        fs::create_dir(romfs_path).unwrap();
-
-        // Read the contents of `Cargo.toml` to a string
-        let mut buf = String::new();
-        fs::File::open(&toml_path)
-            .unwrap()
-            .read_to_string(&mut buf)
-            .unwrap();
+impl Default for ScaffoldOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            yes: false,
+            gitignore: true,
+            template: Template::default(),
+            libnds_dependency: LibndsSys::default().dependency(|_| None),
+        }
+    }
+}
+
+/// Add the nds-specific files and settings to a project created by `cargo new`
+/// or `cargo init`, starting from the template of the `options`.
+///
+/// Files which already have user content (e.g. `cargo init` in an existing
+/// project) are kept unless `force` is set, in which case the changes are shown
+/// and confirmed first (unless `yes` is set). Settings already present in
+/// `Cargo.toml` aren't added again, and neither are the entries already in the
+/// `.gitignore`, which is only updated if `gitignore` is set.
+fn scaffold_project(
+    path: &Path,
+    cargo_args: &RemainingArgs,
+    options: &ScaffoldOptions,
+) -> Result<(), NdsError> {
+    let ScaffoldOptions {
+        force,
+        yes,
+        gitignore,
+        template,
+        libnds_dependency,
+    } = options;
+
+    let write_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e| NdsError::Write(path, e)
+    };
+
+    // Attain a canonicalised path for the new project and it's TOML manifest
+    let project_path = fs::canonicalize(path).map_err(|e| NdsError::Read(path.to_path_buf(), e))?;
+
+    // Commmit changes to the project only if is meant to be a binary
+    if !is_binary_project(&project_path, &cargo_args.cargo_args()) {
+        return Ok(());
+    }
 
-        // Add the custom changes to the TOML
-        let buf = buf + TOML_CHANGES;
-        fs::write(&toml_path, buf).unwrap();
+    let template_files = template
+        .files()
+        .map_err(|e| NdsError::Config(format!("Could not read the template: {e}")))?;
+
+    let toml_path = project_path.join("Cargo.toml");
+    let mut templates: Vec<(PathBuf, String)> = SCAFFOLD_FILES
+        .iter()
+        .map(|(path, contents)| (project_path.join(path), contents.to_string()))
+        .collect();
+    // The template's files replace the default ones at the same path
+    for (relative, contents) in template_files.text {
+        let path = project_path.join(relative);
+        templates.retain(|(existing, _)| *existing != path);
+        templates.push((path, contents));
+    }
 
-        // Add the custom changes to the main.rs file
-        fs::write(main_rs_path, CUSTOM_MAIN_RS).unwrap();
+    let conflicts = find_conflicts(&templates);
+
+    // Create the "romfs" directory, if missing
+    let romfs = project_path.join("romfs");
+    fs::create_dir_all(&romfs).map_err(write_err(&romfs))?;
+
+    // Read the contents of `Cargo.toml` to a string
+    let mut buf = String::new();
+    fs::File::open(&toml_path)
+        .and_then(|mut file| file.read_to_string(&mut buf))
+        .map_err(|e| NdsError::Read(toml_path.clone(), e))?;
+
+    // Add the custom changes to the TOML, along with the template's manifest,
+    // editing it in place
+    let mut manifest: DocumentMut = buf
+        .parse()
+        .map_err(|e| NdsError::Config(format!("Could not parse {}: {e}", toml_path.display())))?;
+    if let Some(template_manifest) = &template_files.manifest {
+        merge_manifest(&mut manifest, template_manifest);
+    }
+    let changed = add_manifest_settings(&mut manifest, libnds_dependency)?;
+    if changed || template_files.manifest.is_some() {
+        fs::write(&toml_path, manifest.to_string()).map_err(write_err(&toml_path))?;
+    }
 
-        fs::write(target_json_path,TARGET_JSON).unwrap();
-        fs::create_dir(project_path.join(".cargo")).unwrap();
-        fs::write(config_path, CUSTOM_CARGO_CONFIG).unwrap();
+    for (path, contents) in templates {
+        if conflicts.contains(&path) && !(*force && confirm_overwrite(&path, &contents, *yes)) {
+            info!(
+                "Keeping existing {} (use --force to overwrite it)",
+                path.display()
+            );
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(write_err(parent))?;
+            }
+            fs::write(&path, contents).map_err(write_err(&path))?;
+        }
+    }
 
+    for (relative, source) in template_files.binary {
+        let path = project_path.join(relative);
+        let overwrite =
+            || *force && (*yes || diff::confirm(&format!("Overwrite {}?", path.display())));
+        if path.exists() && !overwrite() {
+            info!(
+                "Keeping existing {} (use --force to overwrite it)",
+                path.display()
+            );
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(write_err(parent))?;
+            }
+            fs::copy(&source, &path).map_err(write_err(&path))?;
+        }
     }
+
+    if *gitignore {
+        let gitignore_path = project_path.join(".gitignore");
+        let existing = fs::read_to_string(&gitignore_path).unwrap_or_default();
+        let additions = gitignore_additions(&existing);
+        if !additions.is_empty() {
+            let separator = if existing.is_empty() || existing.ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            fs::write(&gitignore_path, existing + separator + &additions)
+                .map_err(write_err(&gitignore_path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Build outputs which shouldn't be committed: cargo's target directory, the
+/// `.nds` files and the linker map.
+const GITIGNORE_ENTRIES: &[&str] = &["/target", "*.nds", "arm9.map"];
+
+/// The lines to append to a `.gitignore` for the [`GITIGNORE_ENTRIES`] it
+/// doesn't have yet. `target`, `target/` and `/target` are the same entry.
+fn gitignore_additions(gitignore: &str) -> String {
+    let normalize = |line: &str| line.trim().trim_matches('/').to_string();
+    let existing: Vec<String> = gitignore.lines().map(normalize).collect();
+
+    GITIGNORE_ENTRIES
+        .iter()
+        .filter(|entry| !existing.contains(&normalize(entry)))
+        .map(|entry| format!("{entry}\n"))
+        .collect()
+}
+
+/// Show how overwriting a file with its template would change it, and ask
+/// whether to go ahead, unless `yes` is set.
+fn confirm_overwrite(path: &Path, template: &str, yes: bool) -> bool {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    eprint!(
+        "{}",
+        diff::unified_diff(&path.display().to_string(), &existing, template)
+    );
+
+    yes || diff::confirm(&format!("Overwrite {}?", path.display()))
+}
+
+/// Get the files which already exist with different contents than their
+/// template, other than the `main.rs` generated by cargo.
+fn find_conflicts(templates: &[(PathBuf, impl AsRef<str>)]) -> Vec<PathBuf> {
+    templates
+        .iter()
+        .filter(|(path, contents)| match fs::read_to_string(path) {
+            Ok(existing) => existing != contents.as_ref() && existing != DEFAULT_MAIN_RS,
+            Err(_) => path.exists(),
+        })
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Add the `libnds_sys` dependency to the `[dependencies]` table of a
+/// `Cargo.toml` and the `[package.metadata.nds]` table, unless they're already
+/// there. Returns whether the manifest changed.
+fn add_manifest_settings(
+    manifest: &mut DocumentMut,
+    libnds_dependency: &str,
+) -> Result<bool, NdsError> {
+    let mut changed = false;
+
+    let dependencies = manifest
+        .entry("dependencies")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| {
+            NdsError::Config(String::from("`dependencies` in Cargo.toml is not a table"))
+        })?;
+    if !dependencies.contains_key("libnds_sys") && !dependencies.contains_key("libnds-sys") {
+        let dependency: DocumentMut = libnds_dependency.parse().map_err(|e| {
+            NdsError::Config(format!(
+                "Invalid libnds_sys dependency `{libnds_dependency}`: {e}"
+            ))
+        })?;
+        for (key, value) in dependency.iter() {
+            dependencies.insert(key, value.clone());
+        }
+        changed = true;
+    }
+
+    let has_metadata = ["package", "metadata", "nds"]
+        .iter()
+        .try_fold(manifest.as_item(), |table, key| table.get(key))
+        .is_some_and(toml_edit::Item::is_table_like);
+    if !has_metadata {
+        let metadata: DocumentMut = NDS_METADATA.parse().unwrap();
+        let mut parent = manifest.as_table_mut();
+        for key in ["package", "metadata"] {
+            let mut implicit = toml_edit::Table::new();
+            implicit.set_implicit(true);
+            parent = parent
+                .entry(key)
+                .or_insert(toml_edit::Item::Table(implicit))
+                .as_table_mut()
+                .ok_or_else(|| NdsError::Config(format!("`{key}` in Cargo.toml is not a table")))?;
+        }
+        parent.insert("nds", metadata["package"]["metadata"]["nds"].clone());
+        changed = true;
+    }
+    Ok(changed)
 }
 
 #[cfg(test)]
@@ -698,6 +1917,91 @@ mod tests {
     use clap::CommandFactory;
 
     use super::*;
+    use crate::target::{CARGO_CONFIG, CARGO_CONFIG_PATH, DEFAULT_TARGET_SPEC, TARGET_JSON};
+    use crate::template::CONSOLE_MAIN_RS;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn parse_addr2line() {
+        let Cargo::Input(input) =
+            Cargo::try_parse_from(["cargo", "nds", "addr2line", "-a", "0x10,32", "--release"])
+                .unwrap();
+        let CargoCmd::Addr2line(addr2line) = input.cmd else {
+            unreachable!();
+        };
+        assert_eq!(addr2line.addresses, [0x10, 32]);
+        assert_eq!(addr2line.build_args.passthrough.cargo_args(), ["--release"]);
+    }
+
+    #[test]
+    fn log_levels() {
+        let level = |args: &[&str]| {
+            let Cargo::Input(input) =
+                Cargo::try_parse_from(["cargo", "nds"].iter().chain(args)).unwrap();
+            input.log_level()
+        };
+        assert_eq!(level(&["build"]), Level::Normal);
+        assert_eq!(level(&["build", "-q"]), Level::Quiet);
+        assert_eq!(level(&["--verbose", "build"]), Level::Verbose);
+        assert!(Cargo::try_parse_from(["cargo", "nds", "build", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn extract_cargo_flags() {
+        let Cargo::Input(mut input) = Cargo::try_parse_from([
+            "cargo",
+            "nds",
+            "--locked",
+            "build",
+            "--release",
+            "--target-dir=out",
+            "--offline",
+            "--manifest-path",
+            "game/Cargo.toml",
+            "--color",
+            "always",
+            "--",
+            "--offline",
+        ])
+        .unwrap();
+        input.extract_cargo_flags();
+
+        let flags = &input.cargo_flags;
+        assert_eq!(flags.target_dir.as_deref(), Some(Path::new("out")));
+        assert_eq!(
+            flags.manifest_path.as_deref(),
+            Some(Path::new("game/Cargo.toml"))
+        );
+        assert!(flags.locked && flags.offline && !flags.frozen);
+        assert_eq!(flags.color, Some(ColorChoice::Always));
+        assert!(flags.use_color());
+        assert!(flags
+            .args()
+            .ends_with(&["--color".to_string(), "always".to_string()]));
+        // Arguments of the executable are left alone
+        assert_eq!(input.cmd.cargo_args(), ["--release"]);
+        let CargoCmd::Build(build) = &input.cmd else {
+            unreachable!();
+        };
+        assert_eq!(build.passthrough.exe_args(), ["--offline"]);
+
+        let metadata = flags.metadata_command().cargo_command();
+        let args: Vec<_> = metadata
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert!(args.contains(&"--offline".to_string()) && args.contains(&"--locked".to_string()));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--manifest-path", "game/Cargo.toml"]));
+        let target_dir = metadata
+            .get_envs()
+            .find(|(key, _)| *key == "CARGO_TARGET_DIR");
+        assert_eq!(
+            target_dir.and_then(|(_, dir)| dir),
+            Some(absolute_path(Path::new("out")).as_os_str())
+        );
+    }
 
     #[test]
     fn verify_app() {
@@ -722,6 +2026,7 @@ mod tests {
                 &["--foo", "--message-format=json-render-diagnostics", "bar"],
                 Some("json-render-diagnostics"),
             ),
+            (&["--foo", "--message-format=short", "bar"], Some("short")),
             (&["--foo", "bar"], None),
         ];
 
@@ -730,7 +2035,14 @@ mod tests {
                 passthrough: RemainingArgs {
                     args: args.iter().map(ToString::to_string).collect(),
                 },
-                verbose: false,
+                max_size: None,
+                dsi: false,
+                versioned_copy: false,
+                out_dir: None,
+                emit_build_json: false,
+                checksum: false,
+                checksum_file: false,
+                no_build: false,
             });
 
             assert_eq!(
@@ -753,13 +2065,460 @@ mod tests {
                 passthrough: RemainingArgs {
                     args: args.iter().map(ToString::to_string).collect(),
                 },
-                verbose: false,
+                max_size: None,
+                dsi: false,
+                versioned_copy: false,
+                out_dir: None,
+                emit_build_json: false,
+                checksum: false,
+                checksum_file: false,
+                no_build: false,
             });
 
             assert!(cmd.extract_message_format().is_err());
         }
     }
 
+    #[test]
+    fn message_format_options() {
+        const CASES: &[(&str, &str)] = &[
+            (
+                "json,diagnostic-rendered-ansi",
+                "json-diagnostic-rendered-ansi",
+            ),
+            (
+                "json, json-diagnostic-rendered-ansi",
+                "json-diagnostic-rendered-ansi",
+            ),
+            (
+                "json-diagnostic-rendered-ansi,json-diagnostic-short,json-diagnostic-rendered-ansi",
+                "json-diagnostic-short,json-diagnostic-rendered-ansi",
+            ),
+            ("json,json", "json"),
+            ("short", "short"),
+        ];
+
+        for (format, expected) in CASES {
+            assert_eq!(
+                CargoCmd::canonical_message_format(format).unwrap(),
+                *expected
+            );
+        }
+
+        for format in [
+            "human,json",
+            "short,diagnostic-rendered-ansi",
+            "human,short",
+            "json,",
+            "json,foo",
+        ] {
+            assert!(
+                CargoCmd::canonical_message_format(format).is_err(),
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn extract_format_twice() {
+        for args in [
+            &["--message-format=json", "--message-format=short"][..],
+            &[
+                "--message-format",
+                "short",
+                "--foo",
+                "--message-format",
+                "short",
+            ],
+        ] {
+            let mut cmd = CargoCmd::Build(Build {
+                passthrough: RemainingArgs {
+                    args: args.iter().map(ToString::to_string).collect(),
+                },
+                max_size: None,
+                dsi: false,
+                versioned_copy: false,
+                out_dir: None,
+                emit_build_json: false,
+                checksum: false,
+                checksum_file: false,
+                no_build: false,
+            });
+
+            let err = cmd.extract_message_format().unwrap_err();
+            assert!(err.contains("more than once"), "{err}");
+        }
+    }
+
+    #[test]
+    fn extract_format_before_separator() {
+        let build = |args: &[&str]| {
+            CargoCmd::Build(Build {
+                passthrough: RemainingArgs {
+                    args: args.iter().map(ToString::to_string).collect(),
+                },
+                max_size: None,
+                dsi: false,
+                versioned_copy: false,
+                out_dir: None,
+                emit_build_json: false,
+                checksum: false,
+                checksum_file: false,
+                no_build: false,
+            })
+        };
+
+        // The executable's arguments are left alone
+        let mut cmd = build(&["--message-format=short", "--", "--", "--message-format=x"]);
+        assert_eq!(
+            cmd.extract_message_format().unwrap().as_deref(),
+            Some("short")
+        );
+        let CargoCmd::Build(build_cmd) = &cmd else {
+            unreachable!()
+        };
+        assert_eq!(
+            build_cmd.passthrough.args,
+            ["--", "--", "--message-format=x"]
+        );
+
+        for args in [&["--message-format"][..], &["--message-format", "--", "x"]] {
+            let err = build(args).extract_message_format().unwrap_err();
+            assert!(err.contains("requires a value"), "{err}");
+        }
+    }
+
+    #[test]
+    fn get_flag_value() {
+        let args = ["--release", "--profile", "foo", "--target-dir=out"].map(String::from);
+
+        assert_eq!(flag_value(&args, "--profile"), Some("foo"));
+        assert_eq!(flag_value(&args, "--target-dir"), Some("out"));
+        assert_eq!(flag_value(&args, "--package"), None);
+    }
+
+    #[test]
+    fn parse_emulator() {
+        for (args, expected) in [
+            (&["cargo", "nds", "run"][..], None),
+            (&["cargo", "nds", "run", "--emulator"], Some(None)),
+            (
+                &["cargo", "nds", "run", "--emulator=melonDS", "--release"],
+                Some(Some("melonDS")),
+            ),
+        ] {
+            let Cargo::Input(Input {
+                cmd: CargoCmd::Run(run),
+                ..
+            }) = Cargo::try_parse_from(args).unwrap_or_else(|e| panic!("{e}"))
+            else {
+                panic!("parsed as something other than `run` subcommand")
+            };
+
+            assert_eq!(run.emulator, expected.map(|e| e.map(String::from)));
+        }
+    }
+
+    /// Run [`scaffold_project`] without any cargo arguments.
+    fn scaffold(path: &Path, options: ScaffoldOptions) -> Result<(), NdsError> {
+        scaffold_project(path, &RemainingArgs { args: Vec::new() }, &options)
+    }
+
+    /// Create a temporary project, as if created by `cargo new`.
+    fn temp_project(name: &str) -> TempDir {
+        let path = TempDir::new(name);
+        fs::create_dir_all(path.join("src")).unwrap();
+        fs::write(
+            path.join("Cargo.toml"),
+            "[package]\nname = \"game\"\nversion = \"0.1.0\"\n\n[dependencies]\n",
+        )
+        .unwrap();
+        fs::write(path.join("src/main.rs"), DEFAULT_MAIN_RS).unwrap();
+        path
+    }
+
+    #[test]
+    fn scaffold_new_project() {
+        let path = temp_project("scaffold");
+        // Existing directories are kept
+        fs::create_dir(path.join("romfs")).unwrap();
+        fs::write(path.join("romfs/data.bin"), "").unwrap();
+
+        scaffold(&path, ScaffoldOptions::default()).unwrap();
+
+        assert!(path.join("romfs").is_dir());
+        assert_eq!(
+            fs::read_to_string(path.join("src/main.rs")).unwrap(),
+            CONSOLE_MAIN_RS
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(DEFAULT_TARGET_SPEC)).unwrap(),
+            TARGET_JSON
+        );
+        assert_eq!(
+            fs::read_to_string(path.join(CARGO_CONFIG_PATH)).unwrap(),
+            CARGO_CONFIG
+        );
+        assert!(fs::read_to_string(path.join("Cargo.toml"))
+            .unwrap()
+            .contains("[package.metadata.nds]"));
+        assert_eq!(
+            fs::read_to_string(path.join(".gitignore")).unwrap(),
+            "/target\n*.nds\narm9.map\n"
+        );
+    }
+
+    #[test]
+    fn scaffold_from_template() {
+        let path = temp_project("from-template");
+        let template_dir = temp_project("template-dir");
+        fs::write(template_dir.join("src/main.rs"), "// demo\n").unwrap();
+        fs::write(template_dir.join("src/sprites.rs"), "// sprites\n").unwrap();
+        fs::write(
+            template_dir.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\n\n[dependencies]\nmicromath = \"2\"\n",
+        )
+        .unwrap();
+
+        let Cargo::Input(input) = Cargo::try_parse_from([
+            "cargo",
+            "nds",
+            "new",
+            "game",
+            "--template",
+            template_dir.to_str().unwrap(),
+        ])
+        .unwrap();
+        let CargoCmd::New(new) = input.cmd else {
+            panic!("expected new")
+        };
+        scaffold(
+            &path,
+            ScaffoldOptions {
+                template: new.template.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(path.join("src/main.rs")).unwrap(),
+            "// demo\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join("src/sprites.rs")).unwrap(),
+            "// sprites\n"
+        );
+        let manifest: toml::Table = fs::read_to_string(path.join("Cargo.toml"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(manifest["package"]["name"].as_str(), Some("game"));
+        assert!(manifest["dependencies"].get("micromath").is_some());
+        assert!(manifest["dependencies"].get("libnds_sys").is_some());
+
+        // A manifest which can't be parsed is left alone
+        fs::write(path.join("Cargo.toml"), "[package\n").unwrap();
+        let result = scaffold(
+            &path,
+            ScaffoldOptions {
+                template: new.template.clone(),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(NdsError::Config(_))));
+        assert_eq!(
+            fs::read_to_string(path.join("Cargo.toml")).unwrap(),
+            "[package\n"
+        );
+    }
+
+    #[test]
+    fn gitignore_entries() {
+        assert_eq!(gitignore_additions(""), "/target\n*.nds\narm9.map\n");
+        // As generated by `cargo new`
+        assert_eq!(gitignore_additions("/target\n"), "*.nds\narm9.map\n");
+        assert_eq!(gitignore_additions("target/\n*.nds\narm9.map"), "");
+
+        let path = temp_project("gitignore");
+        fs::write(path.join(".gitignore"), "/target\nnotes.txt").unwrap();
+        scaffold(&path, ScaffoldOptions::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(path.join(".gitignore")).unwrap(),
+            "/target\nnotes.txt\n*.nds\narm9.map\n"
+        );
+
+        fs::remove_file(path.join(".gitignore")).unwrap();
+        scaffold(
+            &path,
+            ScaffoldOptions {
+                gitignore: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!path.join(".gitignore").exists());
+    }
+
+    #[test]
+    fn scaffold_conflicts() {
+        let path = temp_project("conflicts");
+        let main_rs = path.join("src/main.rs");
+        let config = path.join(CARGO_CONFIG_PATH);
+        let templates = [
+            (main_rs.clone(), CONSOLE_MAIN_RS),
+            (config.clone(), CARGO_CONFIG),
+        ];
+
+        // cargo's main.rs can be replaced
+        assert!(find_conflicts(&templates).is_empty());
+
+        fs::write(&main_rs, "fn main() { game() }\n").unwrap();
+        fs::create_dir(path.join(".cargo")).unwrap();
+        fs::write(&config, CARGO_CONFIG).unwrap();
+        assert_eq!(find_conflicts(&templates), vec![main_rs.clone()]);
+
+        fs::write(&config, "[build]\n").unwrap();
+        assert_eq!(find_conflicts(&templates), [main_rs.clone(), config]);
+
+        // User code is kept, and the manifest isn't changed twice
+        scaffold(&path, ScaffoldOptions::default()).unwrap();
+        let manifest = fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        scaffold(&path, ScaffoldOptions::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&main_rs).unwrap(),
+            "fn main() { game() }\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.join("Cargo.toml")).unwrap(),
+            manifest
+        );
+
+        scaffold(
+            &path,
+            ScaffoldOptions {
+                force: true,
+                yes: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&main_rs).unwrap(), CONSOLE_MAIN_RS);
+    }
+
+    #[test]
+    fn manifest_additions() {
+        let dependency = LibndsSys::default().dependency(|_| None);
+        let add = |manifest: &str| {
+            let mut manifest: DocumentMut = manifest.parse().unwrap();
+            let changed = add_manifest_settings(&mut manifest, &dependency).unwrap();
+            (manifest.to_string(), changed)
+        };
+
+        let manifest = "[package]\nname = \"game\"\n\n[dependencies]\n";
+        let (added, changed) = add(manifest);
+        assert!(changed);
+        assert_eq!(
+            added,
+            format!("[package]\nname = \"game\"\n{NDS_METADATA}\n[dependencies]\n{dependency}")
+        );
+        assert_eq!(add(&added), (added.clone(), false));
+
+        // The dependency goes in `[dependencies]`, wherever it is, and the
+        // rest of the manifest is kept as is
+        let manifest = "[package]\nname = \"game\" # the name\n\n[dependencies]\nlog = \"0.4\"\n\n[profile.release]\nlto = true\n";
+        let (added, _) = add(manifest);
+        assert!(added.starts_with("[package]\nname = \"game\" # the name\n"));
+        let added: toml::Table = added.parse().unwrap();
+        assert!(added["dependencies"].get("libnds_sys").is_some());
+        assert!(added["profile"]["release"].get("libnds_sys").is_none());
+        assert_eq!(
+            added["package"]["metadata"]["nds"]["romfs"].as_str(),
+            Some("romfs")
+        );
+
+        let (added, _) = add("[package]\nname = \"game\"\n\n[profile.release]\nlto = true\n");
+        let added: toml::Table = added.parse().unwrap();
+        assert!(added["dependencies"].get("libnds_sys").is_some());
+        assert_eq!(added["profile"]["release"].as_table().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn libnds_sys_source() {
+        let no_env = |_: &str| None;
+        let parse = |args: &[&str]| {
+            let Cargo::Input(input) =
+                Cargo::try_parse_from(["cargo", "nds", "init", "."].iter().chain(args)).unwrap();
+            let CargoCmd::Init(init) = input.cmd else {
+                panic!("expected init")
+            };
+            init.libnds_sys
+        };
+
+        assert_eq!(
+            parse(&[]).dependency(no_env),
+            format!("libnds_sys = {{ git = \"{LIBNDS_SYS_GIT}\" }}\n")
+        );
+        assert_eq!(
+            parse(&["--libnds-sys-version", "0.2"]).dependency(no_env),
+            "libnds_sys = \"0.2\"\n"
+        );
+        assert_eq!(
+            parse(&["--libnds-sys-git", "https://example.com/fork.git"]).dependency(no_env),
+            "libnds_sys = { git = \"https://example.com/fork.git\" }\n"
+        );
+        assert_eq!(
+            parse(&["--libnds-sys-rev", "v0.2"]).dependency(no_env),
+            format!("libnds_sys = {{ git = \"{LIBNDS_SYS_GIT}\", rev = \"v0.2\" }}\n")
+        );
+        let conflicting = [
+            "cargo",
+            "nds",
+            "init",
+            ".",
+            "--libnds-sys-version",
+            "1",
+            "--libnds-sys-git",
+            "x",
+        ];
+        assert!(Cargo::try_parse_from(conflicting).is_err());
+
+        let env = |var: &str| (var == "CARGO_NDS_LIBNDS_SYS_VERSION").then(|| "0.3".to_string());
+        assert_eq!(parse(&[]).dependency(env), "libnds_sys = \"0.3\"\n");
+        assert_eq!(
+            parse(&["--libnds-sys-git", "x"]).dependency(env),
+            "libnds_sys = { git = \"x\" }\n"
+        );
+
+        // The dependency is added to the `[dependencies]` table
+        let mut manifest: DocumentMut = "[package]\nname = \"game\"\n\n[dependencies]\n"
+            .parse()
+            .unwrap();
+        add_manifest_settings(&mut manifest, "libnds_sys = \"0.2\"\n").unwrap();
+        let manifest: toml::Table = manifest.to_string().parse().unwrap();
+        assert_eq!(manifest["dependencies"]["libnds_sys"].as_str(), Some("0.2"));
+    }
+
+    #[test]
+    fn binary_project() {
+        let path = temp_project("kind");
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        // `cargo new` creates a binary by default
+        assert!(is_binary_project(&path, &args(&[])));
+        assert!(is_binary_project(&path, &args(&["--bin", "--vcs", "none"])));
+        assert!(!is_binary_project(&path, &args(&["--lib"])));
+
+        // `cargo init` in a library project
+        fs::rename(path.join("src/main.rs"), path.join("src/lib.rs")).unwrap();
+        assert!(!is_binary_project(&path, &args(&[])));
+        assert!(!is_binary_project(
+            &path,
+            &args(&["--lib", "--edition=2021"])
+        ));
+        assert!(is_binary_project(&path, &args(&["--bin"])));
+    }
+
     #[test]
     fn split_run_args() {
         struct TestParam {
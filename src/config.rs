@@ -0,0 +1,1865 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::{env, fs, io};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use toml_edit::ImDocument;
+
+use crate::banner;
+
+/// The name of the optional per-project configuration file, looked up next to
+/// the package's `Cargo.toml`.
+pub const CONFIG_FILE_NAME: &str = "nds.toml";
+
+/// The standard library crates built when `build_std` isn't set.
+pub const DEFAULT_BUILD_STD: &[&str] = &["core", "alloc"];
+
+/// Environment variable setting the default address of the device.
+pub const ADDRESS_ENV: &str = "CARGO_NDS_ADDRESS";
+/// Environment variable setting the default emulator.
+pub const EMULATOR_ENV: &str = "CARGO_NDS_EMULATOR";
+/// Environment variable setting the default loader command.
+pub const LOADER_ENV: &str = "CARGO_NDS_LOADER";
+/// Environment variable setting the linker specs of the ARM9 executable.
+pub const SPECS_ENV: &str = "CARGO_NDS_SPECS";
+
+/// The loader used to send executables to the device when none is configured.
+pub const DEFAULT_LOADER: &str = "dslink";
+
+/// Project configuration read from `[package.metadata.nds]` in `Cargo.toml`
+/// and from `nds.toml`, which overrides it, see [`Config::try_load`].
+///
+/// Every field is optional; a missing file is equivalent to an empty one.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Banner text shown in the DS menu, per language.
+    #[serde(default)]
+    pub name: Languages<BannerText>,
+
+    /// Banner author, instead of the package's `authors`. Fields set in `name`
+    /// take precedence.
+    pub author: Option<String>,
+
+    /// Which of the package's `authors` the banner shows, unless `author` is
+    /// set.
+    #[serde(default)]
+    pub authors: BannerAuthors,
+
+    /// Banner description, instead of the package's `description`. Fields set
+    /// in `name` take precedence.
+    pub description: Option<String>,
+
+    /// Text file with the banner text of every language, relative to the
+    /// package directory, see [`parse_banner_file`]. Fields set in `name` take
+    /// precedence over the file.
+    pub banner_file: Option<PathBuf>,
+
+    /// Path of the banner icon, relative to the package directory. Either a
+    /// 32×32 BMP, or a PNG or SVG which is converted to BMP when building.
+    /// Shorthand for `icons.ds`.
+    pub icon: Option<PathBuf>,
+
+    /// Source images for each of the banner's icons.
+    #[serde(default)]
+    pub icons: IconsConfig,
+
+    /// RomFS source directories, relative to the package directory: either one
+    /// directory or a list, which are merged. `romfs_dir` is accepted as well.
+    #[serde(default, alias = "romfs_dir", deserialize_with = "one_or_many")]
+    pub romfs: Option<Vec<PathBuf>>,
+
+    /// Glob patterns of the RomFS files to embed, relative to the RomFS
+    /// directory. All files are embedded when empty.
+    #[serde(default)]
+    pub romfs_include: Vec<String>,
+
+    /// Glob patterns of the RomFS files to leave out, applied after
+    /// `romfs_include`. Defaults to common junk files, such as `.DS_Store`.
+    pub romfs_exclude: Option<Vec<String>>,
+
+    /// Fail the build when there is no RomFS to embed, instead of building a
+    /// ROM without one.
+    #[serde(default)]
+    pub require_romfs: bool,
+
+    /// Additional directories searched (after the package directory) for the
+    /// RomFS directories. Other inputs, such as icons, don't use them.
+    #[serde(default)]
+    pub include_dirs: Vec<PathBuf>,
+
+    /// Maximum size of the `.nds` file. The build fails if the ROM is bigger.
+    pub max_rom_size: Option<ByteSize>,
+
+    /// 4 character game code in the ROM header, used by save managers and
+    /// loaders to identify the game. Defaults to ndstool's `####`.
+    pub game_code: Option<String>,
+
+    /// 2 character maker code in the ROM header. Defaults to ndstool's `00`.
+    pub maker_code: Option<String>,
+
+    /// Path of the ROM, relative to the package directory, e.g.
+    /// `dist/MyGame.nds`. Defaults to the executable's path with the `.nds`
+    /// (or `.dsi`) extension. With `--out-dir`, only its file name is used.
+    pub output: Option<PathBuf>,
+
+    /// Build a DSi-enhanced ROM, like `--dsi`.
+    #[serde(default)]
+    pub dsi: bool,
+
+    /// Also write a copy of the ROM with the package version in its name, e.g.
+    /// `game-1.2.0.nds`, like `--versioned-copy`.
+    #[serde(default)]
+    pub versioned_copy: bool,
+
+    /// Version in the ROM header, from 0 to 255. Defaults to one derived from
+    /// the package version.
+    pub rom_version: Option<u8>,
+
+    /// Default address of the device to send executables to.
+    pub address: Option<Host>,
+
+    /// Named devices selected with `cargo nds run --device <name>`, from
+    /// `[devices.<name>]` tables.
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceProfile>,
+
+    /// Emulator used by `cargo nds run --emulator`.
+    pub emulator: Option<EmulatorConfig>,
+
+    /// Settings for the tool used to send executables to the device.
+    #[serde(default)]
+    pub loader: LoaderConfig,
+
+    /// The ARM7 executable, relative to the package directory. Defaults to the
+    /// `.arm7.elf` next to the ARM9 executable, or the BlocksDS default ARM7.
+    pub arm7: Option<PathBuf>,
+
+    /// Linker specs file of the ARM9 executable, relative to the package
+    /// directory. Defaults to BlocksDS' `sys/crts/ds_arm9.specs`. Overridden by
+    /// `CARGO_NDS_SPECS`.
+    pub specs: Option<PathBuf>,
+
+    /// A Rust package, relative to the package directory, built for the ARM7
+    /// with the same profile and used as the ARM7 executable. Exclusive with
+    /// `arm7`.
+    pub arm7_crate: Option<PathBuf>,
+
+    /// The target spec JSON file, relative to the package directory. Defaults
+    /// to the first `armv5te-nintendo-ds.json` found in the package directory
+    /// or one of its parents. Overridden by `--target` and `--target-spec`.
+    pub target_spec: Option<PathBuf>,
+
+    /// Standard library crates built with `-Z build-std`. Defaults to
+    /// [`DEFAULT_BUILD_STD`]. Overridden by `--build-std`.
+    pub build_std: Option<Vec<String>>,
+
+    /// Standard library features enabled with `-Z build-std-features`.
+    /// Overridden by `--build-std-features`.
+    #[serde(default)]
+    pub build_std_features: Vec<String>,
+
+    /// Extra arguments appended to the ndstool command, for features cargo-nds
+    /// doesn't support. `-c`, `-9` and `-7` are always set by cargo-nds.
+    #[serde(default)]
+    pub ndstool_args: Vec<String>,
+
+    /// Extra arguments for the linker, such as `-Wl,--wrap=malloc`, passed
+    /// after the BlocksDS specs. Each one is a single argument, even if it has
+    /// spaces.
+    #[serde(default)]
+    pub extra_link_args: Vec<String>,
+
+    /// Extra files added to the archive made by `cargo nds bundle`, such as a
+    /// README, relative to the package directory.
+    #[serde(default)]
+    pub bundle: Vec<PathBuf>,
+
+    /// Layout of the ROM header, for loaders with specific requirements.
+    #[serde(default)]
+    pub header: HeaderConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+/// Check a `build_std` list, which cargo rejects when it's empty.
+pub fn validate_build_std(crates: &[String]) -> Result<(), String> {
+    if crates.is_empty() || crates.iter().any(|name| name.trim().is_empty()) {
+        return Err("build_std needs at least one crate, and no empty names".to_string());
+    }
+    Ok(())
+}
+
+/// Deserialize either a single path or a list of paths.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<PathBuf>>, D::Error> {
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => Some(vec![path]),
+        OneOrMany::Many(paths) => Some(paths),
+    })
+}
+
+/// The path of the user-wide configuration, which applies to all projects:
+/// `cargo-nds/nds.toml` in `%APPDATA%` on Windows, or in `$XDG_CONFIG_HOME`
+/// (defaulting to `~/.config`) elsewhere.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+
+    config_dir.map(|dir| dir.join("cargo-nds").join(CONFIG_FILE_NAME))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Parse a TOML file, with errors formatted as `path:line:column: message`.
+fn parse_toml<T: serde::de::DeserializeOwned>(path: &Path, text: &str) -> io::Result<T> {
+    deserialize_toml(path, text, parse_document(path, text)?)
+}
+
+/// Parse a TOML file into a document, which [`deserialize_toml`] can then
+/// deserialize as often as needed without parsing the text again.
+fn parse_document<'a>(path: &Path, text: &'a str) -> io::Result<ImDocument<&'a str>> {
+    ImDocument::parse(text).map_err(|e| toml_error(path, text, e.span(), e.message()))
+}
+
+/// Deserialize a document parsed by [`parse_document`] from `text`, with errors
+/// located as in [`parse_toml`].
+fn deserialize_toml<T: serde::de::DeserializeOwned>(
+    path: &Path,
+    text: &str,
+    document: ImDocument<&str>,
+) -> io::Result<T> {
+    T::deserialize(toml_edit::de::Deserializer::from(document))
+        .map_err(|e| toml_error(path, text, e.span(), e.message()))
+}
+
+/// An error in the TOML file at `path`, located at the start of `span`.
+fn toml_error(path: &Path, text: &str, span: Option<Range<usize>>, message: &str) -> io::Error {
+    let source = match span {
+        Some(span) => {
+            let (line, column) = line_column(text, span.start);
+            format!("{}:{line}:{column}", path.display())
+        }
+        None => path.display().to_string(),
+    };
+    parse_error(message, &source)
+}
+
+/// The 1-based line and column of a byte offset in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// An error parsing the configuration from `source`. Unknown keys are named,
+/// with the closest known key as a suggestion.
+fn parse_error(message: &str, source: &str) -> io::Error {
+    let Some(rest) = message.strip_prefix("unknown field `") else {
+        return invalid_data(format!("{source}: {message}"));
+    };
+    let key = rest.split('`').next().unwrap_or_default();
+
+    // "..., expected one of `a`, `b`" or "..., expected `a`"
+    let expected = rest
+        .split_once("expected")
+        .map_or("", |(_, expected)| expected);
+    let suggestion = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|known| (edit_distance(key, known), known))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance);
+
+    invalid_data(match suggestion {
+        Some((_, known)) => format!("{source}: unknown key `{key}`, did you mean `{known}`?"),
+        None => format!("{source}: unknown key `{key}`"),
+    })
+}
+
+/// The number of single character insertions, deletions or substitutions
+/// turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Read and parse a Cargo manifest.
+pub fn read_manifest(manifest_path: &Path) -> io::Result<toml::Table> {
+    parse_toml(manifest_path, &fs::read_to_string(manifest_path)?)
+}
+
+/// The parts of a Cargo manifest holding the `[package.metadata.nds]` table.
+#[derive(Deserialize)]
+struct ManifestMetadata {
+    #[allow(dead_code)]
+    package: Option<PackageMetadata>,
+}
+
+#[derive(Deserialize)]
+struct PackageMetadata {
+    #[allow(dead_code)]
+    metadata: Option<NdsMetadata>,
+}
+
+#[derive(Deserialize)]
+struct NdsMetadata {
+    #[allow(dead_code)]
+    nds: Option<Config>,
+}
+
+/// The `[package.metadata.nds]` table of a Cargo manifest, if any.
+fn manifest_metadata(manifest: &toml::Table) -> Option<&toml::Table> {
+    ["package", "metadata", "nds"]
+        .iter()
+        .try_fold(manifest, |table, key| table.get(*key)?.as_table())
+}
+
+/// Settings which can't be set together: when the overriding config sets one
+/// of them, the others are dropped from the config it overrides.
+const EXCLUSIVE_KEYS: &[&[&[&str]]] = &[
+    &[&["icon"], &["icons", "ds"], &["icons", "banner"]],
+    &[&["arm7"], &["arm7_crate"]],
+    &[&["romfs"], &["romfs_dir"]],
+];
+
+/// Merge a config table into another, recursively: tables are merged and any
+/// other value of `overrides` replaces the one in `config`.
+fn merge_config(config: &mut toml::Table, overrides: toml::Table) {
+    fn get<'a>(table: &'a toml::Table, path: &[&str]) -> Option<&'a toml::Value> {
+        let (last, parents) = path.split_last()?;
+        let table = parents
+            .iter()
+            .try_fold(table, |table, key| table.get(*key)?.as_table())?;
+        table.get(*last)
+    }
+    fn remove(table: &mut toml::Table, path: &[&str]) {
+        let Some((last, parents)) = path.split_last() else {
+            return;
+        };
+        let mut table = table;
+        for key in parents {
+            match table.get_mut(*key).and_then(toml::Value::as_table_mut) {
+                Some(child) => table = child,
+                None => return,
+            }
+        }
+        table.remove(*last);
+    }
+    fn merge(config: &mut toml::Table, overrides: toml::Table) {
+        for (key, value) in overrides {
+            match (config.get_mut(&key), value) {
+                (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                    merge(existing, value)
+                }
+                (_, value) => {
+                    config.insert(key, value);
+                }
+            }
+        }
+    }
+
+    for group in EXCLUSIVE_KEYS {
+        if group.iter().any(|path| get(&overrides, path).is_some()) {
+            for path in *group {
+                remove(config, path);
+            }
+        }
+    }
+    merge(config, overrides);
+}
+
+/// Settings of a device to send executables to, defined in a
+/// `[devices.<name>]` table and selected with `cargo nds run --device <name>`.
+/// Also holds the same settings given on the command line.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProfile {
+    /// Address of the device.
+    pub address: Option<Host>,
+
+    /// 0th argument of the executable, passed to the loader with `--argv0`.
+    pub argv0: Option<String>,
+
+    /// Start the loader's server after sending the executable, as with `--server`.
+    #[serde(default)]
+    pub server: bool,
+}
+
+/// Settings used by `cargo nds run`, resolved from every source with the
+/// precedence: command line > device profile > environment > user config >
+/// project config.
+#[derive(Debug, PartialEq)]
+pub struct RunConfig {
+    pub address: Option<Host>,
+    pub argv0: Option<String>,
+    pub server: bool,
+    pub emulator: Option<EmulatorConfig>,
+    pub loader: String,
+    pub reset: Option<Vec<String>>,
+}
+
+impl RunConfig {
+    /// Resolve the settings from the command line arguments, the device profile
+    /// named `device` in the user or project config, the environment variables
+    /// (looked up with `env`), and the user and project configs.
+    pub fn resolve(
+        cli: DeviceProfile,
+        device: Option<&str>,
+        emulator: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+        user: &Config,
+        project: &Config,
+    ) -> Result<Self, String> {
+        let env_address = env(ADDRESS_ENV)
+            .map(|address| {
+                address
+                    .parse()
+                    .map_err(|_| format!("invalid {ADDRESS_ENV} `{address}`"))
+            })
+            .transpose()?;
+
+        let profile = match device {
+            Some(name) => find_device(name, user, project)?,
+            None => DeviceProfile::default(),
+        };
+
+        Ok(Self {
+            address: cli
+                .address
+                .or(profile.address)
+                .or(env_address)
+                .or_else(|| user.address.clone())
+                .or_else(|| project.address.clone()),
+            argv0: cli.argv0.or(profile.argv0),
+            server: cli.server || profile.server,
+            emulator: emulator
+                .map(ToString::to_string)
+                .or_else(|| env(EMULATOR_ENV))
+                // Older name of `CARGO_NDS_EMULATOR`
+                .or_else(|| env("NDS_EMULATOR"))
+                .map(EmulatorConfig::new)
+                .or_else(|| user.emulator.clone())
+                .or_else(|| project.emulator.clone()),
+            loader: env(LOADER_ENV)
+                .or_else(|| user.loader.command.clone())
+                .or_else(|| project.loader.command.clone())
+                .unwrap_or_else(|| DEFAULT_LOADER.to_string()),
+            reset: user
+                .loader
+                .reset
+                .clone()
+                .or_else(|| project.loader.reset.clone()),
+        })
+    }
+}
+
+/// Find the device profile with the given name, in the user config first.
+fn find_device(name: &str, user: &Config, project: &Config) -> Result<DeviceProfile, String> {
+    if let Some(profile) = user.devices.get(name).or_else(|| project.devices.get(name)) {
+        return Ok(profile.clone());
+    }
+
+    let mut names: Vec<&str> = user
+        .devices
+        .keys()
+        .chain(project.devices.keys())
+        .map(String::as_str)
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    if names.is_empty() {
+        Err(format!(
+            "unknown device `{name}`: no `[devices]` are configured in {CONFIG_FILE_NAME}"
+        ))
+    } else {
+        Err(format!(
+            "unknown device `{name}`, expected one of: {}",
+            names.join(", ")
+        ))
+    }
+}
+
+/// Settings for the loader used by `cargo nds run`.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LoaderConfig {
+    /// Command used to send executables to the device, `dslink` by default.
+    pub command: Option<String>,
+
+    /// Command run by `cargo nds run --reset` before sending the executable.
+    /// `{address}` in any argument is replaced with the `--address` argument.
+    pub reset: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Load the configuration of the package with the given Cargo manifest: the
+    /// `[package.metadata.nds]` table of the manifest, overridden key by key by
+    /// the `nds.toml` next to it. Either can be missing, in which case the
+    /// default (empty) configuration is used for it.
+    pub fn try_load(manifest_path: &Path) -> io::Result<Self> {
+        let mut table = match fs::read_to_string(manifest_path) {
+            Ok(text) => {
+                // Deserialized as a whole first, for errors located in the manifest
+                let document = parse_document(manifest_path, &text)?;
+                deserialize_toml::<ManifestMetadata>(manifest_path, &text, document.clone())?;
+                let manifest: toml::Table = deserialize_toml(manifest_path, &text, document)?;
+                manifest_metadata(&manifest).cloned().unwrap_or_default()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(e),
+        };
+
+        let path = manifest_path.with_file_name(CONFIG_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let document = parse_document(&path, &text)?;
+                deserialize_toml::<Self>(&path, &text, document.clone())?;
+                merge_config(&mut table, deserialize_toml(&path, &text, document)?);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let config =
+            Self::deserialize(toml::Value::Table(table)).map_err(|e| invalid_data(e.message()))?;
+        config.validate().map_err(invalid_data)?;
+        Ok(config)
+    }
+
+    /// Load the user-wide configuration, see [`user_config_path`]. If there is
+    /// no such file, the default (empty) configuration is returned.
+    pub fn try_load_user() -> io::Result<Self> {
+        match user_config_path() {
+            Some(path) => Self::try_load_file(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load a configuration file, or the default configuration if it doesn't exist.
+    pub fn try_load_file(path: &Path) -> io::Result<Self> {
+        let buf = match fs::read_to_string(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let config: Self = parse_toml(path, &buf)?;
+        config.validate().map_err(invalid_data)?;
+
+        Ok(config)
+    }
+
+    /// Check the settings which can't be checked while parsing.
+    fn validate(&self) -> Result<(), String> {
+        if self.icon.is_some() && (self.icons.ds.is_some() || self.icons.banner.is_some()) {
+            return Err("only one of icon, icons.ds and icons.banner can be set".to_string());
+        }
+        self.icons.validate()?;
+
+        if self.arm7.is_some() && self.arm7_crate.is_some() {
+            return Err("only one of arm7 and arm7_crate can be set".to_string());
+        }
+
+        if let Some(build_std) = &self.build_std {
+            validate_build_std(build_std)?;
+        }
+
+        for (key, code, length) in [
+            ("game_code", &self.game_code, 4),
+            ("maker_code", &self.maker_code, 2),
+        ] {
+            if let Some(code) = code {
+                if code.len() != length || !code.chars().all(|c| c.is_ascii_graphic()) {
+                    return Err(format!(
+                        "{key} `{code}` must be exactly {length} ASCII letters, digits or symbols"
+                    ));
+                }
+            }
+        }
+
+        if self.dsi {
+            self.header.validate_dsi()?;
+        }
+
+        self.header.validate()
+    }
+
+    /// The source of the DS icon, from `icon` or `icons.ds`.
+    pub fn ds_icon(&self) -> Option<&Path> {
+        self.icon.as_deref().or(self.icons.ds.as_deref())
+    }
+}
+
+/// Source images for the banner icons, relative to the package directory.
+///
+/// Each is a 32×32 BMP, PNG or SVG. PNG and SVG images are converted to BMP
+/// when building.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IconsConfig {
+    /// The static icon, shown by the DS menu.
+    pub ds: Option<PathBuf>,
+
+    /// Frames of the animated icon shown by the DSi menu, at most 8. Each frame
+    /// has its own 16 color palette.
+    #[serde(default)]
+    pub animated: Vec<PathBuf>,
+
+    /// The order and duration of the animation frames, at most 64 steps.
+    /// Defaults to showing each frame in turn for `frame_duration`.
+    #[serde(default)]
+    pub sequence: Vec<AnimationStep>,
+
+    /// Duration of each frame in the default sequence, in 1/60 s.
+    pub frame_duration: Option<u8>,
+
+    /// A pre-built banner (e.g. with an animated icon), used instead of the
+    /// icons and `name`.
+    pub banner: Option<PathBuf>,
+}
+
+/// A step of the icon animation.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationStep {
+    /// Index of the frame in `animated`.
+    pub frame: usize,
+    /// How long the frame is shown, in 1/60 s.
+    pub duration: u8,
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default)]
+    pub flip_vertical: bool,
+}
+
+impl IconsConfig {
+    /// Default duration of each frame, in 1/60 s.
+    pub const DEFAULT_FRAME_DURATION: u8 = 10;
+
+    /// The animation sequence, either configured or the default one.
+    pub fn animation_sequence(&self) -> Vec<AnimationStep> {
+        if !self.sequence.is_empty() {
+            return self.sequence.clone();
+        }
+
+        let duration = self.frame_duration.unwrap_or(Self::DEFAULT_FRAME_DURATION);
+        (0..self.animated.len())
+            .map(|frame| AnimationStep {
+                frame,
+                duration,
+                flip_horizontal: false,
+                flip_vertical: false,
+            })
+            .collect()
+    }
+
+    /// Check the animation against the limits of the DSi banner.
+    fn validate(&self) -> Result<(), String> {
+        if self.banner.is_some() && !(self.animated.is_empty() && self.ds.is_none()) {
+            return Err("icons.banner can't be combined with other icons".to_string());
+        }
+        if self.animated.len() > banner::MAX_FRAMES {
+            return Err(format!(
+                "icons.animated has {} frames, but the DSi banner has room for {}",
+                self.animated.len(),
+                banner::MAX_FRAMES
+            ));
+        }
+        if self.animated.is_empty() && (!self.sequence.is_empty() || self.frame_duration.is_some())
+        {
+            return Err("icons.sequence and icons.frame_duration need icons.animated".to_string());
+        }
+        if self.sequence.len() > banner::MAX_STEPS {
+            return Err(format!(
+                "icons.sequence has {} steps, but the DSi banner has room for {}",
+                self.sequence.len(),
+                banner::MAX_STEPS
+            ));
+        }
+        if self.frame_duration == Some(0) {
+            return Err("icons.frame_duration must be at least 1".to_string());
+        }
+        for (index, step) in self.sequence.iter().enumerate() {
+            if step.frame >= self.animated.len() {
+                return Err(format!(
+                    "icons.sequence step {index} shows frame {}, but icons.animated only has {}",
+                    step.frame,
+                    self.animated.len()
+                ));
+            }
+            if step.duration == 0 {
+                return Err(format!(
+                    "icons.sequence step {index} must have a duration of at least 1"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for the ROM header written by ndstool.
+///
+/// The defaults produce a homebrew ROM without a secure area, which is what
+/// homebrew loaders expect.
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderConfig {
+    /// Size of the header, which is also the offset of the ARM9 binary in the
+    /// ROM. Must be a multiple of 0x200, up to 0x4000.
+    pub size: Option<ByteSize>,
+
+    /// Whether the ROM has an encrypted secure area.
+    #[serde(default)]
+    pub secure_area: SecureArea,
+
+    /// Logo bitmap (or binary) shown when booting from the firmware, relative
+    /// to the package directory. Defaults to ndstool's.
+    pub logo: Option<PathBuf>,
+}
+
+/// Which of the package's authors are shown in the banner.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BannerAuthors {
+    /// The first author only.
+    #[default]
+    First,
+    /// All the authors, separated by commas.
+    All,
+}
+
+/// The secure area is the first 16K after the header (0x4000-0x7FFF), which
+/// commercial ROMs encrypt.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecureArea {
+    /// No secure area, like every homebrew ROM.
+    #[default]
+    None,
+    /// Encrypt the start of the ARM9 binary, as the firmware expects when
+    /// booting a cartridge directly.
+    Encrypted,
+}
+
+impl HeaderConfig {
+    /// Offset of the secure area, and so the header size it requires.
+    pub const SECURE_AREA_OFFSET: u64 = 0x4000;
+
+    /// Check that the settings can be combined.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ByteSize(size)) = self.size {
+            if size == 0 || size % 0x200 != 0 || size > Self::SECURE_AREA_OFFSET {
+                return Err(format!(
+                    "header.size {size:#x} must be a multiple of 0x200, up to {:#x}",
+                    Self::SECURE_AREA_OFFSET
+                ));
+            }
+            if self.secure_area == SecureArea::Encrypted && size != Self::SECURE_AREA_OFFSET {
+                return Err(format!(
+                    "header.secure_area = \"encrypted\" needs the ARM9 binary in the secure area, \
+                    so header.size must be {:#x} (or unset), not {size:#x}",
+                    Self::SECURE_AREA_OFFSET
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the settings can be used for a DSi ROM.
+    pub fn validate_dsi(&self) -> Result<(), String> {
+        match self.size {
+            Some(ByteSize(size)) if size != Self::SECURE_AREA_OFFSET => Err(format!(
+                "DSi ROMs need header.size to be {:#x} (or unset), not {size:#x}",
+                Self::SECURE_AREA_OFFSET
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The header size to pass to ndstool, if any.
+    pub fn header_size(&self) -> Option<u64> {
+        match (self.size, self.secure_area) {
+            (Some(ByteSize(size)), _) => Some(size),
+            (None, SecureArea::Encrypted) => Some(Self::SECURE_AREA_OFFSET),
+            (None, SecureArea::None) => None,
+        }
+    }
+}
+
+/// The command used to run an emulator.
+///
+/// In `nds.toml` this is either just the command (a name in $PATH or a path),
+/// or a table with the `command` and its `args`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "EmulatorValue")]
+pub struct EmulatorConfig {
+    pub command: String,
+    /// Arguments passed to the emulator, where `{nds}` is replaced with the path
+    /// of the `.nds` file. The path is added as the last argument if no
+    /// argument contains `{nds}`.
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum EmulatorValue {
+    Command(String),
+    Table {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl From<EmulatorValue> for EmulatorConfig {
+    fn from(value: EmulatorValue) -> Self {
+        match value {
+            EmulatorValue::Command(command) => Self::new(command),
+            EmulatorValue::Table { command, args } => Self { command, args },
+        }
+    }
+}
+
+impl EmulatorConfig {
+    /// An emulator which just takes the `.nds` file as its argument.
+    pub fn new(command: String) -> Self {
+        Self {
+            command,
+            args: vec![String::from("{nds}")],
+        }
+    }
+
+    /// The arguments to run the emulator with, for the given `.nds` file.
+    pub fn args_for(&self, nds: &Path) -> Vec<String> {
+        let nds = nds.to_string_lossy();
+
+        let mut args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| arg.replace("{nds}", &nds))
+            .collect();
+        if !self.args.iter().any(|arg| arg.contains("{nds}")) {
+            args.push(nds.into_owned());
+        }
+        args
+    }
+
+    /// The command running the emulator with the given `.nds` file.
+    pub fn command_for(&self, nds: &Path) -> Command {
+        let mut command = Command::new(&self.command);
+        command.args(self.args_for(nds));
+        command
+    }
+}
+
+/// The address of a device: an IPv4 or IPv6 address, or a hostname.
+///
+/// Hostnames are only checked for characters which can't appear in one, and
+/// are resolved by the loader. An IPv6 address may be written in brackets.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Host(String);
+
+impl FromStr for Host {
+    type Err = String;
+
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        let unbracketed = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if unbracketed.parse::<IpAddr>().is_ok() {
+            return Ok(Host(unbracketed.to_string()));
+        }
+
+        let valid = !host.is_empty()
+            && host.len() <= 253
+            && host.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if valid {
+            Ok(Host(host.to_string()))
+        } else {
+            Err(format!(
+                "invalid address `{host}`, expected an IP address or a hostname"
+            ))
+        }
+    }
+}
+
+impl TryFrom<String> for Host {
+    type Error = String;
+
+    fn try_from(host: String) -> Result<Self, Self::Error> {
+        host.parse()
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A size in bytes, written either as a number of bytes or as a string with a
+/// `K`/`M`/`G` suffix, see [`parse_size`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "SizeValue")]
+pub struct ByteSize(pub u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    String(String),
+}
+
+impl TryFrom<SizeValue> for ByteSize {
+    type Error = String;
+
+    fn try_from(value: SizeValue) -> Result<Self, Self::Error> {
+        match value {
+            SizeValue::Bytes(bytes) => Ok(ByteSize(bytes)),
+            SizeValue::String(size) => parse_size(&size).map(ByteSize),
+        }
+    }
+}
+
+/// Parse a size in bytes with an optional binary `K`, `M` or `G` suffix,
+/// e.g. `512`, `64K`, `4M` or `4MiB`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{size}`"))?;
+
+    let multiplier = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "invalid size suffix in `{size}`, expected K, M or G"
+            ))
+        }
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{size}` is too large"))
+}
+
+/// A value for each language supported by the DS banner, in the order ndstool
+/// (and the banner format) uses.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Languages<T> {
+    pub japanese: Option<T>,
+    pub english: Option<T>,
+    pub french: Option<T>,
+    pub german: Option<T>,
+    pub italian: Option<T>,
+    pub spanish: Option<T>,
+}
+
+// Derived `Default` would require `T: Default`, which isn't needed for all-`None`.
+impl<T> Default for Languages<T> {
+    fn default() -> Self {
+        Self {
+            japanese: None,
+            english: None,
+            french: None,
+            german: None,
+            italian: None,
+            spanish: None,
+        }
+    }
+}
+
+/// Number of languages in the DS banner.
+pub const LANGUAGE_COUNT: usize = 6;
+
+impl<T> Languages<T> {
+    /// The configured values indexed by their banner language number
+    /// (0 = Japanese, 1 = English, ..., 5 = Spanish).
+    pub fn as_array(&self) -> [Option<&T>; LANGUAGE_COUNT] {
+        [
+            self.japanese.as_ref(),
+            self.english.as_ref(),
+            self.french.as_ref(),
+            self.german.as_ref(),
+            self.italian.as_ref(),
+            self.spanish.as_ref(),
+        ]
+    }
+}
+
+impl Languages<BannerText> {
+    /// Resolve the banner text of every language. Unset fields fall back to the
+    /// English text, and unset English fields fall back to `default`.
+    pub fn resolve(&self, default: &BannerText) -> [BannerText; LANGUAGE_COUNT] {
+        let english = match &self.english {
+            Some(english) => english.or(default),
+            None => default.clone(),
+        };
+
+        self.as_array().map(|text| match text {
+            Some(text) => text.or(&english),
+            None => english.clone(),
+        })
+    }
+
+    /// Fill any unset fields of each language from `fallback`.
+    pub fn or(&self, fallback: &Self) -> Self {
+        let or = |text: &Option<BannerText>, fallback: &Option<BannerText>| match (text, fallback) {
+            (Some(text), Some(fallback)) => Some(text.or(fallback)),
+            _ => text.clone().or_else(|| fallback.clone()),
+        };
+
+        Self {
+            japanese: or(&self.japanese, &fallback.japanese),
+            english: or(&self.english, &fallback.english),
+            french: or(&self.french, &fallback.french),
+            german: or(&self.german, &fallback.german),
+            italian: or(&self.italian, &fallback.italian),
+            spanish: or(&self.spanish, &fallback.spanish),
+        }
+    }
+}
+
+/// Names of the banner languages, in the order of [`Languages::as_array`].
+const LANGUAGE_NAMES: [&str; LANGUAGE_COUNT] = [
+    "Japanese", "English", "French", "German", "Italian", "Spanish",
+];
+
+/// Parse a `banner_file`: the title, subtitle and manufacturer of each
+/// language, one per line, in the order Japanese, English, French, German,
+/// Italian and Spanish. Empty lines are unset fields, which fall back like
+/// unset fields of `name`.
+pub fn parse_banner_file(contents: &str) -> Result<Languages<BannerText>, String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let expected = LANGUAGE_COUNT * 3;
+    if lines.len() != expected {
+        return Err(format!(
+            "expected {expected} lines (title, subtitle and manufacturer for each of {}), found {}",
+            LANGUAGE_NAMES.join(", "),
+            lines.len()
+        ));
+    }
+
+    let field = |line: &str| (!line.trim().is_empty()).then(|| line.trim_end().to_string());
+    let texts: Vec<Option<BannerText>> = lines
+        .chunks_exact(3)
+        .map(|text| {
+            let text = BannerText {
+                title: field(text[0]),
+                subtitle: field(text[1]),
+                manufacturer: field(text[2]),
+            };
+            (text != BannerText::default()).then_some(text)
+        })
+        .collect();
+    let [japanese, english, french, german, italian, spanish]: [Option<BannerText>;
+        LANGUAGE_COUNT] = texts.try_into().unwrap();
+
+    Ok(Languages {
+        japanese,
+        english,
+        french,
+        german,
+        italian,
+        spanish,
+    })
+}
+
+/// The three lines of text shown for the application in the DS menu.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BannerText {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub manufacturer: Option<String>,
+}
+
+impl BannerText {
+    /// Fill any unset fields from `fallback`.
+    pub fn or(&self, fallback: &BannerText) -> BannerText {
+        BannerText {
+            title: self.title.clone().or_else(|| fallback.title.clone()),
+            subtitle: self.subtitle.clone().or_else(|| fallback.subtitle.clone()),
+            manufacturer: self
+                .manufacturer
+                .clone()
+                .or_else(|| fallback.manufacturer.clone()),
+        }
+    }
+
+    /// Format the text as ndstool's `title;subtitle;manufacturer` banner argument.
+    pub fn to_ndstool_arg(&self) -> String {
+        [&self.title, &self.subtitle, &self.manufacturer]
+            .map(|field| sanitize_banner_field(field.as_deref().unwrap_or_default()))
+            .join(";")
+    }
+}
+
+/// ndstool has no way to escape the `;` line separator, so replace it in
+/// individual fields to keep them from being split into extra lines.
+fn sanitize_banner_field(field: &str) -> String {
+    field.replace(';', ",")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    fn banner(title: &str, subtitle: &str, manufacturer: &str) -> BannerText {
+        BannerText {
+            title: Some(title.to_string()),
+            subtitle: Some(subtitle.to_string()),
+            manufacturer: Some(manufacturer.to_string()),
+        }
+    }
+
+    #[test]
+    fn error_locations() {
+        let path = Path::new("nds.toml");
+        let err = parse_toml::<Config>(path, "dsi = true\nicon = \n").unwrap_err();
+        assert!(err.to_string().starts_with("nds.toml:2:8: "), "{err}");
+        let err = parse_toml::<Config>(path, "dsi = true\n[header]\n  size = []\n").unwrap_err();
+        assert!(err.to_string().starts_with("nds.toml:3:10: "), "{err}");
+        let err = parse_toml::<toml::Table>(Path::new("Cargo.toml"), "[package\n").unwrap_err();
+        assert!(err.to_string().starts_with("Cargo.toml:1:9: "), "{err}");
+
+        assert_eq!(line_column("ab\ncd", 0), (1, 1));
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+        assert_eq!(line_column("éx\ny", 2), (1, 2));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let dir = TempDir::new("unknown-keys");
+        let manifest = dir.join("Cargo.toml");
+        let config_path = dir.join(CONFIG_FILE_NAME);
+
+        fs::write(&config_path, "icn = \"foo.bmp\"\n").unwrap();
+        let err = Config::try_load(&manifest).unwrap_err().to_string();
+        assert!(
+            err.contains("unknown key `icn`, did you mean `icon`?"),
+            "{err}"
+        );
+        assert!(err.contains(&config_path.display().to_string()), "{err}");
+
+        // Nested tables too
+        fs::write(
+            &config_path,
+            "[header]\nlogo = \"logo.bin\"\nsecure = \"none\"\n",
+        )
+        .unwrap();
+        let err = Config::try_load(&manifest).unwrap_err().to_string();
+        assert!(err.contains("unknown key `secure`"), "{err}");
+        fs::write(&config_path, "[name.english]\ntitel = \"Game\"\n").unwrap();
+        let err = Config::try_load(&manifest).unwrap_err().to_string();
+        assert!(err.contains("did you mean `title`?"), "{err}");
+        fs::write(
+            &config_path,
+            "[emulator]\ncommand = \"melonDS\"\nargz = []\n",
+        )
+        .unwrap();
+        assert!(Config::try_load(&manifest).is_err());
+
+        fs::remove_file(&config_path).unwrap();
+        fs::write(
+            &manifest,
+            "[package.metadata.nds]\nromfs_dir = \"romfs\"\nbanner = \"b.bin\"\n",
+        )
+        .unwrap();
+        let err = Config::try_load(&manifest).unwrap_err().to_string();
+        let location = format!("{}:3:1: unknown key `banner`", manifest.display());
+        assert!(err.contains(&location), "{err}");
+
+        assert_eq!(edit_distance("icn", "icon"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn load_from_manifest_and_config() {
+        let dir = TempDir::new("config");
+        let manifest = dir.join("Cargo.toml");
+        let load = || Config::try_load(&manifest).unwrap();
+
+        // Neither file
+        assert_eq!(load(), Config::default());
+
+        // Cargo metadata only, including the `romfs_dir` written by older versions
+        fs::write(
+            &manifest,
+            "[package]\nname = \"game\"\n\n[package.metadata.nds]\nicon = \"cargo.png\"\n\
+            romfs_dir = \"assets\"\nrom_version = 3\n\n[package.metadata.nds.header]\nlogo = \"logo.bin\"\n",
+        )
+        .unwrap();
+        let config = load();
+        assert_eq!(config.ds_icon(), Some(Path::new("cargo.png")));
+        assert_eq!(config.romfs, Some(vec![PathBuf::from("assets")]));
+        assert_eq!(config.rom_version, Some(3));
+
+        // nds.toml overrides each key, and keeps the others
+        fs::write(
+            dir.join(CONFIG_FILE_NAME),
+            "icon = \"nds.bmp\"\nromfs = [\"a\", \"b\"]\n",
+        )
+        .unwrap();
+        let config = load();
+        assert_eq!(config.ds_icon(), Some(Path::new("nds.bmp")));
+        assert_eq!(
+            config.romfs,
+            Some(vec![PathBuf::from("a"), PathBuf::from("b")])
+        );
+        assert_eq!(config.rom_version, Some(3));
+        assert_eq!(config.header.logo, Some(PathBuf::from("logo.bin")));
+
+        // Setting `icons.ds` in nds.toml replaces the `icon` of the metadata
+        fs::write(dir.join(CONFIG_FILE_NAME), "[icons]\nds = \"ds.bmp\"\n").unwrap();
+        assert_eq!(load().ds_icon(), Some(Path::new("ds.bmp")));
+
+        // Errors are located in the manifest
+        fs::write(&manifest, "[package.metadata.nds]\nrom_version = \"3\"\n").unwrap();
+        let err = Config::try_load(&manifest).unwrap_err().to_string();
+        assert!(
+            err.starts_with(&format!("{}:2:", manifest.display())),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn parse_language_subset() {
+        let config: Config = toml::from_str(
+            r#"
+            [name.english]
+            title = "Game"
+            subtitle = "Subtitle"
+            manufacturer = "Author"
+
+            [name.german]
+            title = "Spiel"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.name.english,
+            Some(banner("Game", "Subtitle", "Author"))
+        );
+        assert_eq!(
+            config.name.german,
+            Some(BannerText {
+                title: Some("Spiel".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.name.japanese, None);
+
+        let resolved = config
+            .name
+            .resolve(&banner("Default", "Description", "Nobody"));
+        assert_eq!(resolved[0], banner("Game", "Subtitle", "Author"));
+        assert_eq!(resolved[3], banner("Spiel", "Subtitle", "Author"));
+        assert_eq!(resolved[5], banner("Game", "Subtitle", "Author"));
+    }
+
+    #[test]
+    fn parse_without_english() {
+        let config: Config = toml::from_str(
+            r#"
+            [name.japanese]
+            title = "Game JP"
+            "#,
+        )
+        .unwrap();
+
+        let default = banner("Default", "Description", "Nobody");
+        let resolved = config.name.resolve(&default);
+        assert_eq!(resolved[0], banner("Game JP", "Description", "Nobody"));
+        assert!(resolved[1..].iter().all(|text| *text == default));
+    }
+
+    #[test]
+    fn banner_file() {
+        let contents = "\
+            ゲーム\n\n\n\
+            Game\nA long description\nAuthor\n\
+            Jeu\n\n\n\
+            \n\n\n\
+            \n\n\n\
+            Juego\nUna descripción\n\n";
+        let names = parse_banner_file(contents).unwrap();
+        assert_eq!(
+            names.english,
+            Some(banner("Game", "A long description", "Author"))
+        );
+        assert_eq!(names.german, None);
+        assert_eq!(
+            names.spanish,
+            Some(BannerText {
+                title: Some("Juego".to_string()),
+                subtitle: Some("Una descripción".to_string()),
+                manufacturer: None,
+            })
+        );
+
+        // Inline names take precedence
+        let config: Config = toml::from_str(
+            r#"
+            [name.english]
+            title = "Inline"
+            "#,
+        )
+        .unwrap();
+        let resolved = config
+            .name
+            .or(&names)
+            .resolve(&banner("Default", "Description", "Nobody"));
+        assert_eq!(
+            resolved[1],
+            banner("Inline", "A long description", "Author")
+        );
+        assert_eq!(resolved[2], banner("Jeu", "A long description", "Author"));
+
+        let err = parse_banner_file("Game\nDescription\nAuthor\n").unwrap_err();
+        assert!(
+            err.contains("expected 18 lines") && err.ends_with("found 3"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn banner_arg() {
+        assert_eq!(banner("a", "b", "c").to_ndstool_arg(), "a;b;c");
+        assert_eq!(
+            BannerText {
+                title: Some("a".to_string()),
+                ..Default::default()
+            }
+            .to_ndstool_arg(),
+            "a;;"
+        );
+        assert_eq!(banner("a;b", "c", "d;").to_ndstool_arg(), "a,b;c;d,");
+    }
+
+    #[test]
+    fn parse_include_dirs() {
+        let config: Config = toml::from_str(r#"include_dirs = ["../shared", "assets"]"#).unwrap();
+        assert_eq!(
+            config.include_dirs,
+            [PathBuf::from("../shared"), PathBuf::from("assets")]
+        );
+    }
+
+    #[test]
+    fn parse_loader_reset() {
+        let config: Config = toml::from_str(
+            r#"
+            [loader]
+            reset = ["curl", "http://{address}/reset"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.loader.reset,
+            Some(vec![
+                "curl".to_string(),
+                "http://{address}/reset".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("4M"), Ok(4 * 1024 * 1024));
+        assert_eq!(parse_size("4 MiB"), Ok(4 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("4X").is_err());
+        assert!(parse_size("M").is_err());
+
+        let config: Config = toml::from_str(r#"max_rom_size = "8M""#).unwrap();
+        assert_eq!(config.max_rom_size, Some(ByteSize(8 * 1024 * 1024)));
+        let config: Config = toml::from_str("max_rom_size = 1024").unwrap();
+        assert_eq!(config.max_rom_size, Some(ByteSize(1024)));
+        assert!(toml::from_str::<Config>(r#"max_rom_size = "big""#).is_err());
+    }
+
+    #[test]
+    fn parse_codes() {
+        let config: Config = toml::from_str("game_code = \"ABCE\"\nmaker_code = \"01\"").unwrap();
+        assert_eq!(config.game_code.as_deref(), Some("ABCE"));
+        assert_eq!(config.maker_code.as_deref(), Some("01"));
+        assert!(config.validate().is_ok());
+
+        for code in [
+            "game_code = \"ABC\"",
+            "game_code = \"ABCDE\"",
+            "game_code = \"AB E\"",
+            "maker_code = \"1\"",
+            "maker_code = \"é\"",
+        ] {
+            let config: Config = toml::from_str(code).unwrap();
+            assert!(config.validate().is_err(), "{code}");
+        }
+    }
+
+    #[test]
+    fn parse_romfs() {
+        let config: Config = toml::from_str("romfs = \"assets\"").unwrap();
+        assert_eq!(config.romfs, Some(vec![PathBuf::from("assets")]));
+        let config: Config = toml::from_str("romfs = [\"assets\", \"shared/data\"]").unwrap();
+        assert_eq!(
+            config.romfs,
+            Some(vec![PathBuf::from("assets"), PathBuf::from("shared/data")])
+        );
+        assert_eq!(toml::from_str::<Config>("").unwrap().romfs, None);
+    }
+
+    #[test]
+    fn parse_romfs_filters() {
+        let config: Config = toml::from_str(
+            r#"
+            romfs_include = ["**/*.bin"]
+            romfs_exclude = ["drafts/**"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.romfs_include, ["**/*.bin"]);
+        assert_eq!(config.romfs_exclude, Some(vec!["drafts/**".to_string()]));
+        assert!(!config.require_romfs);
+        assert!(
+            toml::from_str::<Config>("require_romfs = true")
+                .unwrap()
+                .require_romfs
+        );
+    }
+
+    #[test]
+    fn parse_arm7() {
+        let config: Config = toml::from_str("arm7 = \"arm7/core.elf\"").unwrap();
+        assert_eq!(config.arm7, Some(PathBuf::from("arm7/core.elf")));
+
+        let config: Config = toml::from_str("arm7_crate = \"arm7\"").unwrap();
+        assert_eq!(config.arm7_crate, Some(PathBuf::from("arm7")));
+        assert!(config.validate().is_ok());
+        let config: Config = toml::from_str("arm7 = \"arm7.elf\"\narm7_crate = \"arm7\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parse_build_std() {
+        let config: Config = toml::from_str(
+            r#"
+            build_std = ["core", "alloc", "std", "panic_abort"]
+            build_std_features = ["panic_immediate_abort"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.build_std.unwrap(),
+            ["core", "alloc", "std", "panic_abort"]
+        );
+        assert_eq!(config.build_std_features, ["panic_immediate_abort"]);
+
+        let config: Config = toml::from_str("build_std = []").unwrap();
+        assert!(config.validate().is_err());
+        assert!(validate_build_std(&["core".to_string(), String::new()]).is_err());
+    }
+
+    #[test]
+    fn parse_specs() {
+        let config: Config = toml::from_str("specs = \"link/dsi_arm9.specs\"").unwrap();
+        assert_eq!(config.specs, Some(PathBuf::from("link/dsi_arm9.specs")));
+    }
+
+    #[test]
+    fn parse_output() {
+        let config: Config = toml::from_str("output = \"dist/MyGame.nds\"").unwrap();
+        assert_eq!(config.output, Some(PathBuf::from("dist/MyGame.nds")));
+    }
+
+    #[test]
+    fn parse_target_spec() {
+        let config: Config = toml::from_str("target_spec = \"../specs/ds.json\"").unwrap();
+        assert_eq!(config.target_spec, Some(PathBuf::from("../specs/ds.json")));
+    }
+
+    #[test]
+    fn parse_dsi() {
+        let config: Config = toml::from_str("dsi = true").unwrap();
+        assert!(config.dsi);
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str("dsi = true\nheader.size = 0x200").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parse_rom_version() {
+        let config: Config = toml::from_str("rom_version = 3").unwrap();
+        assert_eq!(config.rom_version, Some(3));
+        assert!(toml::from_str::<Config>("rom_version = 256").is_err());
+    }
+
+    #[test]
+    fn parse_emulator() {
+        let config: Config = toml::from_str(r#"emulator = "melonDS""#).unwrap();
+        let emulator = config.emulator.unwrap();
+        assert_eq!(emulator, EmulatorConfig::new("melonDS".to_string()));
+        assert_eq!(emulator.args_for(Path::new("game.nds")), ["game.nds"]);
+
+        let config: Config = toml::from_str(
+            r#"
+            [emulator]
+            command = "/opt/melonDS/melonDS"
+            args = ["--boot", "always", "{nds}", "-f"]
+            "#,
+        )
+        .unwrap();
+        let emulator = config.emulator.unwrap();
+        assert_eq!(emulator.command, "/opt/melonDS/melonDS");
+        assert_eq!(
+            emulator.args_for(Path::new("game.nds")),
+            ["--boot", "always", "game.nds", "-f"]
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            [emulator]
+            command = "desmume"
+            args = ["--cpu-mode=1"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.emulator.unwrap().args_for(Path::new("game.nds")),
+            ["--cpu-mode=1", "game.nds"]
+        );
+    }
+
+    #[test]
+    fn parse_hosts() {
+        for (host, expected) in [
+            ("192.168.1.2", "192.168.1.2"),
+            ("fe80::1", "fe80::1"),
+            ("[fe80::1]", "fe80::1"),
+            ("nds.local", "nds.local"),
+            ("my-ds", "my-ds"),
+        ] {
+            assert_eq!(host.parse::<Host>().unwrap().to_string(), expected);
+        }
+        for host in ["", "nds..local", "my ds", "http://nds.local", "[nds.local]"] {
+            assert!(host.parse::<Host>().is_err(), "{host}");
+        }
+
+        let config: Config = toml::from_str(r#"address = "nds.lan""#).unwrap();
+        assert_eq!(config.address, Some("nds.lan".parse().unwrap()));
+    }
+
+    #[test]
+    fn run_config_precedence() {
+        let user: Config = toml::from_str(
+            r#"
+            address = "192.168.1.2"
+            emulator = "desmume"
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            address = "192.168.1.3"
+            emulator = "melonDS"
+
+            [loader]
+            command = "dsilink"
+            "#,
+        )
+        .unwrap();
+        let env_vars = |var: &str| match var {
+            ADDRESS_ENV => Some("192.168.1.4".to_string()),
+            LOADER_ENV => Some("my-loader".to_string()),
+            _ => None,
+        };
+        let no_env = |_: &str| None;
+        let host = |host: &str| Some(host.parse::<Host>().unwrap());
+        let cli_address = host("192.168.1.5");
+        let cli = DeviceProfile {
+            address: cli_address.clone(),
+            ..Default::default()
+        };
+        let none = DeviceProfile::default;
+
+        // Command line first
+        let run = RunConfig::resolve(cli, None, Some("no$gba"), env_vars, &user, &project).unwrap();
+        assert_eq!(run.address, cli_address);
+        assert_eq!(
+            run.emulator,
+            Some(EmulatorConfig::new("no$gba".to_string()))
+        );
+
+        // Then the environment
+        let run = RunConfig::resolve(none(), None, None, env_vars, &user, &project).unwrap();
+        assert_eq!(run.address, host("192.168.1.4"));
+        assert_eq!(run.loader, "my-loader");
+
+        // Then the user config
+        let run = RunConfig::resolve(none(), None, None, no_env, &user, &project).unwrap();
+        assert_eq!(run.address, host("192.168.1.2"));
+        assert_eq!(
+            run.emulator,
+            Some(EmulatorConfig::new("desmume".to_string()))
+        );
+        assert_eq!(run.loader, "dsilink");
+
+        // Then the project config, then the defaults
+        let run =
+            RunConfig::resolve(none(), None, None, no_env, &Config::default(), &project).unwrap();
+        assert_eq!(run.address, host("192.168.1.3"));
+        assert_eq!(
+            run.emulator,
+            Some(EmulatorConfig::new("melonDS".to_string()))
+        );
+
+        let default = Config::default();
+        let run = RunConfig::resolve(none(), None, None, no_env, &default, &default).unwrap();
+        assert_eq!(run.address, None);
+        assert_eq!(run.emulator, None);
+        assert_eq!(run.loader, DEFAULT_LOADER);
+
+        let bad_env = |var: &str| (var == ADDRESS_ENV).then(|| "no where".to_string());
+        assert!(RunConfig::resolve(none(), None, None, bad_env, &default, &default).is_err());
+    }
+
+    #[test]
+    fn device_profiles() {
+        let user: Config = toml::from_str(
+            r#"
+            [devices.lite]
+            address = "192.168.1.20"
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            address = "192.168.1.3"
+
+            [devices.lite]
+            address = "192.168.1.30"
+
+            [devices.dsi]
+            address = "dsi.lan"
+            argv0 = "sd:/game.nds"
+            server = true
+            "#,
+        )
+        .unwrap();
+        let env_vars = |var: &str| (var == ADDRESS_ENV).then(|| "192.168.1.4".to_string());
+        let host = |host: &str| Some(host.parse::<Host>().unwrap());
+
+        // The profile overrides the environment and configs
+        let run = RunConfig::resolve(
+            DeviceProfile::default(),
+            Some("dsi"),
+            None,
+            env_vars,
+            &user,
+            &project,
+        )
+        .unwrap();
+        assert_eq!(run.address, host("dsi.lan"));
+        assert_eq!(run.argv0.as_deref(), Some("sd:/game.nds"));
+        assert!(run.server);
+
+        // The user's profile overrides the project's one with the same name
+        let run = RunConfig::resolve(
+            DeviceProfile::default(),
+            Some("lite"),
+            None,
+            env_vars,
+            &user,
+            &project,
+        )
+        .unwrap();
+        assert_eq!(run.address, host("192.168.1.20"));
+        assert!(!run.server);
+
+        // The command line overrides the profile
+        let cli = DeviceProfile {
+            address: host("192.168.1.5"),
+            argv0: Some(String::from("game")),
+            server: false,
+        };
+        let run = RunConfig::resolve(cli, Some("dsi"), None, env_vars, &user, &project).unwrap();
+        assert_eq!(run.address, host("192.168.1.5"));
+        assert_eq!(run.argv0.as_deref(), Some("game"));
+        assert!(run.server);
+
+        let e = RunConfig::resolve(
+            DeviceProfile::default(),
+            Some("xl"),
+            None,
+            env_vars,
+            &user,
+            &project,
+        )
+        .unwrap_err();
+        assert!(e.contains("dsi, lite"), "{e}");
+        assert!(toml::from_str::<Config>("[devices.lite]\nport = 17491").is_err());
+    }
+
+    #[test]
+    fn parse_header() {
+        let config: Config = toml::from_str(
+            r#"
+            [header]
+            size = 0x200
+            logo = "logo.bmp"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.header.header_size(), Some(0x200));
+        assert_eq!(config.header.secure_area, SecureArea::None);
+        assert_eq!(config.header.logo, Some(PathBuf::from("logo.bmp")));
+        assert!(config.header.validate().is_ok());
+
+        let config: Config = toml::from_str("header.secure_area = \"encrypted\"").unwrap();
+        assert_eq!(config.header.header_size(), Some(0x4000));
+        assert!(config.header.validate().is_ok());
+
+        let header = |size, secure_area| HeaderConfig {
+            size: Some(ByteSize(size)),
+            secure_area,
+            logo: None,
+        };
+        assert!(header(0x4000, SecureArea::Encrypted).validate().is_ok());
+        assert!(header(0x200, SecureArea::Encrypted).validate().is_err());
+        assert!(header(0x300, SecureArea::None).validate().is_err());
+        assert!(header(0x8000, SecureArea::None).validate().is_err());
+        assert!(header(0, SecureArea::None).validate().is_err());
+
+        assert!(toml::from_str::<Config>("header.secure_area = \"decrypted\"").is_err());
+    }
+
+    #[test]
+    fn parse_icons() {
+        let config: Config = toml::from_str(
+            r#"
+            [icons]
+            ds = "icon.svg"
+            animated = ["frame0.png", "frame1.png"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.ds_icon(), Some(Path::new("icon.svg")));
+        assert_eq!(config.icons.animated.len(), 2);
+        assert!(config.validate().is_ok());
+
+        let config: Config =
+            toml::from_str("icon = \"icon.png\"\nicons.ds = \"icon.svg\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parse_animation() {
+        let config: Config = toml::from_str(
+            r#"
+            [icons]
+            animated = ["a.png", "b.png"]
+            frame_duration = 5
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let sequence = config.icons.animation_sequence();
+        assert_eq!(sequence.len(), 2);
+        assert_eq!((sequence[1].frame, sequence[1].duration), (1, 5));
+
+        let config: Config = toml::from_str(
+            r#"
+            [icons]
+            animated = ["a.png", "b.png"]
+            sequence = [
+                { frame = 0, duration = 30 },
+                { frame = 1, duration = 5, flip_horizontal = true },
+                { frame = 0, duration = 5, flip_vertical = true },
+            ]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.icons.animation_sequence(), config.icons.sequence);
+
+        for invalid in [
+            "icons.animated = ['a.png']\nicons.sequence = [{ frame = 1, duration = 5 }]",
+            "icons.animated = ['a.png']\nicons.sequence = [{ frame = 0, duration = 0 }]",
+            "icons.sequence = [{ frame = 0, duration = 5 }]",
+            "icons.animated = ['a.png']\nicons.banner = 'banner.bin'",
+            "icons.animated = ['0', '1', '2', '3', '4', '5', '6', '7', '8']",
+            "icon = 'icon.bmp'\nicons.banner = 'banner.bin'",
+        ] {
+            let config: Config = toml::from_str(invalid).unwrap();
+            assert!(config.validate().is_err(), "{invalid}");
+        }
+
+        let steps = vec!["{ frame = 0, duration = 1 }"; 65].join(", ");
+        let config: Config = toml::from_str(&format!(
+            "icons.animated = ['a.png']\nicons.sequence = [{steps}]"
+        ))
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parse_empty() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use similar::{ChangeTag, TextDiff};
+
+use crate::log::{paint, CYAN, GREEN, RED};
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// Format the difference between two versions of a file as a unified diff,
+/// colored if the output on stderr is.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    let line = |style: &str, text: &str| paint(style, text) + "\n";
+
+    let mut out = line(RED, &format!("--- {name}"));
+    out += &line(GREEN, &format!("+++ {name} (template)"));
+
+    let diff = TextDiff::from_lines(old, new);
+    for hunk in diff.unified_diff().context_radius(CONTEXT).iter_hunks() {
+        out += &line(CYAN, &hunk.header().to_string());
+        for change in hunk.iter_changes() {
+            let text = change.value().strip_suffix('\n').unwrap_or(change.value());
+            out += &match change.tag() {
+                ChangeTag::Equal => format!(" {text}\n"),
+                ChangeTag::Delete => line(RED, &format!("-{text}")),
+                ChangeTag::Insert => line(GREEN, &format!("+{text}")),
+            };
+        }
+    }
+
+    out
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no. Returns false
+/// without asking if stdin isn't a terminal.
+pub fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+
+    eprint!("{question} [y/N] ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log::set_color;
+    use crate::test_util::lock_color;
+
+    use super::*;
+
+    #[test]
+    fn format_hunks() {
+        let _lock = lock_color();
+        set_color(false);
+        let old: String = (1..=20).map(|i| format!("{i}\n")).collect();
+        let new = old.replace("\n2\n", "\ntwo\n").replace("18\n", "");
+
+        let diff = unified_diff("main.rs", &old, &new);
+        assert_eq!(
+            diff,
+            "--- main.rs\n+++ main.rs (template)\n\
+            @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+            @@ -15,6 +15,5 @@\n 15\n 16\n 17\n-18\n 19\n 20\n"
+        );
+
+        assert_eq!(
+            unified_diff("main.rs", &old, &old),
+            "--- main.rs\n+++ main.rs (template)\n"
+        );
+    }
+}
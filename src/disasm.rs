@@ -0,0 +1,113 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::size::elf_size;
+use crate::{run_tool, NdsError};
+
+/// What to disassemble, as selected on the command line.
+#[derive(Debug, Default, PartialEq)]
+pub struct DisasmOptions {
+    /// Only this function.
+    pub symbol: Option<String>,
+    /// Only around this address, with `context` bytes before and after.
+    pub address: Option<u64>,
+    pub context: u64,
+    /// Interleave the source code.
+    pub source: bool,
+}
+
+/// Parse an address, in hexadecimal with a `0x` prefix, or in decimal.
+pub fn parse_address(address: &str) -> Result<u64, String> {
+    let parsed = match address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address `{address}`, expected e.g. 0x02001234"))
+}
+
+/// The `arm-none-eabi-objdump` arguments to disassemble `elf`.
+fn objdump_args(elf: &Path, options: &DisasmOptions) -> Vec<String> {
+    let mut args = vec!["-d".to_string(), "-C".to_string()];
+    if options.source {
+        args.push("-S".to_string());
+    }
+    if let Some(symbol) = &options.symbol {
+        args.push(format!("--disassemble={symbol}"));
+    }
+    if let Some(address) = options.address {
+        let start = address.saturating_sub(options.context);
+        let stop = address.saturating_add(options.context);
+        args.push(format!("--start-address={start:#x}"));
+        args.push(format!("--stop-address={stop:#x}"));
+    }
+    args.push(elf.to_string_lossy().into_owned());
+    args
+}
+
+/// Whether the ELF file has DWARF debug info to interleave the source code.
+fn has_debug_info(elf: &Path) -> Result<bool, NdsError> {
+    Ok(elf_size(elf)?
+        .sections
+        .iter()
+        .any(|section| section.name == ".debug_info"))
+}
+
+/// Disassemble the ELF file with `arm-none-eabi-objdump`, printing it on
+/// stdout. The source is only interleaved if the file has debug info.
+pub fn disassemble(elf: &Path, mut options: DisasmOptions) -> Result<(), NdsError> {
+    options.source = options.source && has_debug_info(elf)?;
+
+    let mut command = Command::new("arm-none-eabi-objdump");
+    command.args(objdump_args(elf, &options));
+    run_tool(&mut command, "arm-none-eabi-objdump")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(parse_address("0x02001234"), Ok(0x0200_1234));
+        assert_eq!(parse_address("0X10"), Ok(16));
+        assert_eq!(parse_address("4096"), Ok(4096));
+        assert!(parse_address("0xZZ").is_err());
+    }
+
+    #[test]
+    fn objdump_arguments() {
+        let elf = Path::new("game.arm9.elf");
+        assert_eq!(
+            objdump_args(
+                elf,
+                &DisasmOptions {
+                    source: true,
+                    ..Default::default()
+                }
+            ),
+            ["-d", "-C", "-S", "game.arm9.elf"]
+        );
+        assert_eq!(
+            objdump_args(
+                elf,
+                &DisasmOptions {
+                    symbol: Some("main".to_string()),
+                    address: Some(0x0200_1000),
+                    context: 0x20,
+                    source: false,
+                }
+            ),
+            [
+                "-d",
+                "-C",
+                "--disassemble=main",
+                "--start-address=0x2000fe0",
+                "--stop-address=0x2001020",
+                "game.arm9.elf"
+            ]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::env;
+use std::path::PathBuf;
+
+use crate::log::{paint, GREEN, RED, YELLOW};
+use crate::{check_blocksds, find_blocksds, verify_rust_version};
+
+/// Check that the toolchain and tools needed to build and run nds executables
+/// are set up, printing a summary of each check.
+///
+/// Returns whether every essential check passed.
+pub fn run_doctor() -> bool {
+    let mut ok = true;
+
+    match verify_rust_version() {
+        Ok(()) => pass("rustc", "nightly version is recent enough"),
+        Err(msg) => {
+            fail("rustc", &msg);
+            ok = false;
+        }
+    }
+
+    let blocksds = find_blocksds();
+    match check_blocksds(&blocksds) {
+        Ok(()) => pass("BLOCKSDS", &blocksds.display().to_string()),
+        Err(msg) => {
+            fail("BLOCKSDS", &msg);
+            ok = false;
+        }
+    }
+
+    for (tool, essential) in [
+        ("arm-none-eabi-gcc", true),
+        ("ndstool", true),
+        ("dslink", false),
+    ] {
+        match find_in_path(tool) {
+            Some(path) => pass(tool, &path.display().to_string()),
+            None if essential => {
+                fail(tool, "not found in $PATH");
+                ok = false;
+            }
+            None => warn(
+                tool,
+                "not found in $PATH, `cargo nds run` and `test` won't work",
+            ),
+        }
+    }
+
+    ok
+}
+
+/// Find an executable in one of the directories of $PATH.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+
+    env::split_paths(&path)
+        .map(|dir| dir.join(name).with_extension(env::consts::EXE_EXTENSION))
+        .find(|candidate| candidate.is_file())
+}
+
+fn pass(check: &str, msg: &str) {
+    eprintln!("{}      {check}: {msg}", paint(GREEN, "[ok]"));
+}
+
+fn warn(check: &str, msg: &str) {
+    eprintln!("{} {check}: {msg}", paint(YELLOW, "[warning]"));
+}
+
+fn fail(check: &str, msg: &str) {
+    eprintln!("{}   {check}: {msg}", paint(RED, "[error]"));
+}
@@ -0,0 +1,313 @@
+//! `--dry-run`: print the commands cargo-nds would run to build the ROM and
+//! run it, without running them. The config and paths are resolved as usual,
+//! but cargo doesn't build anything, so the arguments which depend on its
+//! output, starting with the executable ndstool packs, are predicted from the
+//! package and the cargo arguments, with a note saying so.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::command::{flag_value, profile_dir, CargoCmd, Input};
+use crate::config::{self, Config, SecureArea};
+use crate::target::{target_name, target_spec};
+use crate::{
+    absolute_path, arm7_crate_command, banner_names, default_banner_text, encrypt_command,
+    filter_romfs, get_include_dirs, get_name, load_build_cache, load_config, make_cargo_command,
+    ndstool_command, package_dir, package_nds_config, romfs_needs_staging, romfs_sources,
+    target_dir, write_command, Banner, NDSConfig, NdsError, ARM7_TARGET_SPEC,
+};
+
+/// The header of each command printed.
+const HEADER: &str = "Would run:";
+
+/// Print the commands `input` would run: cargo's with the given
+/// `message_format`, then ndstool's and the loader's or emulator's if the
+/// command builds and runs a ROM. With `--no-build`, the ROM of the last build
+/// is used, as when running.
+pub fn print_commands(
+    input: &Input,
+    nds_config: &Config,
+    message_format: Option<String>,
+) -> Result<(), NdsError> {
+    let no_build = input.cmd.no_build();
+    if input.cmd.should_compile() && !no_build {
+        write_command(
+            HEADER,
+            &make_cargo_command(input, nds_config, &message_format)?,
+        );
+    }
+    if !input.cmd.builds_rom() {
+        return Ok(());
+    }
+
+    let config = if no_build {
+        load_build_cache(&input.cargo_flags, &input.cmd.cargo_args())
+            .map_err(NdsError::Executable)?
+    } else {
+        let mut config =
+            input
+                .cmd
+                .with_options(predicted_config(input)?, &input.cargo_flags, false)?;
+        let nds_config = load_config(&config)?;
+        if let (None, Some(arm7_crate)) = (&nds_config.arm7, &nds_config.arm7_crate) {
+            let cargo_args = input.cmd.cargo_args();
+            write_command(
+                HEADER,
+                &arm7_crate_command(&config, arm7_crate, &cargo_args, &input.cargo_flags)?,
+            );
+            let executable = predicted_arm7_executable(&config, arm7_crate, &cargo_args, input)?;
+            note(&format!(
+                "cargo isn't run, so the ARM7 executable is assumed to be {}",
+                absolute_path(&executable).display()
+            ));
+            config.arm7 = Some(executable);
+        }
+        for command in ndstool_commands(&config)? {
+            write_command(HEADER, &command);
+        }
+        config
+    };
+
+    for command in input.cmd.run_commands(&config)? {
+        write_command(HEADER, &command);
+    }
+    Ok(())
+}
+
+/// Print a note about an argument which may differ when actually running.
+fn note(msg: &str) {
+    eprintln!("note: {msg}");
+}
+
+/// The [`NDSConfig`] of the executable cargo would build, from the package it
+/// builds and the `--bin`, `--example` and profile arguments.
+fn predicted_config(input: &Input) -> Result<NDSConfig, NdsError> {
+    let cargo_args = input.cmd.cargo_args();
+    let metadata = input.cargo_flags.metadata_command().exec()?;
+
+    let package =
+        match flag_value(&cargo_args, "--package").or_else(|| flag_value(&cargo_args, "-p")) {
+            Some(name) => metadata
+                .packages
+                .iter()
+                .find(|package| package.name == name),
+            None => {
+                let manifest_path = package_dir(&input.cargo_flags).join("Cargo.toml");
+                metadata
+                    .packages
+                    .iter()
+                    .find(|package| package.manifest_path.as_std_path() == manifest_path)
+            }
+        };
+    let package = package.ok_or_else(|| {
+        NdsError::Executable(String::from(
+            "Could not find the package cargo would build, select one with `--package`",
+        ))
+    })?;
+
+    let example = flag_value(&cargo_args, "--example");
+    let target = match (example, flag_value(&cargo_args, "--bin")) {
+        (Some(example), _) => example.to_string(),
+        (None, Some(bin)) => bin.to_string(),
+        (None, None) => {
+            let bins: Vec<&str> = package
+                .targets
+                .iter()
+                .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+                .map(|target| target.name.as_str())
+                .collect();
+            match (package.default_run.as_deref(), bins.as_slice()) {
+                (Some(bin), _) | (None, &[bin]) => bin.to_string(),
+                _ => {
+                    return Err(NdsError::Executable(format!(
+                        "Could not tell which executable of {} cargo would build, select one with `--bin`",
+                        package.name
+                    )))
+                }
+            }
+        }
+    };
+
+    let mut dir = metadata
+        .target_directory
+        .as_std_path()
+        .join(target_name(target_spec(&input.target)))
+        .join(profile_dir(&cargo_args).unwrap_or("debug"));
+    let (name, file_name) = if matches!(input.cmd, CargoCmd::Test(_)) {
+        // The test executables are named after the crate, with a hash
+        dir.push("deps");
+        (
+            format!("{target} tests"),
+            format!("{}-<hash>", target.replace('-', "_")),
+        )
+    } else if example.is_some() {
+        dir.push("examples");
+        (
+            format!("{target} - {} example", package.name),
+            target.clone(),
+        )
+    } else {
+        (target.clone(), target.clone())
+    };
+
+    let executable = dir.join(format!("{file_name}.arm9.elf"));
+    note(&format!(
+        "cargo isn't run, so the executable is assumed to be {}",
+        absolute_path(&executable).display()
+    ));
+    package_nds_config(package, name, executable)
+}
+
+/// The ARM7 executable [`build_arm7_crate`](crate::build_arm7_crate) would
+/// build from the package in `crate_dir`: the package's executable, in the
+/// directory of the ARM7 target and the profile.
+fn predicted_arm7_executable(
+    config: &NDSConfig,
+    crate_dir: &Path,
+    cargo_args: &[String],
+    input: &Input,
+) -> Result<PathBuf, NdsError> {
+    let manifest_path = config
+        .cargo_manifest_path
+        .parent()
+        .unwrap()
+        .join(crate_dir)
+        .join("Cargo.toml");
+    let manifest = config::read_manifest(&manifest_path)
+        .map_err(|e| NdsError::Read(manifest_path.clone(), e))?;
+    let name = manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| {
+            NdsError::Config(format!("{} has no package name", manifest_path.display()))
+        })?;
+
+    Ok(target_dir(&input.cargo_flags)
+        .join(Path::new(ARM7_TARGET_SPEC).file_stem().unwrap())
+        .join(profile_dir(cargo_args).unwrap_or("debug"))
+        .join(name))
+}
+
+/// The ndstool commands [`build_nds`](crate::build_nds) runs, without
+/// converting the icons, building the animated banner or staging the RomFS,
+/// which it does first.
+fn ndstool_commands(config: &NDSConfig) -> Result<Vec<Command>, NdsError> {
+    let nds_config = load_config(config)?;
+
+    let default_banner = default_banner_text(&get_name(config)?, config, &nds_config);
+    let banner_text = banner_names(config, &nds_config)?.resolve(&default_banner);
+    let (banner, icon);
+    let ndstool_banner = if let Some(file) = &nds_config.icons.banner {
+        banner = config.cargo_manifest_path.with_file_name(file);
+        Banner::File(&banner)
+    } else if !nds_config.icons.animated.is_empty() {
+        banner = config.path_banner();
+        note(&format!(
+            "the banner is built from the animated icon into {}",
+            banner.display()
+        ));
+        Banner::File(&banner)
+    } else {
+        icon = match nds_config.ds_icon() {
+            Some(icon) => config.cargo_manifest_path.with_file_name(icon),
+            None => PathBuf::from(&config.icon),
+        };
+        if !is_bmp(&icon) {
+            note(&format!(
+                "{} is converted to a BMP icon first",
+                icon.display()
+            ));
+        }
+        Banner::Icon(&icon, &banner_text)
+    };
+
+    let include_dirs = get_include_dirs(config, &nds_config)?;
+    let dirs = romfs_sources(config, &nds_config, &include_dirs)?;
+    let romfs = if dirs.is_empty() {
+        None
+    } else {
+        let (_, excluded) = filter_romfs(&nds_config, &dirs).map_err(NdsError::Input)?;
+        if romfs_needs_staging(&dirs, excluded) {
+            note(&format!(
+                "the RomFS is staged into {} first",
+                config.path_romfs().display()
+            ));
+            Some(config.path_romfs())
+        } else {
+            Some(dirs[0].clone())
+        }
+    };
+
+    let mut commands = vec![ndstool_command(
+        config,
+        &nds_config,
+        ndstool_banner,
+        romfs.as_deref(),
+    )?];
+    if nds_config.header.secure_area == SecureArea::Encrypted {
+        commands.push(encrypt_command(config));
+    }
+    Ok(commands)
+}
+
+/// Whether an icon is already a BMP image, which ndstool takes as is.
+fn is_bmp(icon: &Path) -> bool {
+    icon.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bmp"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn predicted_ndstool_commands() {
+        let dir = TempDir::new("dry-run");
+        fs::create_dir_all(dir.join("romfs")).unwrap();
+        fs::create_dir_all(dir.join("music")).unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"game\"\n").unwrap();
+        fs::write(
+            dir.join("nds.toml"),
+            "romfs = [\"romfs\", \"music\"]\nicons.ds = \"icon.png\"\nheader.secure_area = \"encrypted\"\n",
+        )
+        .unwrap();
+
+        let config = NDSConfig {
+            name: String::from("game"),
+            target_path: dir.join("target/game.arm9.elf"),
+            cargo_manifest_path: dir.join("Cargo.toml"),
+            arm7: Some(PathBuf::from("arm7.elf")),
+            ..Default::default()
+        };
+        let commands = ndstool_commands(&config).unwrap();
+        let args: Vec<String> = commands[0]
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        let (nds, arm9) = (config.path_nds(), config.path_arm9());
+        assert_eq!(
+            args[..6],
+            [
+                "-c",
+                &nds.to_string_lossy(),
+                "-9",
+                &arm9.to_string_lossy(),
+                "-7",
+                "arm7.elf"
+            ]
+        );
+        // The icon is passed before it's converted, and the RomFS where it's staged
+        assert_eq!(args[6..8], ["-b", &dir.join("icon.png").to_string_lossy()]);
+        assert_eq!(
+            args[args.len() - 2..],
+            ["-d", &dir.join("target/game.romfs").to_string_lossy()]
+        );
+        assert_eq!(commands[1].get_args().next().unwrap(), "-se");
+    }
+}
@@ -0,0 +1,165 @@
+//! Errors of the functions building and running ROMs, so that they can be
+//! used as a library without exiting. The CLI prints them and exits with
+//! [`NdsError::exit_code`].
+
+use std::path::PathBuf;
+use std::process::{self, ExitStatus};
+use std::{fmt, io};
+
+use crate::CargoError;
+
+/// Why building or running a ROM failed.
+#[derive(Debug)]
+pub enum NdsError {
+    /// cargo could not be run, or its output could not be read.
+    Cargo(CargoError),
+    /// `cargo metadata` failed, e.g. because of an invalid manifest.
+    Metadata(cargo_metadata::Error),
+    /// The toolchain isn't set up: rustc, the sysroot, `rust-src` or BlocksDS.
+    Toolchain(String),
+    /// The package's config (`nds.toml`, `[package.metadata.nds]` or the user
+    /// config) could not be loaded or is invalid.
+    Config(String),
+    /// cargo didn't build the executable to make the ROM from, or it can't be
+    /// read.
+    Executable(String),
+    /// Another input of the ROM is missing or invalid, such as an icon or a
+    /// RomFS directory.
+    Input(String),
+    /// A file could not be read.
+    Read(PathBuf, io::Error),
+    /// A file could not be written.
+    Write(PathBuf, io::Error),
+    /// A tool, such as ndstool or the loader, isn't installed or not in $PATH.
+    ToolNotFound(String),
+    /// A tool could be found, but not started.
+    Spawn(String, io::Error),
+    /// A tool exited unsuccessfully, after printing its own errors. `details`
+    /// says what it was doing, if that helps.
+    Failed {
+        tool: String,
+        status: ExitStatus,
+        details: Option<String>,
+    },
+    /// The ROM is bigger than `--max-size` or `max_rom_size`. The message
+    /// includes a size report, if one could be made.
+    RomTooBig(String),
+    /// The tests run in an emulator failed, with the given exit code.
+    TestsFailed { emulator: String, code: i32 },
+    /// The Ctrl-C handler stopping `watch` could not be set.
+    CtrlCHandler(ctrlc::Error),
+}
+
+impl NdsError {
+    /// The code the CLI exits with: the one of the failed tool, or 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Failed { status, .. } => crate::exit_code(*status),
+            Self::TestsFailed { code, .. } => *code,
+            _ => 1,
+        }
+    }
+
+    /// The error of a tool which could not be started.
+    pub(crate) fn spawn(tool: &str, e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::NotFound {
+            Self::ToolNotFound(tool.to_string())
+        } else {
+            Self::Spawn(tool.to_string(), e)
+        }
+    }
+
+    /// Print the error, unless the tool which failed already did, and exit.
+    pub fn exit(&self) -> ! {
+        if !matches!(self, Self::Failed { details: None, .. }) {
+            eprintln!("error: {self}");
+        }
+        process::exit(self.exit_code())
+    }
+}
+
+impl fmt::Display for NdsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cargo(e) => write!(f, "{e}"),
+            Self::Metadata(e) => write!(f, "Failed to get cargo metadata: {e}"),
+            Self::Toolchain(msg) | Self::Config(msg) | Self::Executable(msg) | Self::Input(msg) => {
+                f.write_str(msg)
+            }
+            Self::Read(path, e) => write!(f, "Could not read {}: {e}", path.display()),
+            Self::Write(path, e) => write!(f, "Could not write {}: {e}", path.display()),
+            Self::ToolNotFound(tool) => {
+                write!(
+                    f,
+                    "Could not run `{tool}`: it was not found in $PATH. Check that it is installed"
+                )
+            }
+            Self::Spawn(tool, e) => write!(f, "Could not run `{tool}`: {e}"),
+            Self::Failed {
+                details: Some(details),
+                ..
+            } => f.write_str(details),
+            Self::Failed {
+                tool,
+                status,
+                details: None,
+            } => write!(f, "`{tool}` failed ({status})"),
+            Self::RomTooBig(msg) => f.write_str(msg),
+            Self::TestsFailed { emulator, code } => {
+                write!(f, "the tests failed in `{emulator}` (exit code {code})")
+            }
+            Self::CtrlCHandler(e) => write!(f, "Could not set the Ctrl-C handler: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for NdsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Cargo(e) => Some(e),
+            Self::Metadata(e) => Some(e),
+            Self::Read(_, e) | Self::Write(_, e) | Self::Spawn(_, e) => Some(e),
+            Self::CtrlCHandler(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CargoError> for NdsError {
+    fn from(e: CargoError) -> Self {
+        Self::Cargo(e)
+    }
+}
+
+impl From<cargo_metadata::Error> for NdsError {
+    fn from(e: cargo_metadata::Error) -> Self {
+        Self::Metadata(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_errors() {
+        let missing = NdsError::spawn("ndstool", io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(&missing, NdsError::ToolNotFound(tool) if tool == "ndstool"));
+        assert_eq!(missing.exit_code(), 1);
+        let denied = NdsError::spawn("ndstool", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(denied, NdsError::Spawn(..)));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            let failed = NdsError::Failed {
+                tool: String::from("dslink"),
+                status: ExitStatus::from_raw(3 << 8),
+                details: None,
+            };
+            assert_eq!(failed.exit_code(), 3);
+            assert_eq!(failed.to_string(), "`dslink` failed (exit status: 3)");
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use crate::checksum::short_sha256;
+use crate::log::debug;
+use crate::print_command;
+
+/// Width and height of the DS banner icon, in pixels.
+pub const ICON_SIZE: u32 = 32;
+
+/// The icon has a 16 color palette, where the first color is transparent.
+const PALETTE_SIZE: usize = 16;
+
+/// Get a BMP version of an icon source image, converting it if needed.
+///
+/// BMP images are used as they are. PNG and SVG images are converted into
+/// `cache_dir`, named after a hash of their contents, so an image is only
+/// converted again when it changes. SVG images are rasterized with
+/// `rsvg-convert`, which must be in $PATH.
+pub fn convert_icon(src: &Path, cache_dir: &Path) -> Result<PathBuf, String> {
+    let extension = src
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if extension == "bmp" {
+        return Ok(src.to_path_buf());
+    }
+    if extension != "png" && extension != "svg" {
+        return Err(format!("{} is not a BMP, PNG or SVG image", src.display()));
+    }
+
+    let contents = fs::read(src).map_err(|e| format!("could not read {}: {e}", src.display()))?;
+    let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+    let dst = cache_dir.join(format!("{stem}-{}.bmp", short_sha256(&contents)));
+
+    if dst.is_file() {
+        debug!("Icon {} is up to date", src.display());
+        return Ok(dst);
+    }
+
+    fs::create_dir_all(cache_dir)
+        .map_err(|e| format!("could not create {}: {e}", cache_dir.display()))?;
+
+    debug!("Converting icon {} to {}", src.display(), dst.display());
+
+    // An existing BMP is trusted, so it's only put in place once complete
+    let partial = dst.with_extension(format!("{}.partial", process::id()));
+    if extension == "svg" {
+        let png = dst.with_extension("png");
+        rasterize_svg(src, &png)?;
+        let converted = png_to_bmp(&png, &partial);
+        let _ = fs::remove_file(png);
+        converted?;
+    } else {
+        png_to_bmp(src, &partial)?;
+    }
+    fs::rename(&partial, &dst).map_err(|e| {
+        let _ = fs::remove_file(&partial);
+        format!("could not write {}: {e}", dst.display())
+    })?;
+
+    Ok(dst)
+}
+
+/// Render an SVG image to a 32×32 PNG with `rsvg-convert`.
+fn rasterize_svg(src: &Path, dst: &Path) -> Result<(), String> {
+    let mut command = Command::new("rsvg-convert");
+    command
+        .args(["--width", "32", "--height", "32", "--output"])
+        .arg(dst)
+        .arg(src);
+
+    print_command(&command);
+
+    let status = command.status().map_err(|e| {
+        format!("could not run rsvg-convert, which is needed to convert SVG icons: {e}")
+    })?;
+    if !status.success() {
+        return Err(format!("rsvg-convert failed to convert {}", src.display()));
+    }
+
+    Ok(())
+}
+
+/// Convert a 32×32 PNG icon to the 16 color BMP format ndstool expects.
+///
+/// Pixels which are mostly transparent use the transparent palette entry. The
+/// icon can't have more than 15 other colors, since it isn't quantized.
+pub fn png_to_bmp(src: &Path, dst: &Path) -> Result<(), String> {
+    let file = File::open(src).map_err(|e| format!("could not open {}: {e}", src.display()))?;
+    let (width, height, pixels) =
+        decode_png(BufReader::new(file)).map_err(|e| format!("{}: {e}", src.display()))?;
+
+    if (width, height) != (ICON_SIZE, ICON_SIZE) {
+        return Err(format!(
+            "{} is {width}×{height}, but the icon must be {ICON_SIZE}×{ICON_SIZE}",
+            src.display()
+        ));
+    }
+
+    let bmp = encode_bmp(&pixels).map_err(|e| format!("{}: {e}", src.display()))?;
+    fs::write(dst, bmp).map_err(|e| format!("could not write {}: {e}", dst.display()))
+}
+
+/// Decode a PNG image into its size and RGBA pixels.
+fn decode_png(reader: impl std::io::Read) -> Result<(u32, u32, Vec<[u8; 4]>), String> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let buf = &buf[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+        png::ColorType::Rgb => buf.chunks(3).map(|p| [p[0], p[1], p[2], 0xff]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buf.iter().map(|&p| [p, p, p, 0xff]).collect(),
+        png::ColorType::Indexed => unreachable!("indexed images are expanded to RGB"),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
+
+/// Encode 32×32 RGBA pixels as a 4 bits per pixel BMP.
+fn encode_bmp(pixels: &[[u8; 4]]) -> Result<Vec<u8>, String> {
+    // Index 0 is always transparent on the DS, so opaque colors start at 1
+    let mut palette: Vec<[u8; 3]> = vec![[0xff, 0x00, 0xff]];
+    let mut indices = Vec::with_capacity(pixels.len());
+
+    for &[r, g, b, a] in pixels {
+        if a < 0x80 {
+            indices.push(0);
+            continue;
+        }
+
+        let index = match palette[1..].iter().position(|&color| color == [r, g, b]) {
+            Some(index) => index + 1,
+            None => {
+                palette.push([r, g, b]);
+                palette.len() - 1
+            }
+        };
+        indices.push(index as u8);
+    }
+
+    if palette.len() > PALETTE_SIZE {
+        return Err(format!(
+            "the icon has {} colors, but at most {} (plus transparency) are supported",
+            palette.len() - 1,
+            PALETTE_SIZE - 1
+        ));
+    }
+    palette.resize(PALETTE_SIZE, [0, 0, 0]);
+
+    let row_size = ICON_SIZE as usize / 2;
+    let data_offset = 14 + 40 + PALETTE_SIZE * 4;
+    let data_size = row_size * ICON_SIZE as usize;
+
+    let mut bmp = Vec::with_capacity(data_offset + data_size);
+
+    // BITMAPFILEHEADER
+    bmp.extend(b"BM");
+    bmp.extend(((data_offset + data_size) as u32).to_le_bytes());
+    bmp.extend(0u32.to_le_bytes());
+    bmp.extend((data_offset as u32).to_le_bytes());
+
+    // BITMAPINFOHEADER
+    bmp.extend(40u32.to_le_bytes());
+    bmp.extend((ICON_SIZE as i32).to_le_bytes());
+    bmp.extend((ICON_SIZE as i32).to_le_bytes());
+    bmp.extend(1u16.to_le_bytes()); // planes
+    bmp.extend(4u16.to_le_bytes()); // bits per pixel
+    bmp.extend(0u32.to_le_bytes()); // no compression
+    bmp.extend((data_size as u32).to_le_bytes());
+    bmp.extend(2835i32.to_le_bytes()); // 72 DPI
+    bmp.extend(2835i32.to_le_bytes());
+    bmp.extend((PALETTE_SIZE as u32).to_le_bytes());
+    bmp.extend(0u32.to_le_bytes());
+
+    for [r, g, b] in palette {
+        bmp.extend([b, g, r, 0]);
+    }
+
+    // Rows are stored bottom-up, with the left pixel in the high nibble
+    for row in indices.chunks(ICON_SIZE as usize).rev() {
+        bmp.extend(row.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    }
+
+    Ok(bmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    fn encode_png(width: u32, height: u32, pixels: &[[u8; 4]]) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(pixels.concat().as_slice())
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn convert_icon() {
+        let mut pixels = vec![[0, 0, 0, 0]; 32 * 32];
+        pixels[0] = [0xff, 0, 0, 0xff];
+        pixels[1] = [0, 0xff, 0, 0xff];
+        pixels[32 * 31] = [0xff, 0, 0, 0xff];
+
+        let png = encode_png(32, 32, &pixels);
+        let (width, height, decoded) = decode_png(png.as_slice()).unwrap();
+        assert_eq!((width, height), (32, 32));
+        assert_eq!(decoded, pixels);
+
+        let bmp = encode_bmp(&decoded).unwrap();
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp.len(), 14 + 40 + 16 * 4 + 16 * 32);
+        // 4 bits per pixel
+        assert_eq!(bmp[28], 4);
+
+        // Red is the first opaque color, green the second
+        let palette = &bmp[54..54 + 16 * 4];
+        assert_eq!(&palette[4..8], [0, 0, 0xff, 0]);
+        assert_eq!(&palette[8..12], [0, 0xff, 0, 0]);
+
+        // The top row is stored last
+        let data = &bmp[54 + 16 * 4..];
+        assert_eq!(data[0], 0x10);
+        assert_eq!(data[31 * 16], 0x12);
+    }
+
+    #[test]
+    fn too_many_colors() {
+        let pixels: Vec<[u8; 4]> = (0..32 * 32).map(|i| [i as u8, 0, 0, 0xff]).collect();
+        assert!(encode_bmp(&pixels).is_err());
+    }
+
+    #[test]
+    fn cache_conversions() {
+        let dir = TempDir::new("icon-cache");
+        let cache = dir.join("cache");
+        let src = dir.join("icon.png");
+        fs::write(&src, encode_png(32, 32, &[[0, 0, 0, 0xff]; 32 * 32])).unwrap();
+
+        let bmp = super::convert_icon(&src, &cache).unwrap();
+        assert!(bmp.starts_with(&cache));
+
+        // The cached conversion is reused, even if it was modified
+        fs::write(&bmp, "cached").unwrap();
+        assert_eq!(super::convert_icon(&src, &cache).unwrap(), bmp);
+        assert_eq!(fs::read(&bmp).unwrap(), b"cached");
+
+        // Changing the source converts it again
+        fs::write(&src, encode_png(32, 32, &[[0xff, 0, 0, 0xff]; 32 * 32])).unwrap();
+        let changed = super::convert_icon(&src, &cache).unwrap();
+        assert_ne!(changed, bmp);
+        assert_eq!(&fs::read(&changed).unwrap()[..2], b"BM");
+        // Only the complete conversions are in the cache
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 2);
+
+        // BMP images are used as they are
+        assert_eq!(super::convert_icon(&changed, &cache).unwrap(), changed);
+        assert!(super::convert_icon(&dir.join("icon.gif"), &cache).is_err());
+    }
+
+    #[test]
+    fn wrong_size() {
+        let dir = TempDir::new("icon");
+        let src = dir.join("icon.png");
+        fs::write(&src, encode_png(16, 16, &[[0, 0, 0, 0xff]; 16 * 16])).unwrap();
+
+        let err = png_to_bmp(&src, &dir.join("icon.bmp")).unwrap_err();
+        assert!(err.contains("16×16"), "{err}");
+    }
+}
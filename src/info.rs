@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::banner;
+
+/// Size of the part of the header read by [`RomInfo::parse`].
+const HEADER_SIZE: usize = 0x88;
+
+/// The main fields of a ROM's header, and its banner titles.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub rom_version: u8,
+    /// Size of the file.
+    pub rom_size: u64,
+    /// Size of the ROM's contents, according to the header.
+    pub used_rom_size: u32,
+    pub arm9_entry: u32,
+    pub arm9_load_address: u32,
+    pub arm9_size: u32,
+    pub arm7_entry: u32,
+    pub arm7_load_address: u32,
+    pub arm7_size: u32,
+    /// The banner titles by language, if the ROM has a banner.
+    pub banner: Vec<BannerTitle>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BannerTitle {
+    pub language: &'static str,
+    pub title: String,
+}
+
+impl RomInfo {
+    /// Read the header and banner of the ROM at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let rom = fs::read(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        Self::parse(&rom).map_err(|e| format!("{} is not a valid ROM: {e}", path.display()))
+    }
+
+    /// Parse the header and banner of a ROM.
+    pub fn parse(rom: &[u8]) -> Result<Self, String> {
+        let header = rom.get(..HEADER_SIZE).ok_or("the header is truncated")?;
+        let text = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&header[range])
+                .trim_end_matches('\0')
+                .to_string()
+        };
+        let word =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+
+        let banner_offset = word(0x68) as usize;
+        let banner = if banner_offset == 0 {
+            Vec::new()
+        } else {
+            let banner = rom
+                .get(banner_offset..)
+                .ok_or("the banner is out of the ROM")?;
+            banner::read_titles(banner)?
+                .into_iter()
+                .map(|(language, title)| BannerTitle { language, title })
+                .collect()
+        };
+
+        Ok(Self {
+            title: text(0x00..0x0c),
+            game_code: text(0x0c..0x10),
+            maker_code: text(0x10..0x12),
+            rom_version: header[0x1e],
+            rom_size: rom.len() as u64,
+            used_rom_size: word(0x80),
+            arm9_entry: word(0x24),
+            arm9_load_address: word(0x28),
+            arm9_size: word(0x2c),
+            arm7_entry: word(0x34),
+            arm7_load_address: word(0x38),
+            arm7_size: word(0x3c),
+            banner,
+        })
+    }
+
+    /// Print the fields, one per line.
+    pub fn print(&self) {
+        println!("Title:        {}", self.title);
+        println!("Game code:    {}", self.game_code);
+        println!("Maker code:   {}", self.maker_code);
+        println!("ROM version:  {}", self.rom_version);
+        println!(
+            "ROM size:     {} bytes ({} used)",
+            self.rom_size, self.used_rom_size
+        );
+        println!(
+            "ARM9:         entry {:#010x}, loaded at {:#010x}, {} bytes",
+            self.arm9_entry, self.arm9_load_address, self.arm9_size
+        );
+        println!(
+            "ARM7:         entry {:#010x}, loaded at {:#010x}, {} bytes",
+            self.arm7_entry, self.arm7_load_address, self.arm7_size
+        );
+
+        if self.banner.is_empty() {
+            println!("Banner:       none");
+            return;
+        }
+        println!("Banner:");
+        for title in &self.banner {
+            println!(
+                "  {:<10}  {}",
+                title.language,
+                title.title.replace('\n', " / ")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header() {
+        let mut rom = vec![0; 0x200 + 0x840];
+        rom[..4].copy_from_slice(b"GAME");
+        rom[0x0c..0x12].copy_from_slice(b"ABCD01");
+        rom[0x1e] = 0x12;
+        rom[0x24..0x28].copy_from_slice(&0x0200_0800u32.to_le_bytes());
+        rom[0x34..0x38].copy_from_slice(&0x037f_8000u32.to_le_bytes());
+        rom[0x68..0x6c].copy_from_slice(&0x200u32.to_le_bytes());
+        rom[0x80..0x84].copy_from_slice(&0x1000u32.to_le_bytes());
+
+        // Version 1 banner, with an English title
+        rom[0x200] = 1;
+        let title: Vec<u8> = "Game".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        rom[0x200 + 0x340..][..title.len()].copy_from_slice(&title);
+
+        let info = RomInfo::parse(&rom).unwrap();
+        assert_eq!(info.title, "GAME");
+        assert_eq!(info.game_code, "ABCD");
+        assert_eq!(info.maker_code, "01");
+        assert_eq!(info.rom_version, 0x12);
+        assert_eq!(info.used_rom_size, 0x1000);
+        assert_eq!(info.arm9_entry, 0x0200_0800);
+        assert_eq!(info.arm7_entry, 0x037f_8000);
+        assert_eq!(info.banner.len(), 6);
+        assert_eq!(info.banner[0].title, "");
+        assert_eq!(info.banner[1].title, "Game");
+
+        assert!(RomInfo::parse(&rom[..0x40]).is_err());
+        rom[0x68..0x6c].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert!(RomInfo::parse(&rom).is_err());
+    }
+}
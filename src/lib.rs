@@ -1,94 +1,311 @@
+//! The library behind `cargo nds`, which can also be embedded, e.g. by a
+//! launcher, to build and run ROMs without running the CLI.
+//!
+//! # Embedding
+//!
+//! These functions make up the stable embedding API. They return an
+//! [`NdsError`] instead of exiting, and don't print anything but progress
+//! messages (see [`log::set_level`]) and the output of the tools they run:
+//!
+//! - [`run_cargo`] (and [`make_cargo_command`]) builds the executable, with
+//!   an [`Input`] parsed from the command line arguments as in `main`.
+//! - [`get_metadata`] finds the executable among cargo's messages, and
+//!   [`load_config`] loads the package's config.
+//! - [`build_nds`] builds the ROM, or [`build_nds_with_assets`] with assets
+//!   prepared while cargo was building (see [`prepare::start`]).
+//! - [`load_run_config`] and [`link`] send it to a device.
+//!
+//! Everything else, including the callbacks of [`command::CargoCmd`], is
+//! there for the CLI and may change, but also returns an [`NdsError`]: only
+//! `main` exits the process, with [`NdsError::exit`].
+
+mod addr2line;
+mod banner;
+mod bundle;
+mod checksum;
 pub mod command;
-mod graph;
+pub mod config;
+mod diff;
+mod disasm;
+pub mod doctor;
+pub mod dry_run;
+mod error;
+mod icon;
+mod info;
+pub mod log;
+pub mod prepare;
+mod romfs;
+mod runner;
+pub mod sarif;
+mod size;
+pub mod target;
+pub mod template;
+#[cfg(test)]
+mod test_util;
+pub mod watch;
 
 use core::fmt;
-use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
-use std::{env, io, process};
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{env, fs, io};
 
-use cargo_metadata::{Message, MetadataCommand};
+use cargo_metadata::diagnostic::DiagnosticLevel;
+use cargo_metadata::{Artifact, Message, Package};
 use command::{Input, Test};
 use rustc_version::Channel;
 use semver::Version;
+use serde::{Deserialize, Serialize};
 use tee::TeeReader;
 
-use crate::command::{CargoCmd, Run};
-use crate::graph::UnitGraph;
+use crate::command::{CargoCmd, CargoFlags, Run};
+use crate::config::{
+    parse_banner_file, validate_build_std, BannerAuthors, BannerText, Config, DeviceProfile,
+    EmulatorConfig, HeaderConfig, Languages, RunConfig, SecureArea, CONFIG_FILE_NAME,
+    DEFAULT_BUILD_STD, EMULATOR_ENV, LANGUAGE_COUNT, SPECS_ENV,
+};
+pub use crate::error::NdsError;
+use crate::log::{debug, info};
+use crate::prepare::Prepared;
+use crate::size::SizeReport;
+use crate::target::{find_target_spec, write_builtin_target_spec, ARM7_TARGET_JSON, TARGET_JSON};
+pub use crate::target::{target_name, target_spec, ARM7_TARGET_SPEC, DEFAULT_TARGET_SPEC};
 
 /// Build a command using [`make_cargo_build_command`] and execute it,
-/// parsing and returning the messages from the spawned process.
+/// parsing the messages from the spawned process as they arrive and returning
+/// the ones needed afterwards.
 ///
 /// For commands that produce an executable output, this function will build the
 /// `.elf` binary that can be used to create other nds files.
-pub fn run_cargo(input: &Input, message_format: Option<String>) -> (ExitStatus, Vec<Message>) {
-    let mut command = make_cargo_command(input, &message_format);
-
-    if input.verbose {
-        print_command(&command);
-    }
-
-    let mut process = command.spawn().unwrap();
-    let command_stdout = process.stdout.take().unwrap();
+pub fn run_cargo(
+    input: &Input,
+    nds_config: &Config,
+    message_format: Option<String>,
+) -> Result<(ExitStatus, Vec<Message>), NdsError> {
+    let mut command = make_cargo_command(input, nds_config, &message_format)?;
+
+    print_command(&command);
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut process = command.spawn().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => CargoError::NotFound(program),
+        _ => CargoError::Spawn(program, e),
+    })?;
+    let command_stdout = process
+        .stdout
+        .take()
+        .ok_or_else(|| CargoError::Read(io::Error::other("cargo's stdout is not piped")))?;
 
     let mut tee_reader;
     let mut stdout_reader;
 
-    let buf_reader: &mut dyn BufRead = match (message_format, &input.cmd) {
+    let rendered_format = rendered_format(&message_format, &input.cmd);
+    let buf_reader: &mut dyn BufRead = match (rendered_format, &message_format, &input.cmd) {
         // The user presumably cares about the message format if set, so we should
         // copy stuff to stdout like they expect. We can still extract the executable
         // information out of it that we need for ndstool etc.
-        (Some(_), _) |
+        (None, Some(_), _) |
         // Rustdoc unfortunately prints to stdout for compile errors, so
         // we also use a tee when building doc tests too.
         // Possibly related: https://github.com/rust-lang/rust/issues/75135
-        (None, CargoCmd::Test(Test { doc: true, .. })) => {
+        (None, None, CargoCmd::Test(Test { doc: true, .. })) => {
             tee_reader = BufReader::new(TeeReader::new(command_stdout, io::stdout()));
             &mut tee_reader
         }
+        // Formats rendered by cargo-nds are printed below rather than copying the JSON
         _ => {
             stdout_reader = BufReader::new(command_stdout);
             &mut stdout_reader
         }
     };
 
-    let messages = Message::parse_stream(buf_reader)
-        .collect::<io::Result<_>>()
-        .unwrap();
+    // Messages are handled as cargo prints them (it renders the diagnostics
+    // itself), and only the ones used afterwards are kept
+    let keep_diagnostics = input.emit_sarif.is_some() || input.cmd.keeps_going();
+    let mut messages = Vec::new();
+    for message in Message::parse_stream(buf_reader) {
+        let message = message.map_err(|e| {
+            // Don't leave cargo running if we stop reading its output
+            let _ = process.kill();
+            match e.kind() {
+                io::ErrorKind::InvalidData => CargoError::Parse(e),
+                _ => CargoError::Read(e),
+            }
+        })?;
+        if rendered_format.is_some() {
+            render_message(&message);
+        }
+        if is_retained(&message, keep_diagnostics) {
+            messages.push(message);
+        }
+    }
+
+    let status = process.wait().map_err(CargoError::Read)?;
+    Ok((status, messages))
+}
+
+/// The `--message-format` values which cargo-nds renders itself from cargo's
+/// JSON messages, since it needs JSON to find the built executable.
+pub const RENDERED_FORMATS: &[&str] = &["human", "short"];
+
+/// The requested message format if cargo-nds renders the diagnostics itself.
+/// Doc tests are built without JSON, so their format is passed to cargo as is.
+fn rendered_format<'a>(message_format: &'a Option<String>, cmd: &CargoCmd) -> Option<&'a str> {
+    if let CargoCmd::Test(Test { doc: true, .. }) = cmd {
+        return None;
+    }
+    message_format
+        .as_deref()
+        .filter(|format| RENDERED_FORMATS.contains(format))
+}
+
+/// The message format requested from cargo. For the formats rendered by
+/// cargo-nds, this is JSON with the diagnostics rendered (with colors if
+/// `color`) in the requested style.
+fn cargo_message_format(message_format: &Option<String>, cmd: &CargoCmd, color: bool) -> String {
+    let color = if color {
+        ",json-diagnostic-rendered-ansi"
+    } else {
+        ""
+    };
+    match rendered_format(message_format, cmd) {
+        Some("short") => format!("json-diagnostic-short{color}"),
+        Some(_) => format!("json{color}"),
+        None => message_format
+            .as_deref()
+            .unwrap_or(CargoCmd::DEFAULT_MESSAGE_FORMAT)
+            .to_string(),
+    }
+}
+
+/// Print a message in the format requested by the user: the diagnostics, as
+/// rendered by rustc, on stderr, and any other output of cargo on stdout.
+fn render_message(message: &Message) {
+    match message {
+        Message::CompilerMessage(message) => {
+            if let Some(rendered) = &message.message.rendered {
+                eprint!("{rendered}");
+            }
+        }
+        Message::TextLine(line) => println!("{line}"),
+        _ => {}
+    }
+}
+
+/// Whether [`run_cargo`] keeps a message: the artifacts of executables, which
+/// are needed to build the ROM, and the diagnostics if they are written out
+/// later, e.g. as SARIF.
+fn is_retained(message: &Message, keep_diagnostics: bool) -> bool {
+    match message {
+        Message::CompilerArtifact(artifact) => artifact.executable.is_some(),
+        Message::CompilerMessage(_) => keep_diagnostics,
+        _ => false,
+    }
+}
+
+/// The exit code to exit with after cargo exited with `status`: cargo's own
+/// code, or as shells report it (128 + the signal) if cargo was killed.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
 
-    (process.wait().unwrap(), messages)
+/// Why [`run_cargo`] could not get cargo's result.
+#[derive(Debug)]
+pub enum CargoError {
+    /// The cargo executable doesn't exist.
+    NotFound(String),
+    /// The cargo executable exists, but could not be started.
+    Spawn(String, io::Error),
+    /// Reading cargo's output failed.
+    Read(io::Error),
+    /// cargo's output is not a valid stream of JSON messages.
+    Parse(io::Error),
+}
+
+impl fmt::Display for CargoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(program) => write!(
+                f,
+                "could not find cargo (`{program}`). Check that cargo is installed and in \
+                $PATH, or set $CARGO to the path of the cargo executable"
+            ),
+            Self::Spawn(program, e) => write!(f, "could not run cargo (`{program}`): {e}"),
+            Self::Read(e) => write!(f, "could not read cargo's output: {e}"),
+            Self::Parse(e) => write!(f, "could not parse cargo's output: {e}"),
+        }
+    }
 }
 
+impl std::error::Error for CargoError {}
+
 /// Create a cargo command based on the context.
 ///
 /// For "build" commands (which compile code, such as `cargo nds build` or `cargo nds clippy`),
-/// if there is no pre-built std detected in the sysroot, `build-std` will be used instead.
-pub fn make_cargo_command(input: &Input, message_format: &Option<String>) -> Command {
-    let blocksds = env::var("BLOCKSDS").unwrap_or("/opt/wonderful/thirdparty/blocksds/core".to_owned());
-    let rustflags = format!("-C link-args=-specs={blocksds}/sys/crts/ds_arm9.specs");
-
+/// if there is no pre-built std detected in the sysroot, `build-std` will be used instead
+/// (see `build_std_args`). `nds_config` is the config of the package, see
+/// [`load_command_config`].
+pub fn make_cargo_command(
+    input: &Input,
+    nds_config: &Config,
+    message_format: &Option<String>,
+) -> Result<Command, NdsError> {
     let cargo_cmd = &input.cmd;
 
     let mut command = cargo(&input.config);
-    command
-        .arg(cargo_cmd.subcommand_name())
-        .env("RUSTFLAGS", rustflags);
+    command.arg(cargo_cmd.subcommand_name());
+
+    // `cargo new` and `init` don't work on an existing package
+    match cargo_cmd {
+        CargoCmd::New(_) | CargoCmd::Init(_) => command.args(input.cargo_flags.network_args()),
+        _ => command.args(input.cargo_flags.args()),
+    };
+
+    // Only commands which compile code need BlocksDS
+    if cargo_cmd.should_compile() {
+        let map = target_dir(&input.cargo_flags).join(LINKER_MAP);
+        let rustflags = arm9_rustflags(
+            &arm9_specs(&input.cargo_flags, nds_config)?,
+            &map,
+            &nds_config.extra_link_args,
+        );
+        debug!(
+            "Using RUSTFLAGS: {}",
+            shlex::join(rustflags.iter().map(String::as_str))
+        );
+        // The encoded flags are separated by 0x1f instead of spaces, so that
+        // paths and linker args with spaces stay a single argument
+        command.env("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\x1f"));
+    }
 
     // Any command that needs to compile code will run under this environment.
     // Even `clippy` and `check` need this kind of context, so we'll just assume any other `Passthrough` command uses it too.
     if cargo_cmd.should_compile() {
         command
             .arg("--target")
-            .arg("armv5te-nintendo-ds.json")
-            .arg("-Z")
-            .arg("build-std=core,alloc")
+            .arg(target_spec(&input.target))
+            .args(build_std_args(input, nds_config)?)
             .arg("--message-format")
-            .arg(
-                message_format
-                    .as_deref()
-                    .unwrap_or(CargoCmd::DEFAULT_MESSAGE_FORMAT),
-            );
+            .arg(cargo_message_format(
+                message_format,
+                cargo_cmd,
+                input.cargo_flags.use_color(),
+            ));
     }
 
     if let CargoCmd::Test(test) = cargo_cmd {
@@ -100,7 +317,7 @@ pub fn make_cargo_command(input: &Input, message_format: &Option<String>) -> Com
     command.args(cargo_cmd.cargo_args());
 
     if let CargoCmd::Run(run) | CargoCmd::Test(Test { run_args: run, .. }) = &cargo_cmd {
-        if run.use_custom_runner() {
+        if run.use_cargo_runner() {
             command
                 .arg("--")
                 .args(run.build_args.passthrough.exe_args());
@@ -112,351 +329,2406 @@ pub fn make_cargo_command(input: &Input, message_format: &Option<String>) -> Com
         .stdin(Stdio::inherit())
         .stderr(Stdio::inherit());
 
-    command
+    Ok(command)
 }
 
-/// Build a `cargo` command with the given `--config` flags.
-fn cargo(config: &[String]) -> Command {
-    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
-    let mut cmd = Command::new(cargo);
-    cmd.args(config.iter().map(|cfg| format!("--config={cfg}")));
-    cmd
+/// The linker specs of the ARM9 executable: from `CARGO_NDS_SPECS`, or `specs`
+/// in `nds.toml` (relative to the package directory), or the BlocksDS default.
+/// Fails if the file doesn't exist.
+fn arm9_specs(cargo_flags: &CargoFlags, nds_config: &Config) -> Result<PathBuf, NdsError> {
+    let specs = match env::var_os(SPECS_ENV) {
+        Some(specs) => absolute_path(Path::new(&specs)),
+        None => match &nds_config.specs {
+            Some(specs) => package_dir(cargo_flags).join(specs),
+            None => blocksds_specs(&blocksds_root()?),
+        },
+    };
+
+    if !specs.is_file() {
+        return Err(NdsError::Input(format!(
+            "Could not find the linker specs: {}",
+            specs.display()
+        )));
+    }
+    debug!("Linking with the specs {}", specs.display());
+    Ok(specs)
 }
 
-fn print_command(command: &Command) {
-    let mut cmd_str = vec![command.get_program().to_string_lossy().to_string()];
-    cmd_str.extend(command.get_args().map(|s| s.to_string_lossy().to_string()));
+/// The `-Z build-std` arguments: the crates and features from the command line,
+/// or else from `nds.toml`. Fails if the list of crates is invalid.
+///
+/// Unless crates or features are configured, nothing is built when the sysroot
+/// already has a pre-built `core` and `alloc` for the target.
+fn build_std_args(input: &Input, nds_config: &Config) -> Result<Vec<String>, NdsError> {
+    let crates = input.build_std.clone().or(nds_config.build_std.clone());
+    let features = input
+        .build_std_features
+        .clone()
+        .unwrap_or(nds_config.build_std_features.clone());
+
+    let sysroot = find_sysroot().map_err(|e| {
+        NdsError::Toolchain(format!("Could not find the sysroot of the toolchain: {e}"))
+    })?;
+
+    if crates.is_none() && features.is_empty() {
+        let target = target_name(target_spec(&input.target));
+        if has_prebuilt_std(&sysroot, &target, DEFAULT_BUILD_STD) {
+            debug!("Using the pre-built standard library of {target} from the sysroot");
+            return Ok(Vec::new());
+        }
+    }
 
-    eprintln!("Running command:");
-    for (k, v) in command.get_envs() {
-        let v = v.map(|v| v.to_string_lossy().to_string());
-        eprintln!(
-            "   {}={} \\",
-            k.to_string_lossy(),
-            v.map_or_else(String::new, |s| shlex::quote(&s).to_string())
-        );
+    let crates =
+        crates.unwrap_or_else(|| DEFAULT_BUILD_STD.iter().map(ToString::to_string).collect());
+    validate_build_std(&crates)
+        .map_err(|e| NdsError::Config(format!("Invalid --build-std: {e}")))?;
+
+    // Only reached for commands that compile, so that e.g. `cargo nds clean`
+    // works without the component
+    if !has_rust_src(&sysroot) {
+        return Err(NdsError::Toolchain(format!(
+            "The `rust-src` component is not installed in {}, but is needed to build \
+            the standard library with `-Z build-std`. Install it with:\n\
+            \n    rustup component add rust-src",
+            sysroot.display()
+        )));
+    }
+
+    let mut args = vec!["-Z".to_string(), format!("build-std={}", crates.join(","))];
+    if !features.is_empty() {
+        args.push("-Z".to_string());
+        args.push(format!("build-std-features={}", features.join(",")));
+    }
+
+    Ok(args)
+}
+
+/// The rustc flags to link the ARM9 executable: the BlocksDS specs, the
+/// linker map, then the `extra_link_args` of `nds.toml`.
+fn arm9_rustflags(specs: &Path, map: &Path, extra_link_args: &[String]) -> Vec<String> {
+    let mut flags = vec![
+        "-C".to_string(),
+        format!("link-arg=-specs={}", specs.display()),
+        "-C".to_string(),
+        format!("link-arg=-Wl,-Map,{}", map.display()),
+    ];
+    for arg in extra_link_args {
+        flags.push("-C".to_string());
+        flags.push(format!("link-arg={arg}"));
     }
-    eprintln!("   {}\n", shlex::join(cmd_str.iter().map(String::as_str)));
+    flags
+}
+
+/// Whether the sysroot has the sources of the standard library, installed by
+/// the `rust-src` rustup component.
+fn has_rust_src(sysroot: &Path) -> bool {
+    sysroot.join("lib/rustlib/src/rust/library").is_dir()
 }
 
-/// Finds the sysroot path of the current toolchain
-pub fn find_sysroot() -> PathBuf {
-    let sysroot = env::var("SYSROOT").ok().unwrap_or_else(|| {
-        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+/// Whether the sysroot has an rlib of each of the `crates` for the target, as
+/// installed with rustup for tier 2 targets.
+fn has_prebuilt_std(sysroot: &Path, target: &str, crates: &[&str]) -> bool {
+    let Ok(entries) = fs::read_dir(sysroot.join("lib/rustlib").join(target).join("lib")) else {
+        return false;
+    };
+    let rlibs: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".rlib"))
+        .collect();
+
+    crates.iter().all(|name| {
+        let prefix = format!("lib{name}-");
+        rlibs.iter().any(|rlib| rlib.starts_with(&prefix))
+    })
+}
 
-        let output = Command::new(&rustc)
-            .arg("--print")
-            .arg("sysroot")
-            .output()
-            .unwrap_or_else(|_| panic!("Failed to run `{rustc} -- print sysroot`"));
-        String::from_utf8(output.stdout).expect("Failed to parse sysroot path into a UTF-8 string")
-    });
+/// The directory of the package cargo builds: the one of `--manifest-path`, or
+/// else the closest one to the current directory with a `Cargo.toml`.
+pub(crate) fn package_dir(cargo_flags: &CargoFlags) -> PathBuf {
+    if let Some(manifest_path) = &cargo_flags.manifest_path {
+        let manifest_path = absolute_path(manifest_path);
+        return manifest_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or(manifest_path);
+    }
 
-    PathBuf::from(sysroot.trim())
+    let cwd = env::current_dir().unwrap_or_default();
+    cwd.ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map_or_else(|| cwd.clone(), Path::to_path_buf)
 }
 
-/// Checks the current rust version and channel.
-/// Exits if the minimum requirement is not met.
-pub fn check_rust_version() {
-    let rustc_version = rustc_version::version_meta().unwrap();
+/// Load the config of the package cargo builds, see [`package_dir`] and
+/// [`load_config`].
+fn load_package_config(cargo_flags: &CargoFlags) -> Result<Config, NdsError> {
+    Config::try_load(&package_dir(cargo_flags).join("Cargo.toml"))
+        .map_err(|e| NdsError::Config(format!("Could not load {CONFIG_FILE_NAME}: {e}")))
+}
 
-    if rustc_version.channel > Channel::Nightly {
-        eprintln!("cargo-nds requires a nightly rustc version.");
-        eprintln!(
-            "Please run `rustup override set nightly` to use nightly in the \
-            current directory, or use `cargo +nightly nds` to use it for a \
-            single invocation."
-        );
-        process::exit(1);
+/// Load the config of the package `input` works on, once for the whole command,
+/// see [`load_package_config`]. `new` and `init` create the package, so they get
+/// the default config.
+pub fn load_command_config(input: &Input) -> Result<Config, NdsError> {
+    match input.cmd {
+        CargoCmd::New(_) | CargoCmd::Init(_) => Ok(Config::default()),
+        _ => load_package_config(&input.cargo_flags),
     }
+}
 
-    let old_version = MINIMUM_RUSTC_VERSION
-        > Version {
-            // Remove `-nightly` pre-release tag for comparison.
-            pre: semver::Prerelease::EMPTY,
-            ..rustc_version.semver.clone()
+/// The directories the assets of the package may come from: its RomFS
+/// directories (`romfs` by default) and `include_dirs`, those which exist.
+pub(crate) fn asset_dirs(cargo_flags: &CargoFlags) -> Result<Vec<PathBuf>, NdsError> {
+    let package_dir = package_dir(cargo_flags);
+    let nds_config = load_package_config(cargo_flags)?;
+
+    let include_dirs = include_dir_paths(&package_dir, &nds_config);
+    let romfs = find_romfs_dirs(&package_dir, &nds_config, &include_dirs).unwrap_or_default();
+    Ok(romfs
+        .into_iter()
+        .chain(include_dirs.into_iter().filter(|dir| dir.is_dir()))
+        .collect())
+}
+
+/// The `include_dirs` of the package in `package_dir`, whether they exist or not.
+pub(crate) fn include_dir_paths(package_dir: &Path, nds_config: &Config) -> Vec<PathBuf> {
+    nds_config
+        .include_dirs
+        .iter()
+        .map(|dir| package_dir.join(dir))
+        .collect()
+}
+
+/// When cargo must not access the network, make sure no command run by
+/// cargo-nds does: rustup doesn't install missing toolchains (which it would
+/// do for `rustc --print sysroot` and such), and cargo is offline even when
+/// the flags can't be passed to it.
+///
+/// This sets environment variables of the process, so that they also apply to
+/// the commands run by `rustc_version`.
+pub fn prevent_network_access(cargo_flags: &CargoFlags) {
+    if cargo_flags.is_offline() {
+        env::set_var("RUSTUP_AUTO_INSTALL", "0");
+        env::set_var("CARGO_NET_OFFLINE", "true");
+    }
+}
+
+/// After a failed offline build, explain what `-Z build-std` needs to have
+/// been downloaded beforehand, since the error from cargo doesn't say.
+pub fn explain_offline_failure(input: &Input, nds_config: &Config) {
+    if !input.cargo_flags.is_offline()
+        || !input.cmd.should_compile()
+        || build_std_args(input, nds_config).is_ok_and(|args| args.is_empty())
+    {
+        return;
+    }
+
+    let sysroot = find_sysroot().unwrap_or_else(|_| PathBuf::from("$(rustc --print sysroot)"));
+    eprintln!("{}", offline_build_std_note(&sysroot));
+}
+
+/// After a failed build with `--keep-going`, list the targets which built and
+/// the ones which failed. No ROM is built from the ones which did, since the
+/// executable to use may be one of those which failed.
+pub fn explain_keep_going_failure(input: &Input, messages: &[Message]) {
+    if input.cmd.keeps_going() {
+        eprintln!("{}", keep_going_summary(messages));
+    }
+}
+
+fn keep_going_summary(messages: &[Message]) -> String {
+    let describe =
+        |target: &cargo_metadata::Target| format!("{} ({})", target.name, target.kind.join(", "));
+    let mut built = Vec::new();
+    let mut failed = Vec::new();
+    for message in messages {
+        let (list, target) = match message {
+            Message::CompilerArtifact(artifact) if artifact.executable.is_some() => {
+                (&mut built, &artifact.target)
+            }
+            Message::CompilerMessage(msg) if msg.message.level == DiagnosticLevel::Error => {
+                (&mut failed, &msg.target)
+            }
+            _ => continue,
         };
+        let target = describe(target);
+        if !list.contains(&target) {
+            list.push(target);
+        }
+    }
 
-    let old_commit = match rustc_version.commit_date {
-        None => false,
-        Some(date) => {
-            MINIMUM_COMMIT_DATE
-                > CommitDate::parse(&date).expect("could not parse `rustc --version` commit date")
+    let list = |targets: &[String]| {
+        if targets.is_empty() {
+            String::from("none")
+        } else {
+            targets.join(", ")
         }
     };
+    format!(
+        "note: with `--keep-going`, no ROM is built unless every target builds\n    \
+        built: {}\n    failed: {}",
+        list(&built),
+        list(&failed)
+    )
+}
 
-    if old_version || old_commit {
-        eprintln!("cargo-nds requires rustc nightly version >= {MINIMUM_COMMIT_DATE}");
-        eprintln!("Please run `rustup update nightly` to upgrade your nightly version");
+/// How to prepare for offline builds with `-Z build-std`.
+fn offline_build_std_note(sysroot: &Path) -> String {
+    let library = sysroot.join("lib/rustlib/src/rust/library/Cargo.toml");
+    format!(
+        "note: the standard library is built with `-Z build-std`, which needs the `rust-src` \
+        component and the dependencies of the standard library. If cargo couldn't find them \
+        offline, fetch them beforehand with:\n\
+        \n    rustup component add rust-src\
+        \n    cargo fetch --manifest-path {}",
+        library.display()
+    )
+}
+
+/// File name of the linker map, written in the target dir.
+pub const LINKER_MAP: &str = "arm9.map";
+
+/// The target dir cargo builds into: from `--target-dir`, or as resolved by
+/// cargo from `CARGO_TARGET_DIR` and its configuration.
+pub fn target_dir(cargo_flags: &CargoFlags) -> PathBuf {
+    if let Some(dir) = &cargo_flags.target_dir {
+        return absolute_path(dir);
+    }
 
-        process::exit(1);
+    match cargo_flags.metadata_command().exec() {
+        Ok(metadata) => metadata.target_directory.into_std_path_buf(),
+        Err(_) => absolute_path(Path::new(
+            &env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into()),
+        )),
     }
 }
 
-/// Parses messages returned by "build" cargo commands (such as `cargo nds build` or `cargo nds run`).
-/// The returned [`CTRConfig`] is then used for further building in and execution
-/// in [`build_nds`], and [`link`].
-pub fn get_metadata(messages: &[Message]) -> NDSConfig {
-    let metadata = MetadataCommand::new()
-        .no_deps()
-        .exec()
-        .expect("Failed to get cargo metadata");
-
-    let mut package = None;
-    let mut artifact = None;
-
-    // Extract the final built executable. We may want to fail in cases where
-    // multiple executables, or none, were built?
-    for message in messages.iter().rev() {
-        if let Message::CompilerArtifact(art) = message {
-            if art.executable.is_some() {
-                package = Some(metadata[&art.package_id].clone());
-                artifact = Some(art.clone());
+/// Where the target used for a build comes from, see [`find_target`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetSource {
+    /// The `--target` argument.
+    Target,
+    /// The `--target-spec` argument.
+    TargetSpec,
+    /// `target_spec` in `nds.toml`.
+    Config,
+    /// The `armv5te-nintendo-ds.json` of the package or one of its parents.
+    Found,
+    /// The spec built into cargo-nds.
+    Builtin,
+}
 
-                break;
-            }
+impl fmt::Display for TargetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Target => write!(f, "--target"),
+            Self::TargetSpec => write!(f, "--target-spec"),
+            Self::Config => write!(f, "`target_spec` in {CONFIG_FILE_NAME}"),
+            Self::Found => write!(f, "found in the package directory or one of its parents"),
+            Self::Builtin => write!(f, "no {DEFAULT_TARGET_SPEC} found, using the built-in spec"),
         }
     }
-    if package.is_none() || artifact.is_none() {
-        eprintln!("No executable found from build command output!");
-        process::exit(1);
+}
+
+/// The target to build for, and why it was chosen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedTarget {
+    /// A target spec path, or the name of a target known to rustc.
+    pub spec: String,
+    pub source: TargetSource,
+}
+
+/// Find the target to build for, in order: from `--target`, from
+/// `--target-spec`, from `target_spec` in `nds.toml`, the first
+/// `armv5te-nintendo-ds.json` in the package directory or one of its parents,
+/// or else the built-in spec written to `nds/` in the target directory.
+pub fn find_target(input: &Input, nds_config: &Config) -> Result<ResolvedTarget, NdsError> {
+    let spec_path = |spec: PathBuf| spec.to_string_lossy().into_owned();
+
+    if let Some(target) = &input.target {
+        return Ok(ResolvedTarget {
+            spec: target.clone(),
+            source: TargetSource::Target,
+        });
+    }
+    if let Some(spec) = &input.target_spec {
+        return Ok(ResolvedTarget {
+            spec: spec_path(spec.clone()),
+            source: TargetSource::TargetSpec,
+        });
     }
 
-    let (package, artifact) = (package.unwrap(), artifact.unwrap());
+    let package_dir = package_dir(&input.cargo_flags);
+    if let Some(spec) = &nds_config.target_spec {
+        return Ok(ResolvedTarget {
+            spec: spec_path(package_dir.join(spec)),
+            source: TargetSource::Config,
+        });
+    }
+    if let Some(spec) = find_target_spec(&package_dir) {
+        return Ok(ResolvedTarget {
+            spec: spec_path(spec),
+            source: TargetSource::Found,
+        });
+    }
 
-    let mut icon = String::from("./icon.bmp");
+    let spec = write_builtin_target_spec(
+        &target_dir(&input.cargo_flags),
+        DEFAULT_TARGET_SPEC,
+        TARGET_JSON,
+    )
+    .map_err(|e| NdsError::Toolchain(format!("Could not write the default target spec: {e}")))?;
+    Ok(ResolvedTarget {
+        spec: spec_path(spec),
+        source: TargetSource::Builtin,
+    })
+}
 
-    if !Path::new(&icon).exists() {
-        icon = format!(
-            "{}/sys/icon.bmp",
-            env::var("BLOCKSDS").unwrap()
-        );
+/// Resolve the target spec when `--target` isn't given, see [`find_target`].
+/// The result is set as the target of `input`.
+pub fn resolve_target(input: &mut Input, nds_config: &Config) -> Result<(), NdsError> {
+    let uses_target = input.cmd.should_compile() || matches!(input.cmd, CargoCmd::Clean(_));
+    if input.target.is_some() || !uses_target {
+        return Ok(());
     }
 
-    // for now assume a single "kind" since we only support one output artifact
-    let name = match artifact.target.kind[0].as_ref() {
-        "bin" | "lib" | "rlib" | "dylib" if artifact.target.test => {
-            format!("{} tests", artifact.target.name)
-        }
-        "example" => {
-            format!("{} - {} example", artifact.target.name, package.name)
-        }
-        _ => artifact.target.name,
-    };
+    let spec = find_target(input, nds_config)?.spec;
+    debug!(
+        "Using target spec {}",
+        absolute_path(Path::new(&spec)).display()
+    );
 
-    let author = match package.authors.as_slice() {
-        [name, ..] => name.clone(),
-        [] => String::from("Unspecified Author"), // as standard with the devkitPRO toolchain
-    };
+    input.cmd.set_target(&spec);
+    input.target = Some(spec);
+    Ok(())
+}
 
-    NDSConfig {
-        name : name,
-        author : author,
-        description: package
-            .description
-            .clone()
-            .unwrap_or_else(|| String::from("Homebrew Application")),
-        icon : icon,
-        target_path: artifact.executable.unwrap().into(),
-        cargo_manifest_path: package.manifest_path.into(),
+/// Print the target that would be used, why, and the key settings of its spec,
+/// for `--print-target`.
+pub fn print_target(input: &Input, nds_config: &Config) -> Result<(), NdsError> {
+    let target = find_target(input, nds_config)?;
+    let is_file = target.spec.ends_with(".json") || input.target.is_none();
+
+    if is_file {
+        println!(
+            "target spec: {}",
+            absolute_path(Path::new(&target.spec)).display()
+        );
+    } else {
+        println!("target: {}", target.spec);
+    }
+    println!("chosen from: {}", target.source);
+
+    if !is_file {
+        println!("(a target known to rustc)");
+        return Ok(());
+    }
+
+    let json = fs::read_to_string(&target.spec)
+        .map_err(|e| NdsError::Read(PathBuf::from(&target.spec), e))?;
+    let settings = target::describe_spec(&json)
+        .map_err(|e| NdsError::Toolchain(format!("Invalid target spec {}: {e}", target.spec)))?;
+    for (key, value) in settings {
+        println!("{key}: {value}");
     }
+    if target.source != TargetSource::Builtin && !target::is_builtin_spec(&json) {
+        println!("note: differs from the built-in {DEFAULT_TARGET_SPEC} of this cargo-nds version");
+    }
+    Ok(())
 }
 
-/// Builds the nds using `ndstool`.
-/// This will fail if `ndstool` is not within the running directory or in a directory found in $PATH
-pub fn build_nds(config: &NDSConfig, verbose: bool) {
-    let mut command = Command::new("ndstool");
-    let name = get_name(config);
-    let banner_text = format!("{};{};{}", name.0.file_name().unwrap().to_string_lossy(), &config.description, &config.author);
-    command
-        .arg("-c")
-        .arg(config.path_nds())
-        .arg("-9")
-        .arg(config.path_arm9())
-        .arg("-7")
-        .arg(config.path_arm7())
-        .arg("-b")
-        .arg(&config.icon)
-        .arg(banner_text);
+/// Validates the `--target` override, if any. A spec file has to exist, and any
+/// other target has to be known to rustc.
+pub fn check_target(input: &Input) -> Result<(), NdsError> {
+    let Some(target) = &input.target else {
+        return Ok(());
+    };
 
-    // If romfs directory exists, automatically include it
-    let (romfs_path, is_default_romfs) = get_romfs_path(config);
-    if romfs_path.is_dir() {
-        eprintln!("Adding RomFS from {}", romfs_path.display());
-        command.arg("-d")
-        .arg(&romfs_path);
-    } else if !is_default_romfs {
-        eprintln!(
-            "Could not find configured RomFS dir: {}",
-            romfs_path.display()
-        );
-        process::exit(1);
+    if target.ends_with(".json") {
+        if !Path::new(target).is_file() {
+            return Err(NdsError::Toolchain(format!(
+                "Could not find target spec: {}",
+                absolute_path(Path::new(target)).display()
+            )));
+        }
+        return Ok(());
     }
 
-    if verbose {
-        print_command(&command);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(&rustc)
+        .args(["--print", "target-list"])
+        .output()
+        .map_err(|e| NdsError::spawn(&rustc, e))?;
+
+    if !String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .any(|line| line == target)
+    {
+        return Err(NdsError::Toolchain(format!(
+            "Unknown target `{target}`: expected a path to a target spec `.json` file \
+            or a target listed by `rustc --print target-list`"
+        )));
     }
+    Ok(())
+}
 
-    let mut process = command
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .expect("ndstool command failed, most likely due to 'ndstool' not being in $PATH");
+/// Build a `cargo` command with the given `--config` flags.
+fn cargo(config: &[String]) -> Command {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut cmd = Command::new(cargo);
+    cmd.args(config.iter().map(|cfg| format!("--config={cfg}")));
+    cmd
+}
+
+/// Print a command and its environment before running it, with `--verbose`.
+fn print_command(command: &Command) {
+    if log::enabled(log::Level::Verbose) {
+        write_command("Running command:", command);
+    }
+}
 
-    let status = process.wait().unwrap();
+/// Print a command and its environment under a header, as [`print_command`]
+/// and `--dry-run` do.
+pub(crate) fn write_command(header: &str, command: &Command) {
+    let mut cmd_str = vec![command.get_program().to_string_lossy().to_string()];
+    cmd_str.extend(command.get_args().map(|s| s.to_string_lossy().to_string()));
 
-    if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+    // Plain text when redirected, so that it can be copied or parsed as is
+    eprintln!("{}", log::paint(log::BOLD, header));
+    for (k, v) in command.get_envs() {
+        let v = v.map(|v| v.to_string_lossy().to_string());
+        eprintln!(
+            "   {}={} \\",
+            k.to_string_lossy(),
+            v.map_or_else(String::new, |s| shlex::quote(&s).to_string())
+        );
     }
+    eprintln!(
+        "   {}\n",
+        log::paint(log::BOLD, &shlex::join(cmd_str.iter().map(String::as_str)))
+    );
 }
 
-/// Link the generated nds to a ds to execute and test using `dslink`.
-/// This will fail if `dslink` is not within the running directory or in a directory found in $PATH
-pub fn link(config: &NDSConfig, run_args: &Run, verbose: bool) {
-    let mut command = Command::new("dslink");
+/// Run a tool with the terminal as its input and output, so that it prints its
+/// own errors, and fail if it does.
+pub(crate) fn run_tool(command: &mut Command, tool: &str) -> Result<(), NdsError> {
     command
-        .args(run_args.get_dslink_args())
-        .arg(config.path_nds())
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit());
 
-    if verbose {
-        print_command(&command);
-    }
-
-    let status = command.spawn().unwrap().wait().unwrap();
+    print_command(command);
 
+    let status = command.status().map_err(|e| NdsError::spawn(tool, e))?;
     if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+        return Err(NdsError::Failed {
+            tool: tool.to_string(),
+            status,
+            details: None,
+        });
     }
+    Ok(())
 }
 
-/// Read the `RomFS` path from the Cargo manifest. If it's unset, use the default.
-/// The returned boolean is true when the default is used.
-pub fn get_romfs_path(config: &NDSConfig) -> (PathBuf, bool) {
-    let manifest_path = &config.cargo_manifest_path;
-    let manifest_str = std::fs::read_to_string(manifest_path)
-        .unwrap_or_else(|e| panic!("Could not open {}: {e}", manifest_path.display()));
-    let manifest_data: toml::Value =
-        toml::de::from_str(&manifest_str).expect("Could not parse Cargo manifest as TOML");
+/// The BlocksDS install of the Wonderful toolchain on this platform, used when
+/// neither `BLOCKSDS` nor `WONDERFUL_TOOLCHAIN` are set. On Windows, the
+/// toolchain is installed in MSYS2.
+const DEFAULT_BLOCKSDS: &str = if cfg!(windows) {
+    r"C:\msys64\opt\wonderful\thirdparty\blocksds\core"
+} else {
+    "/opt/wonderful/thirdparty/blocksds/core"
+};
 
-    // Find the romfs setting and compute the path
-    let mut is_default = false;
-    let romfs_dir_setting = manifest_data
-        .as_table()
-        .and_then(|table| table.get("package"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("metadata"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("nds"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("romfs"))
-        .and_then(toml::Value::as_str)
-        .unwrap_or_else(|| {
-            is_default = true;
-            "romfs"
-        });
-    let mut romfs_path = manifest_path.clone();
-    romfs_path.pop(); // Pop Cargo.toml
-    romfs_path.push(romfs_dir_setting);
+/// Where to download BlocksDS, for error messages.
+const BLOCKSDS_SETUP_URL: &str = "https://blocksds.github.io/docs/setup/options/";
+
+/// The BlocksDS directory, without checking it: `$BLOCKSDS`, or the BlocksDS
+/// of `$WONDERFUL_TOOLCHAIN`, or the default install location.
+pub fn find_blocksds() -> PathBuf {
+    if let Some(blocksds) = env::var_os("BLOCKSDS") {
+        return PathBuf::from(blocksds);
+    }
+    if let Some(wonderful) = env::var_os("WONDERFUL_TOOLCHAIN") {
+        return Path::new(&wonderful)
+            .join("thirdparty")
+            .join("blocksds")
+            .join("core");
+    }
+    PathBuf::from(DEFAULT_BLOCKSDS)
+}
 
-    (romfs_path, is_default)
+/// The linker specs of the ARM9 in a BlocksDS directory.
+pub fn blocksds_specs(blocksds: &Path) -> PathBuf {
+    blocksds.join("sys").join("crts").join("ds_arm9.specs")
 }
 
+/// Check that `blocksds` is a BlocksDS install, explaining how to fix it if not.
+pub fn check_blocksds(blocksds: &Path) -> Result<(), String> {
+    let source = if env::var_os("BLOCKSDS").is_some() {
+        "$BLOCKSDS"
+    } else if env::var_os("WONDERFUL_TOOLCHAIN").is_some() {
+        "$WONDERFUL_TOOLCHAIN"
+    } else {
+        "the default location"
+    };
 
-/// Read the `RomFS` path from the Cargo manifest. If it's unset, use the default.
-/// The returned boolean is true when the default is used.
-pub fn get_name(config: &NDSConfig) -> (PathBuf, bool) {
-    let manifest_path = &config.cargo_manifest_path;
-    let manifest_str = std::fs::read_to_string(manifest_path)
-        .unwrap_or_else(|e| panic!("Could not open {}: {e}", manifest_path.display()));
-    let manifest_data: toml::Value =
-        toml::de::from_str(&manifest_str).expect("Could not parse Cargo manifest as TOML");
+    let problem = if !blocksds.is_dir() {
+        format!("{} (from {source}) doesn't exist", blocksds.display())
+    } else if !blocksds_specs(blocksds).is_file() {
+        format!(
+            "{} (from {source}) has no sys/crts/ds_arm9.specs",
+            blocksds.display()
+        )
+    } else {
+        return Ok(());
+    };
 
-    // Find the romfs setting and compute the path
-    let mut is_default = false;
-    let name_setting = manifest_data
-        .as_table()
-        .and_then(|table| table.get("package"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("name"))
-        .and_then(toml::Value::as_str)
-        .unwrap_or_else(|| {
-            is_default = true;
-            "No Name"
-        });
-    let mut name = manifest_path.clone();
-    name.pop(); // Pop Cargo.toml
-    name.push(name_setting);
-
-    (name, is_default)
-}
-
-/// Read the `icon` path from the Cargo manifest. If it's unset, use the default.
-/// The returned boolean is true when the default is used.
-pub fn get_icon_path(config: &NDSConfig) -> (PathBuf, bool) {
-    let manifest_path = &config.cargo_manifest_path;
-    let manifest_str = std::fs::read_to_string(manifest_path)
-        .unwrap_or_else(|e| panic!("Could not open {}: {e}", manifest_path.display()));
-    let manifest_data: toml::Value =
-        toml::de::from_str(&manifest_str).expect("Could not parse Cargo manifest as TOML");
-
-    // Find the icon setting and compute the path
-    let mut is_default = false;
-    
-    let icon_setting = manifest_data
-        .as_table()
-        .and_then(|table| table.get("package"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("metadata"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("nds"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("icon"))
-        .and_then(toml::Value::as_str)
-        .unwrap_or_else(|| {
-            is_default = true;
-            "/opt/wonderful/thirdparty/blocksds/core/sys/icon.bmp"
-        });
-    let mut icon_path = manifest_path.clone();
-    icon_path.pop(); // Pop Cargo.toml
-    icon_path.push(icon_setting);
+    Err(format!(
+        "Could not find BlocksDS: {problem}. Install BlocksDS ({BLOCKSDS_SETUP_URL}) and set \
+        the BLOCKSDS environment variable to its directory, e.g. {DEFAULT_BLOCKSDS}"
+    ))
+}
 
-    (icon_path, is_default)
+/// The BlocksDS directory (see [`find_blocksds`]), resolved once. Fails if it
+/// isn't a BlocksDS install.
+pub fn blocksds_root() -> Result<PathBuf, NdsError> {
+    static ROOT: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+    ROOT.get_or_init(|| {
+        let blocksds = find_blocksds();
+        check_blocksds(&blocksds).map(|()| blocksds)
+    })
+    .clone()
+    .map_err(NdsError::Toolchain)
 }
 
-#[derive(Default)]
-pub struct NDSConfig {
-    name: String,
-    author: String,
-    description: String,
-    icon: String,
-    target_path: PathBuf,
-    cargo_manifest_path: PathBuf,
+/// The default BlocksDS banner icon.
+fn blocksds_icon(blocksds: &Path) -> PathBuf {
+    blocksds.join("sys").join("icon.bmp")
 }
 
-impl NDSConfig {
-    pub fn path_nds(&self) -> PathBuf {
-        self.target_path.with_extension("").with_extension("nds")
+/// Make a path absolute for display in user-facing messages, so it's always clear
+/// where a file is. This doesn't resolve symlinks or require the path to exist.
+pub(crate) fn absolute_path(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
     }
-    pub fn path_arm9(&self) -> PathBuf {
-        self.target_path.with_extension("").with_extension("arm9.elf")
+
+    env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Finds the sysroot path of the current toolchain, from `SYSROOT` or by asking
+/// the `RUSTC` compiler.
+pub fn find_sysroot() -> Result<PathBuf, String> {
+    if let Some(sysroot) = env::var_os("SYSROOT") {
+        return Ok(PathBuf::from(sysroot));
     }
-    pub fn path_arm7(&self) -> PathBuf {
-        let arm7 =self.target_path.with_extension("").with_extension("arm7.elf");
-        if arm7.exists() {
-            return arm7;
-        }
-        let blocksds= env::var("BLOCKSDS").unwrap_or("/opt/wonderful/thirdparty/blocksds/core".to_owned());
-        PathBuf::from(format!("{}/sys/default_arm7/arm7.elf",blocksds))
+
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc"));
+    let output = Command::new(&rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .map_err(|e| {
+            format!(
+                "could not run `{} --print sysroot`: {e}",
+                rustc.to_string_lossy()
+            )
+        })?;
+    if !output.status.success() {
+        return Err(format!(
+            "`{} --print sysroot` failed ({}): {}",
+            rustc.to_string_lossy(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
+
+    Ok(sysroot_from_output(output.stdout))
 }
 
-#[derive(Ord, PartialOrd, PartialEq, Eq, Debug)]
-pub struct CommitDate {
-    year: i32,
+/// The sysroot printed by rustc, without requiring it to be valid UTF-8.
+fn sysroot_from_output(mut stdout: Vec<u8>) -> PathBuf {
+    while stdout.last().is_some_and(u8::is_ascii_whitespace) {
+        stdout.pop();
+    }
+
+    #[cfg(unix)]
+    let sysroot = {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(stdout)
+    };
+    #[cfg(not(unix))]
+    let sysroot = OsString::from(String::from_utf8_lossy(&stdout).into_owned());
+
+    PathBuf::from(sysroot)
+}
+
+/// Checks the current rust version and channel meet the minimum requirement.
+pub fn check_rust_version() -> Result<(), NdsError> {
+    verify_rust_version().map_err(NdsError::Toolchain)
+}
+
+/// How to get a nightly toolchain for cargo-nds.
+const NIGHTLY_HELP: &str = "Please run `rustup override set nightly` to use nightly in the \
+    current directory, or use `cargo +nightly nds` to use it for a \
+    single invocation.";
+
+/// Checks the current rust version and channel, returning an explanation of how
+/// to fix it if the minimum requirement is not met.
+pub fn verify_rust_version() -> Result<(), String> {
+    check_version_meta(rustc_version::version_meta())
+}
+
+/// Checks the version of rustc reported by `rustc -vV`, which may have failed to
+/// run if no toolchain is installed.
+fn check_version_meta(
+    rustc_version: rustc_version::Result<rustc_version::VersionMeta>,
+) -> Result<(), String> {
+    let rustc_version = rustc_version.map_err(|e| {
+        format!(
+            "cargo-nds could not get the version of rustc: {e}\n\
+            Make sure a nightly toolchain is installed with `rustup toolchain install nightly`.\n\
+            {NIGHTLY_HELP}"
+        )
+    })?;
+
+    if rustc_version.channel > Channel::Nightly {
+        return Err(format!(
+            "cargo-nds requires a nightly rustc version.\n{NIGHTLY_HELP}"
+        ));
+    }
+
+    let old_version = MINIMUM_RUSTC_VERSION
+        > Version {
+            // Remove `-nightly` pre-release tag for comparison.
+            pre: semver::Prerelease::EMPTY,
+            ..rustc_version.semver.clone()
+        };
+
+    let old_commit = match rustc_version.commit_date {
+        None => false,
+        Some(date) => {
+            let date = CommitDate::parse(&date).ok_or_else(|| {
+                format!("cargo-nds could not parse the rustc commit date `{date}`.\n{NIGHTLY_HELP}")
+            })?;
+            MINIMUM_COMMIT_DATE > date
+        }
+    };
+
+    if old_version || old_commit {
+        return Err(format!(
+            "cargo-nds requires rustc nightly version >= {MINIMUM_COMMIT_DATE}\n\
+            Please run `rustup update nightly` to upgrade your nightly version"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that the cargo profile of the build doesn't ask for `panic = "unwind"`,
+/// since the nds target only supports aborting. Returns an explanation if it
+/// does, instead of letting the build fail later in codegen.
+///
+/// The profile is the one selected by `--release` or `--profile` (`dev`
+/// otherwise), and its `panic` setting is looked up along its `inherits` chain
+/// in `--config` overrides, `CARGO_PROFILE_<name>_PANIC` environment variables
+/// and the workspace manifest, in that order.
+pub fn check_panic_strategy(input: &Input) -> Result<(), NdsError> {
+    if !input.cmd.should_compile() {
+        return Ok(());
+    }
+
+    let cargo_args = input.cmd.cargo_args();
+    let profile = selected_profile(&cargo_args);
+    // Cargo ignores `panic` for these
+    if profile == "test" || profile == "bench" {
+        return Ok(());
+    }
+
+    // Later `--config` values take precedence
+    let mut layers: Vec<toml::Value> = input
+        .config
+        .iter()
+        .rev()
+        .filter_map(|config| toml::from_str::<toml::Value>(config).ok())
+        .filter_map(|config| config.get("profile").cloned())
+        .collect();
+    layers.push(env_profiles());
+    if let Some(manifest) = fs::read_to_string(workspace_manifest(&input.cargo_flags))
+        .ok()
+        .and_then(|manifest| toml::from_str::<toml::Value>(&manifest).ok())
+    {
+        layers.extend(manifest.get("profile").cloned());
+    }
+
+    if let Some((source, panic)) = profile_panic(&layers, profile) {
+        if panic == "unwind" {
+            let from = if source == profile {
+                String::new()
+            } else {
+                format!(" (inherited from `{source}`)")
+            };
+            return Err(NdsError::Config(format!(
+                "profile `{profile}` sets `panic = \"unwind\"`{from}, but the nds target only \
+                supports `panic = \"abort\"` (see `panic-strategy` in {DEFAULT_TARGET_SPEC})\n\
+                Remove the `panic` setting from the profile or set it to \"abort\""
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The cargo profile selected by `--release` or `--profile`, `dev` otherwise.
+fn selected_profile(args: &[String]) -> &str {
+    if args.iter().any(|arg| arg == "--release" || arg == "-r") {
+        return "release";
+    }
+    command::flag_value(args, "--profile").unwrap_or("dev")
+}
+
+/// The `panic` and `inherits` settings of profiles set by
+/// `CARGO_PROFILE_<name>_<setting>` environment variables, as a `[profile]`
+/// table.
+fn env_profiles() -> toml::Value {
+    let mut profiles = toml::Table::new();
+    for (key, value) in env::vars() {
+        let Some(key) = key.strip_prefix("CARGO_PROFILE_") else {
+            continue;
+        };
+        for (suffix, setting) in [("_PANIC", "panic"), ("_INHERITS", "inherits")] {
+            if let Some(profile) = key.strip_suffix(suffix) {
+                let profile = profiles
+                    .entry(profile.to_lowercase().replace('_', "-"))
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let Some(profile) = profile.as_table_mut() {
+                    profile.insert(setting.to_string(), toml::Value::String(value.clone()));
+                }
+            }
+        }
+    }
+    toml::Value::Table(profiles)
+}
+
+/// The manifest of the workspace of the package cargo builds, which is the one
+/// whose profiles cargo uses: the closest one with a `[workspace]` table, or
+/// else the package's own.
+fn workspace_manifest(cargo_flags: &CargoFlags) -> PathBuf {
+    let package_manifest = package_dir(cargo_flags).join("Cargo.toml");
+    package_manifest
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| {
+            fs::read_to_string(manifest)
+                .ok()
+                .and_then(|manifest| toml::from_str::<toml::Value>(&manifest).ok())
+                .is_some_and(|manifest| manifest.get("workspace").is_some())
+        })
+        .unwrap_or(package_manifest)
+}
+
+/// Find the `panic` setting of `profile` in `[profile]` tables, given from the
+/// highest precedence, following the `inherits` chain. Returns the profile the
+/// setting comes from, and its value.
+fn profile_panic(layers: &[toml::Value], profile: &str) -> Option<(String, String)> {
+    let setting = |name: &str, key: &str| {
+        layers
+            .iter()
+            .find_map(|layer| layer.get(name)?.get(key)?.as_str())
+            .map(str::to_string)
+    };
+
+    let mut name = profile.to_string();
+    let mut seen = Vec::new();
+    while !seen.contains(&name) {
+        if let Some(panic) = setting(&name, "panic") {
+            return Some((name, panic));
+        }
+        let inherits = match (setting(&name, "inherits"), name.as_str()) {
+            (Some(inherits), _) => inherits,
+            (None, "test") => String::from("dev"),
+            (None, "bench") => String::from("release"),
+            (None, _) => return None,
+        };
+        seen.push(std::mem::replace(&mut name, inherits));
+    }
+    None
+}
+
+/// Cargo options which select a single target to build, and the kind of target
+/// they select by name (for those which take a name).
+const TARGET_SELECTION_FLAGS: &[(&str, Option<&str>)] = &[
+    ("--bin", Some("bin")),
+    ("--example", Some("example")),
+    ("--test", Some("test")),
+    ("--bench", Some("bench")),
+    ("--lib", None),
+    ("--doc", None),
+];
+
+/// A target selected in cargo's arguments, e.g. `--bin foo`.
+#[derive(Debug, PartialEq)]
+struct TargetSelection<'a> {
+    flag: &'a str,
+    kind: Option<&'a str>,
+    name: Option<&'a str>,
+}
+
+impl TargetSelection<'_> {
+    /// Whether the artifact was built for this target.
+    fn matches(&self, artifact: &Artifact) -> bool {
+        match (self.kind, self.name) {
+            (Some(kind), Some(name)) => {
+                artifact.target.name == name && artifact.target.kind.iter().any(|k| k == kind)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TargetSelection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "`{} {name}`", self.flag),
+            None => write!(f, "`{}`", self.flag),
+        }
+    }
+}
+
+/// Parse the [`TARGET_SELECTION_FLAGS`] in cargo's arguments.
+fn target_selections(cargo_args: &[String]) -> Vec<TargetSelection<'_>> {
+    let mut selections = Vec::new();
+    let mut args = cargo_args.iter();
+
+    while let Some(arg) = args.next() {
+        for &(flag, kind) in TARGET_SELECTION_FLAGS {
+            let name = if arg == flag {
+                // Flags without a kind don't take a value
+                match kind {
+                    Some(_) => args.next().map(String::as_str),
+                    None => None,
+                }
+            } else if let Some(name) = arg.strip_prefix(flag).and_then(|s| s.strip_prefix('=')) {
+                Some(name)
+            } else {
+                continue;
+            };
+
+            selections.push(TargetSelection { flag, kind, name });
+            break;
+        }
+    }
+
+    selections
+}
+
+/// Find the executable built by cargo. If a target was selected by name (such
+/// as with `--bin NAME`), its executable is used. Otherwise, if several were
+/// built, one of them must have been selected, rather than silently picking one.
+fn find_executable<'a>(
+    messages: &'a [Message],
+    cargo_args: &[String],
+) -> Result<&'a Artifact, String> {
+    let mut executables: Vec<&Artifact> = Vec::new();
+    for message in messages {
+        if let Message::CompilerArtifact(art) = message {
+            if art.executable.is_some()
+                && !executables
+                    .iter()
+                    .any(|exe| exe.executable == art.executable)
+            {
+                executables.push(art);
+            }
+        }
+    }
+
+    let selections = target_selections(cargo_args);
+    let named: Vec<&TargetSelection> = selections.iter().filter(|sel| sel.name.is_some()).collect();
+
+    if !named.is_empty() {
+        executables.retain(|exe| named.iter().any(|sel| sel.matches(exe)));
+        if executables.is_empty() {
+            let named: Vec<String> = named.iter().map(ToString::to_string).collect();
+            return Err(format!(
+                "{} did not produce an executable",
+                named.join(", ")
+            ));
+        }
+    }
+
+    match executables.as_slice() {
+        [] => Err(String::from(
+            "No executable found from build command output!",
+        )),
+        [exe] => Ok(exe),
+        _ => {
+            let mut msg = String::from(
+                "Multiple executables were built, select one with `--bin NAME` or \
+                `--example NAME` (or `--lib`/`--test NAME` for tests):",
+            );
+            for exe in executables {
+                msg += &format!("\n    {} ({})", exe.target.name, exe.target.kind.join(", "));
+            }
+            Err(msg)
+        }
+    }
+}
+
+/// The icon used when `nds.toml` doesn't set one: `icon.bmp` or `icon.png` in
+/// the package directory, or the BlocksDS default icon.
+pub(crate) fn default_icon(package_dir: &Path, blocksds: &Path) -> String {
+    ["icon.bmp", "icon.png"]
+        .iter()
+        .map(|name| package_dir.join(name))
+        .find(|icon| icon.exists())
+        .map(|icon| icon.to_string_lossy().into_owned())
+        .unwrap_or_else(|| blocksds_icon(blocksds).to_string_lossy().into_owned())
+}
+
+/// Parses messages returned by "build" cargo commands (such as `cargo nds build` or `cargo nds run`).
+/// The returned [`CTRConfig`] is then used for further building in and execution
+/// in [`build_nds`], and [`link`].
+pub fn get_metadata(
+    messages: &[Message],
+    cargo_args: &[String],
+    cargo_flags: &CargoFlags,
+) -> Result<NDSConfig, NdsError> {
+    let artifact = find_executable(messages, cargo_args)
+        .map_err(NdsError::Executable)?
+        .clone();
+
+    // Get the metadata of the workspace the artifact's package belongs to, which
+    // may not be the one of the current directory
+    let metadata = cargo_flags
+        .metadata_command()
+        .manifest_path(&artifact.manifest_path)
+        .exec()?;
+    let package = metadata
+        .packages
+        .iter()
+        .find(|package| package.id == artifact.package_id)
+        .ok_or_else(|| {
+            NdsError::Input(format!(
+                "Could not find the package of {} in the metadata of {}",
+                artifact.target.name, artifact.manifest_path
+            ))
+        })?;
+
+    // for now assume a single "kind" since we only support one output artifact
+    let name = match artifact.target.kind[0].as_ref() {
+        "bin" | "lib" | "rlib" | "dylib" if artifact.target.test => {
+            format!("{} tests", artifact.target.name)
+        }
+        "example" => {
+            format!("{} - {} example", artifact.target.name, package.name)
+        }
+        _ => artifact.target.name,
+    };
+
+    package_nds_config(package, name, artifact.executable.unwrap().into())
+}
+
+/// The [`NDSConfig`] of an executable of `package`, named `name` in the banner.
+pub(crate) fn package_nds_config(
+    package: &Package,
+    name: String,
+    executable: PathBuf,
+) -> Result<NDSConfig, NdsError> {
+    let icon = default_icon(
+        package.manifest_path.parent().unwrap().as_std_path(),
+        &blocksds_root()?,
+    );
+
+    let author = match package.authors.as_slice() {
+        [name, ..] => name.clone(),
+        [] => String::from("Unspecified Author"), // as standard with the devkitPRO toolchain
+    };
+    let authors = package.authors.clone();
+
+    Ok(NDSConfig {
+        name,
+        author,
+        authors,
+        description: package
+            .description
+            .clone()
+            .unwrap_or_else(|| String::from("Homebrew Application")),
+        icon,
+        target_path: executable,
+        cargo_manifest_path: package.manifest_path.clone().into(),
+        version: Some(package.version.clone()),
+        dsi: false,
+        versioned_copy: false,
+        output: None,
+        arm7: None,
+    })
+}
+
+/// File name of the [`NDSConfig`] of the last ROM built, in the target dir, so
+/// that commands given `--no-build` can use the ROM without running cargo.
+const BUILD_CACHE: &str = "nds-build.json";
+
+/// Save `config` as the last ROM built, see [`load_build_cache`].
+pub fn write_build_cache(config: &NDSConfig, cargo_flags: &CargoFlags) {
+    let path = target_dir(cargo_flags).join(BUILD_CACHE);
+    let written = fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| fs::write(&path, serde_json::to_string_pretty(config).unwrap()));
+    // Only `--no-build` needs it, so this isn't worth failing the build for
+    if let Err(e) = written {
+        eprintln!("warning: could not write {}: {e}", path.display());
+    }
+}
+
+/// The [`NDSConfig`] of the last ROM built, as saved by [`write_build_cache`].
+/// Fails if nothing was built yet, if it was built from another executable
+/// than `cargo_args` select, if the ROM was removed since, or if the executable
+/// was rebuilt since (e.g. by cargo itself), so that the ROM may be out of
+/// date.
+pub fn load_build_cache(
+    cargo_flags: &CargoFlags,
+    cargo_args: &[String],
+) -> Result<NDSConfig, String> {
+    let path = target_dir(cargo_flags).join(BUILD_CACHE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err("No ROM was built yet: run `cargo nds build` first".to_string());
+        }
+        Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+    };
+    let config: NDSConfig = serde_json::from_str(&contents)
+        .map_err(|e| format!("Could not parse {}: {e}", path.display()))?;
+
+    if let Some(mismatch) = cached_build_mismatch(&config, cargo_flags, cargo_args) {
+        return Err(format!(
+            "The last build ({}) was not built {mismatch}. Run `cargo nds build` with the same options first",
+            config.path_nds().display()
+        ));
+    }
+
+    if !config.path_nds().is_file() {
+        return Err(format!(
+            "The ROM of the last build does not exist anymore: {}. Run `cargo nds build` first",
+            config.path_nds().display()
+        ));
+    }
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    if let (Ok(cached), Ok(built)) = (modified(&path), modified(&config.target_path)) {
+        if built > cached {
+            return Err(format!(
+                "{} was rebuilt since the last ROM build. Run `cargo nds build` first",
+                config.target_path.display()
+            ));
+        }
+    }
+
+    Ok(config)
+}
+
+/// How the executable of the last build differs from the one `cargo_args`
+/// select: its profile, `--bin` or `--example` and package.
+fn cached_build_mismatch(
+    config: &NDSConfig,
+    cargo_flags: &CargoFlags,
+    cargo_args: &[String],
+) -> Option<String> {
+    let executable = config.target_path.with_extension("");
+    let name = executable.file_stem().unwrap_or_default().to_string_lossy();
+    let mut dir = executable.parent().unwrap_or(Path::new(""));
+    let dir_name = |dir: &Path| {
+        dir.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let is_example = dir_name(dir) == "examples";
+    if is_example {
+        dir = dir.parent().unwrap_or(dir);
+    }
+    match command::flag_value(cargo_args, "--example") {
+        Some(example) if !is_example || example != name => {
+            return Some(format!("for the `{example}` example"))
+        }
+        None if is_example => {
+            return Some(format!("without `--example`, but as the `{name}` example"))
+        }
+        _ => {}
+    }
+    if let Some(bin) = command::flag_value(cargo_args, "--bin") {
+        if bin != name {
+            return Some(format!("for the `{bin}` executable"));
+        }
+    }
+
+    let profile = command::profile_dir(cargo_args).unwrap_or("debug");
+    if dir_name(dir) != profile {
+        return Some(format!(
+            "with the `{profile}` profile, but the `{}` one",
+            dir_name(dir)
+        ));
+    }
+
+    // The package of `--package`, or else the one in the current directory
+    let manifest = |path: &Path| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|manifest| toml::from_str::<toml::Value>(&manifest).ok())
+    };
+    let package_name =
+        |manifest: toml::Value| Some(manifest.get("package")?.get("name")?.as_str()?.to_string());
+    let cached_package = manifest(&config.cargo_manifest_path).and_then(package_name);
+    let package = match command::flag_value(cargo_args, "--package")
+        .or_else(|| command::flag_value(cargo_args, "-p"))
+    {
+        Some(package) => Some(package.to_string()),
+        None => manifest(&package_dir(cargo_flags).join("Cargo.toml")).and_then(package_name),
+    };
+    match (package, cached_package) {
+        (Some(package), Some(cached)) if package != cached => {
+            Some(format!("from the `{package}` package"))
+        }
+        _ => None,
+    }
+}
+
+/// What [`build_nds`] built the ROM from, printed by `--emit-build-json`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BuildOutput {
+    /// The built `.nds` or `.dsi` file.
+    pub nds: PathBuf,
+    pub arm9: PathBuf,
+    pub arm7: PathBuf,
+    /// The DS icon, unless a prebuilt banner was used.
+    pub icon: Option<PathBuf>,
+    /// The banner binary, if one was configured or built for an animated icon.
+    pub banner: Option<PathBuf>,
+    /// The English banner text.
+    pub banner_text: BannerText,
+    /// The directory added as the RomFS, if any.
+    pub romfs: Option<PathBuf>,
+    /// The copy of the ROM named after the package version, if one was written.
+    pub versioned: Option<PathBuf>,
+}
+
+/// Builds the nds using `ndstool`.
+/// This will fail if `ndstool` is not within the running directory or in a directory found in $PATH
+pub fn build_nds(config: &NDSConfig) -> Result<BuildOutput, NdsError> {
+    build_nds_with_assets(config, Prepared::default())
+}
+
+/// [`build_nds`], with the RomFS and icons already `prepared` (e.g. while
+/// cargo was building, see [`prepare::start`]). Those which weren't are
+/// prepared first.
+pub fn build_nds_with_assets(
+    config: &NDSConfig,
+    mut prepared: Prepared,
+) -> Result<BuildOutput, NdsError> {
+    let nds_config = load_config(config)?;
+
+    let default_banner = default_banner_text(&get_name(config)?, config, &nds_config);
+    let banner_text = banner_names(config, &nds_config)?.resolve(&default_banner);
+    if let Some(dir) = config.path_nds().parent() {
+        fs::create_dir_all(dir).map_err(|e| NdsError::Write(dir.to_path_buf(), e))?;
+    }
+
+    check_executables(config).map_err(NdsError::Executable)?;
+
+    let (icon, banner) = match &nds_config.icons.banner {
+        Some(banner) => {
+            let banner = config.cargo_manifest_path.with_file_name(banner);
+            if !banner.is_file() {
+                return Err(NdsError::Input(format!(
+                    "Could not find configured banner: {}",
+                    absolute_path(&banner).display()
+                )));
+            }
+            (None, Some(banner))
+        }
+        None => {
+            let icons = match prepared.icons.take() {
+                Some(icons) => icons,
+                None => get_icons(config, &nds_config)?,
+            };
+            let banner = if icons.animated.is_empty() {
+                None
+            } else {
+                Some(build_animated_banner(
+                    config,
+                    &nds_config,
+                    &banner_text,
+                    &icons,
+                )?)
+            };
+            (Some(icons.ds), banner)
+        }
+    };
+
+    let include_dirs = get_include_dirs(config, &nds_config)?;
+
+    // If romfs directory exists, automatically include it
+    let romfs = match prepared.romfs {
+        Some(romfs) => Some(romfs),
+        None => get_romfs_path(config, &nds_config, &include_dirs)?,
+    };
+    if let Some(romfs_path) = &romfs {
+        info!("Adding RomFS from {}", absolute_path(romfs_path).display());
+    }
+
+    let ndstool_banner = match (&banner, &icon) {
+        (Some(banner), _) => Banner::File(banner),
+        (None, Some(icon)) => Banner::Icon(icon, &banner_text),
+        (None, None) => unreachable!("there is always a banner or an icon"),
+    };
+    let mut command = ndstool_command(config, &nds_config, ndstool_banner, romfs.as_deref())?;
+
+    print_command(&command);
+
+    let status = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .map_err(|e| NdsError::spawn("ndstool", e))?;
+
+    let [_, english, ..] = banner_text;
+    let mut output = BuildOutput {
+        nds: absolute_path(&config.path_nds()),
+        arm9: absolute_path(&config.path_arm9()),
+        arm7: absolute_path(&config.path_arm7()),
+        icon: icon.map(|icon| absolute_path(&icon)),
+        banner: banner.map(|banner| absolute_path(&banner)),
+        banner_text: english,
+        romfs: romfs.map(|romfs| absolute_path(&romfs)),
+        versioned: None,
+    };
+
+    // ndstool's own error was already printed, but not what it was built from
+    if !status.success() {
+        return Err(NdsError::Failed {
+            tool: String::from("ndstool"),
+            status,
+            details: Some(ndstool_failure(&command, status, &output)),
+        });
+    }
+
+    // ndstool may exit successfully without writing the whole file, e.g. if the disk is full
+    check_nds_output(&config.path_nds()).map_err(NdsError::Input)?;
+
+    if nds_config.header.secure_area == SecureArea::Encrypted {
+        encrypt_secure_area(config)?;
+    }
+
+    if config.versioned_copy {
+        output.versioned = Some(write_versioned_copy(config)?);
+    }
+    Ok(output)
+}
+
+/// Check that the ARM9 and ARM7 executables can be read, so that a wrong path
+/// is reported with where it comes from, rather than as an ndstool error.
+fn check_executables(config: &NDSConfig) -> Result<(), String> {
+    let arm9 = config.path_arm9();
+    fs::File::open(&arm9).map_err(|e| {
+        format!(
+            "Could not read the ARM9 executable {}: {e}\n\
+            It is expected next to the executable built by cargo, {}",
+            absolute_path(&arm9).display(),
+            absolute_path(&config.target_path).display()
+        )
+    })?;
+
+    let arm7 = config.path_arm7();
+    fs::File::open(&arm7).map_err(|e| {
+        let origin = if config.arm7.is_some() {
+            format!("It was configured with `arm7` or `arm7_crate` in {CONFIG_FILE_NAME}")
+        } else {
+            format!(
+                "It is the default ARM7 of BlocksDS, since there is no `.arm7.elf` next to {}. \
+                Check that BlocksDS is installed, or set $BLOCKSDS",
+                absolute_path(&config.target_path).display()
+            )
+        };
+        format!(
+            "Could not read the ARM7 executable {}: {e}\n{origin}",
+            absolute_path(&arm7).display()
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Describe a failed ndstool command: its arguments, the inputs of the ROM,
+/// and the usual causes.
+fn ndstool_failure(command: &Command, status: ExitStatus, output: &BuildOutput) -> String {
+    let mut args = vec![command.get_program().to_string_lossy().into_owned()];
+    args.extend(
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned()),
+    );
+    let optional = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .map_or_else(|| "none".to_string(), |path| path.display().to_string())
+    };
+
+    format!(
+        "ndstool failed ({status}) to build {}\n\
+        \x20 command:     {}\n\
+        \x20 ARM9:        {}\n\
+        \x20 ARM7:        {}\n\
+        \x20 icon:        {}\n\
+        \x20 banner:      {}\n\
+        \x20 banner text: {}\n\
+        \x20 RomFS:       {}\n\
+        hint: the usual causes are an icon which isn't a 32x32 BMP with a 16 color palette, \
+        and a missing or invalid ARM7 executable (see `arm7` and `arm7_crate` in {CONFIG_FILE_NAME})",
+        output.nds.display(),
+        shlex::join(args.iter().map(String::as_str)),
+        output.arm9.display(),
+        output.arm7.display(),
+        optional(&output.icon),
+        optional(&output.banner),
+        output.banner_text.to_ndstool_arg(),
+        optional(&output.romfs),
+    )
+}
+
+/// The path of the ROM with the package version appended to its name, e.g.
+/// `game-1.2.0.nds` for `game.nds`.
+fn versioned_path(path_nds: &Path, version: &Version) -> PathBuf {
+    let stem = path_nds.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{stem}-{version}");
+    if let Some(extension) = path_nds.extension() {
+        file_name = format!("{file_name}.{}", extension.to_string_lossy());
+    }
+    path_nds.with_file_name(file_name)
+}
+
+/// Copy the built ROM to [`versioned_path`], keeping the original for
+/// `cargo nds run`, and return the absolute path of the copy.
+fn write_versioned_copy(config: &NDSConfig) -> Result<PathBuf, NdsError> {
+    let Some(version) = &config.version else {
+        return Err(NdsError::Input(String::from(
+            "Could not write a versioned copy of the ROM: the package has no version",
+        )));
+    };
+
+    let path = absolute_path(&versioned_path(&config.path_nds(), version));
+    fs::copy(config.path_nds(), &path).map_err(|e| NdsError::Write(path.clone(), e))?;
+    info!("Copied the ROM to {}", path.display());
+    Ok(path)
+}
+
+/// Encrypt the secure area of the built `.nds` file in place.
+fn encrypt_secure_area(config: &NDSConfig) -> Result<(), NdsError> {
+    run_tool(&mut encrypt_command(config), "ndstool")
+}
+
+/// The ndstool command encrypting the secure area of the ROM.
+pub(crate) fn encrypt_command(config: &NDSConfig) -> Command {
+    let mut command = Command::new("ndstool");
+    command.arg("-se").arg(config.path_nds());
+    command
+}
+
+/// Add the ndstool arguments for the ROM header: the codes, title and version,
+/// the DSi header and the logo.
+pub(crate) fn add_header_args(
+    command: &mut Command,
+    config: &NDSConfig,
+    nds_config: &Config,
+) -> Result<(), NdsError> {
+    let rom_version = nds_config
+        .rom_version
+        .map_or_else(|| header_version(config.version.as_ref()), Ok)
+        .map_err(NdsError::Config)?;
+    // ndstool only takes the version after the codes and title, so pass its
+    // defaults for any which aren't set
+    command
+        .arg("-g")
+        .arg(nds_config.game_code.as_deref().unwrap_or(DEFAULT_GAME_CODE))
+        .arg(
+            nds_config
+                .maker_code
+                .as_deref()
+                .unwrap_or(DEFAULT_MAKER_CODE),
+        )
+        .arg(header_title(&config.name))
+        .arg(rom_version.to_string());
+
+    // DSi ROMs have an extended header, which needs the ARM9 binary at 0x4000.
+    // BlocksDS uses the same specs and ARM7 binary for the DS and DSi, since
+    // they detect the console at runtime.
+    let header_size = if config.dsi {
+        // `dsi = true` is checked when loading nds.toml, but not `--dsi`
+        nds_config.header.validate_dsi().map_err(NdsError::Config)?;
+        command.arg("-u").arg(DSI_TITLE_ID_HIGH);
+        Some(HeaderConfig::SECURE_AREA_OFFSET)
+    } else {
+        nds_config.header.header_size()
+    };
+    if let Some(size) = header_size {
+        command.arg("-h").arg(format!("{size:#x}"));
+    }
+    if let Some(logo) = &nds_config.header.logo {
+        command
+            .arg("-o")
+            .arg(config.cargo_manifest_path.with_file_name(logo));
+    }
+    Ok(())
+}
+
+/// The banner text of each language from `name` in the package config,
+/// falling back to the `banner_file`, if any.
+pub(crate) fn banner_names(
+    config: &NDSConfig,
+    nds_config: &Config,
+) -> Result<Languages<BannerText>, NdsError> {
+    let Some(banner_file) = &nds_config.banner_file else {
+        return Ok(nds_config.name.clone());
+    };
+
+    let path = config.cargo_manifest_path.with_file_name(banner_file);
+    let names = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| parse_banner_file(&contents))
+        .map_err(|e| {
+            NdsError::Input(format!(
+                "Could not load banner_file {}: {e}",
+                absolute_path(&path).display()
+            ))
+        })?;
+    Ok(nds_config.name.or(&names))
+}
+
+/// What ndstool builds the banner of the ROM from.
+pub(crate) enum Banner<'a> {
+    /// A whole banner, from `icons.banner` or built from the animated icon.
+    File(&'a Path),
+    /// The DS icon and the text for each language.
+    Icon(&'a Path, &'a [BannerText; LANGUAGE_COUNT]),
+}
+
+/// The ndstool command building the ROM of `config` with the given banner and
+/// RomFS directory, along with the header settings and `ndstool_args` of
+/// `nds.toml`.
+pub(crate) fn ndstool_command(
+    config: &NDSConfig,
+    nds_config: &Config,
+    banner: Banner,
+    romfs: Option<&Path>,
+) -> Result<Command, NdsError> {
+    let mut command = Command::new("ndstool");
+    command
+        .arg("-c")
+        .arg(config.path_nds())
+        .arg("-9")
+        .arg(config.path_arm9())
+        .arg("-7")
+        .arg(config.path_arm7());
+
+    match banner {
+        Banner::File(banner) => {
+            command.arg("-t").arg(banner);
+        }
+        Banner::Icon(icon, banner_text) => {
+            command
+                .arg("-b")
+                .arg(icon)
+                // `-b` sets the text for every language, using the English text
+                .arg(banner_text[1].to_ndstool_arg());
+
+            // Languages which were explicitly localized get their own text, in banner order
+            for (lang, text) in nds_config.name.as_array().into_iter().enumerate() {
+                if lang != 1 && text.is_some() {
+                    command
+                        .arg(format!("-bt{lang}"))
+                        .arg(banner_text[lang].to_ndstool_arg());
+                }
+            }
+        }
+    }
+
+    add_header_args(&mut command, config, nds_config)?;
+
+    if let Some(romfs) = romfs {
+        command.arg("-d").arg(romfs);
+    }
+
+    command.args(extra_ndstool_args(&nds_config.ndstool_args));
+    Ok(command)
+}
+
+/// Build the whole banner from the animated icon, since ndstool can't, and
+/// return where it was written.
+fn build_animated_banner(
+    config: &NDSConfig,
+    nds_config: &Config,
+    banner_text: &[BannerText; LANGUAGE_COUNT],
+    icons: &Icons,
+) -> Result<PathBuf, NdsError> {
+    let path = config.path_banner();
+    let sequence = nds_config.icons.animation_sequence();
+    let result = banner::build_dsi_banner(&icons.ds, &icons.animated, &sequence, banner_text)
+        .and_then(|banner| {
+            fs::write(&path, banner).map_err(|e| format!("could not write {}: {e}", path.display()))
+        });
+    result.map_err(|e| NdsError::Input(format!("Could not build the animated banner: {e}")))?;
+    Ok(path)
+}
+
+/// ndstool flags which cargo-nds always sets, and which take a value.
+const MANDATORY_NDSTOOL_FLAGS: [&str; 3] = ["-c", "-9", "-7"];
+
+/// The `ndstool_args` from `nds.toml`, without any of the
+/// [`MANDATORY_NDSTOOL_FLAGS`] (and their values), which are warned about.
+pub(crate) fn extra_ndstool_args(args: &[String]) -> Vec<String> {
+    let mut extra = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if MANDATORY_NDSTOOL_FLAGS.contains(&arg.as_str()) {
+            let value = args.next().map(String::as_str).unwrap_or_default();
+            eprintln!(
+                "warning: ignoring `{arg} {value}` in ndstool_args, since cargo-nds sets {arg} itself"
+            );
+        } else {
+            extra.push(arg.clone());
+        }
+    }
+
+    extra
+}
+
+/// The game code ndstool uses for homebrew.
+const DEFAULT_GAME_CODE: &str = "####";
+/// The maker code ndstool uses for homebrew.
+const DEFAULT_MAKER_CODE: &str = "00";
+/// The high half of the title ID of DSi-enhanced homebrew, in the DSi header.
+const DSI_TITLE_ID_HIGH: &str = "00030004";
+/// Length of the title in the header (not the banner title).
+const HEADER_TITLE_LENGTH: usize = 12;
+
+/// The ROM version in the header for a package version: the major version in
+/// the high nibble and the minor version in the low one, e.g. 0x12 for 1.2.x.
+/// Versions which don't fit are an error, since they'd make the ROM version go
+/// backwards.
+pub fn header_version(version: Option<&Version>) -> Result<u8, String> {
+    let Some(version) = version else {
+        return Ok(0);
+    };
+
+    if version.major > 0xf || version.minor > 0xf {
+        return Err(format!(
+            "The package version {version} doesn't fit in the ROM header, which only has room \
+            for major and minor versions up to 15. Set `rom_version` in {CONFIG_FILE_NAME} instead"
+        ));
+    }
+
+    Ok(((version.major as u8) << 4) | version.minor as u8)
+}
+
+/// The title in the header, which is limited to 12 uppercase ASCII characters.
+fn header_title(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9') => c,
+            _ => ' ',
+        })
+        .take(HEADER_TITLE_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// The smallest possible `.nds` file: the header, followed by the banner.
+const MINIMUM_NDS_SIZE: u64 = 0x200 + 0x840;
+
+/// Checks that the `.nds` file written by ndstool exists and isn't truncated.
+pub fn check_nds_output(path: &Path) -> Result<(), String> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| {
+            format!(
+                "ndstool did not produce {}: {e}",
+                absolute_path(path).display()
+            )
+        })?
+        .len();
+
+    if size < MINIMUM_NDS_SIZE {
+        return Err(format!(
+            "{} is only {size} bytes, but a valid ROM is at least {MINIMUM_NDS_SIZE} bytes. \
+            It was probably not written completely, check that there is enough disk space",
+            absolute_path(path).display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// The banner icons, as BMP images which can be passed to ndstool.
+pub struct Icons {
+    /// The static DS icon.
+    pub ds: PathBuf,
+    /// Frames of the animated DSi icon, if any.
+    pub animated: Vec<PathBuf>,
+}
+
+/// Get the banner icons: those from the package's config if set (see
+/// [`load_config`]), otherwise the default one found by [`get_metadata`] as the
+/// DS icon. PNG and SVG images are converted to BMP,
+/// since that's the only format ndstool supports, and the conversions are cached
+/// next to the executable.
+pub fn get_icons(config: &NDSConfig, nds_config: &Config) -> Result<Icons, NdsError> {
+    let cache_dir = config.target_path.with_file_name("icons");
+    let convert = |icon: &Path| {
+        icon::convert_icon(icon, &cache_dir)
+            .map_err(|e| NdsError::Input(format!("Could not convert icon: {e}")))
+    };
+    let resolve = |icon: &Path| {
+        let icon = config.cargo_manifest_path.with_file_name(icon);
+        if !icon.is_file() {
+            return Err(NdsError::Input(format!(
+                "Could not find configured icon: {}",
+                absolute_path(&icon).display()
+            )));
+        }
+        convert(&icon)
+    };
+
+    Ok(Icons {
+        ds: match nds_config.ds_icon() {
+            Some(icon) => resolve(icon)?,
+            None => convert(Path::new(&config.icon))?,
+        },
+        animated: nds_config
+            .icons
+            .animated
+            .iter()
+            .map(|icon| resolve(icon))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// Checks the size of the generated nds against the `--max-size` argument, or
+/// `max_rom_size` from `nds.toml`. The error includes a size report if the ROM
+/// is too big.
+pub fn check_rom_size(config: &NDSConfig, max_size: Option<u64>) -> Result<(), NdsError> {
+    let max_size = match max_size {
+        Some(max_size) => max_size,
+        None => match load_config(config)?.max_rom_size {
+            Some(size) => size.0,
+            None => return Ok(()),
+        },
+    };
+
+    let rom_size = std::fs::metadata(config.path_nds())
+        .map(|metadata| metadata.len())
+        .map_err(|e| NdsError::Read(config.path_nds(), e))?;
+
+    if rom_size <= max_size {
+        return Ok(());
+    }
+
+    let mut msg = format!(
+        "{} is {rom_size} bytes, which exceeds the maximum ROM size of {max_size} bytes by {} bytes",
+        absolute_path(&config.path_nds()).display(),
+        rom_size - max_size,
+    );
+
+    if doctor::find_in_path("arm-none-eabi-size").is_some() {
+        msg.push_str(&format!("\n\n{}", SizeReport::new(config)?));
+    }
+
+    Err(NdsError::RomTooBig(msg))
+}
+
+/// The banner text used when `nds.toml` doesn't set one, made of the package
+/// name, description and author. The description and author can be overridden
+/// in `nds.toml`, without changing the package's metadata.
+pub fn default_banner_text(name: &str, config: &NDSConfig, nds_config: &Config) -> BannerText {
+    BannerText {
+        title: Some(name.to_string()),
+        subtitle: Some(
+            nds_config
+                .description
+                .clone()
+                .unwrap_or_else(|| config.description.clone()),
+        ),
+        manufacturer: Some(
+            nds_config
+                .author
+                .clone()
+                .unwrap_or_else(|| banner_author(config, nds_config.authors)),
+        ),
+    }
+}
+
+/// The package's authors shown in the banner, as chosen with `authors` in
+/// `nds.toml`.
+fn banner_author(config: &NDSConfig, authors: BannerAuthors) -> String {
+    match authors {
+        BannerAuthors::All if !config.authors.is_empty() => config.authors.join(", "),
+        _ => config.author.clone(),
+    }
+}
+
+/// Load the config of the package, from `[package.metadata.nds]` in its
+/// `Cargo.toml` and its `nds.toml`.
+pub fn load_config(config: &NDSConfig) -> Result<Config, NdsError> {
+    Config::try_load(&config.cargo_manifest_path)
+        .map_err(|e| NdsError::Config(format!("Could not load {CONFIG_FILE_NAME}: {e}")))
+}
+
+/// The path of the ROM, from `output` in `nds.toml` (relative to the package
+/// directory) and `--out-dir`, or `None` to write it next to the executable.
+pub fn output_path(
+    config: &NDSConfig,
+    output: Option<&Path>,
+    out_dir: Option<&Path>,
+) -> Option<PathBuf> {
+    match (output, out_dir) {
+        (None, None) => None,
+        (Some(output), None) => Some(config.cargo_manifest_path.parent().unwrap().join(output)),
+        (output, Some(out_dir)) => {
+            let default = config.default_path_nds();
+            let file_name = output.unwrap_or(&default).file_name().unwrap();
+            Some(absolute_path(out_dir).join(file_name))
+        }
+    }
+}
+
+/// Build the ARM7 executable from the Rust package in `crate_dir` (relative to
+/// the package directory), with the same profile and target dir as the ARM9
+/// executable, and return its path.
+///
+/// The package's own `armv4t-nintendo-ds-arm7.json` is used as target spec if
+/// it has one, and it's linked with the BlocksDS ARM7 specs.
+pub fn build_arm7_crate(
+    config: &NDSConfig,
+    crate_dir: &Path,
+    cargo_args: &[String],
+    cargo_flags: &CargoFlags,
+) -> Result<PathBuf, NdsError> {
+    let mut command = arm7_crate_command(config, crate_dir, cargo_args, cargo_flags)?;
+    command.stdout(Stdio::piped()).stderr(Stdio::inherit());
+
+    print_command(&command);
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| NdsError::Cargo(CargoError::Spawn(program.clone(), e)))?;
+    if !output.status.success() {
+        return Err(NdsError::Failed {
+            tool: program,
+            status: output.status,
+            details: None,
+        });
+    }
+
+    let messages: Vec<Message> = Message::parse_stream(output.stdout.as_slice())
+        .collect::<io::Result<_>>()
+        .map_err(CargoError::Parse)?;
+    match find_executable(&messages, &[]) {
+        Ok(artifact) => Ok(artifact.executable.clone().unwrap().into_std_path_buf()),
+        Err(e) => Err(NdsError::Executable(format!(
+            "Could not find the ARM7 executable: {e}"
+        ))),
+    }
+}
+
+/// The `cargo build` command [`build_arm7_crate`] runs for the package in
+/// `crate_dir`, with its target spec and linker specs.
+pub(crate) fn arm7_crate_command(
+    config: &NDSConfig,
+    crate_dir: &Path,
+    cargo_args: &[String],
+    cargo_flags: &CargoFlags,
+) -> Result<Command, NdsError> {
+    let crate_dir = config.cargo_manifest_path.parent().unwrap().join(crate_dir);
+    let manifest = crate_dir.join("Cargo.toml");
+    if !manifest.is_file() {
+        return Err(NdsError::Input(format!(
+            "Could not find the ARM7 package: {}",
+            absolute_path(&manifest).display()
+        )));
+    }
+
+    let spec = match Some(crate_dir.join(ARM7_TARGET_SPEC)).filter(|spec| spec.is_file()) {
+        Some(spec) => spec,
+        None => {
+            write_builtin_target_spec(&target_dir(cargo_flags), ARM7_TARGET_SPEC, ARM7_TARGET_JSON)
+                .map_err(|e| {
+                    NdsError::Input(format!("Could not write the ARM7 target spec: {e}"))
+                })?
+        }
+    };
+    let arm7_specs = blocksds_root()?
+        .join("sys")
+        .join("crts")
+        .join("ds_arm7.specs");
+
+    let mut command = arm7_cargo_command(&manifest, &spec, cargo_args, cargo_flags);
+    command.env(
+        "RUSTFLAGS",
+        format!("-C link-args=-specs={}", arm7_specs.display()),
+    );
+    Ok(command)
+}
+
+/// The arguments selecting the profile among `cargo_args`.
+fn profile_args(cargo_args: &[String]) -> Vec<String> {
+    if cargo_args
+        .iter()
+        .any(|arg| arg == "--release" || arg == "-r")
+    {
+        return vec!["--release".to_string()];
+    }
+    match command::flag_value(cargo_args, "--profile") {
+        Some(profile) => vec!["--profile".to_string(), profile.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// The `cargo build` command of the ARM7 package with the given manifest, with
+/// the profile of the ARM9 executable's `cargo_args`. The [`CargoFlags`] apply
+/// to it too, except for `--manifest-path`.
+fn arm7_cargo_command(
+    manifest: &Path,
+    spec: &Path,
+    cargo_args: &[String],
+    cargo_flags: &CargoFlags,
+) -> Command {
+    let mut command = cargo(&[]);
+    command
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(manifest)
+        .arg("--target")
+        .arg(spec)
+        .arg("-Z")
+        .arg(format!("build-std={}", DEFAULT_BUILD_STD.join(",")))
+        .arg("--message-format")
+        .arg(CargoCmd::DEFAULT_MESSAGE_FORMAT)
+        .arg("--target-dir")
+        .arg(target_dir(cargo_flags))
+        .args(profile_args(cargo_args))
+        .args(cargo_flags.network_args());
+    command
+}
+
+/// Resolve the `arm7` path from `nds.toml`, relative to the package directory.
+/// Fails if the file doesn't exist, rather than falling back to the default.
+pub fn resolve_arm7(config: &NDSConfig, arm7: &Path) -> Result<PathBuf, NdsError> {
+    let arm7 = config.cargo_manifest_path.with_file_name(arm7);
+    if !arm7.is_file() {
+        return Err(NdsError::Input(format!(
+            "Could not find configured ARM7 executable: {}",
+            absolute_path(&arm7).display()
+        )));
+    }
+    Ok(arm7)
+}
+
+/// Resolve the settings for running the executable from the command line,
+/// environment, user config and project config. Fails if any of them is invalid.
+pub fn load_run_config(config: &NDSConfig, run_args: &Run) -> Result<RunConfig, NdsError> {
+    let user_config = Config::try_load_user().map_err(|e| {
+        let path = config::user_config_path().unwrap_or_default();
+        NdsError::Config(format!("Could not load {}: {e}", path.display()))
+    })?;
+
+    let cli = DeviceProfile {
+        address: run_args.address.clone(),
+        argv0: run_args.argv0.clone(),
+        server: run_args.server,
+    };
+    RunConfig::resolve(
+        cli,
+        run_args.device.as_deref(),
+        run_args.emulator.as_ref().and_then(Option::as_deref),
+        |var| env::var(var).ok(),
+        &user_config,
+        &load_config(config)?,
+    )
+    .map_err(NdsError::Config)
+}
+
+/// The command sending the ROM to the device with the loader, `dslink` by
+/// default. The arguments after a second `--` are passed to the executable,
+/// after its `argv0`.
+pub(crate) fn loader_command(
+    config: &NDSConfig,
+    run_args: &Run,
+    run_config: &RunConfig,
+) -> Command {
+    let mut command = Command::new(&run_config.loader);
+    command
+        .args(run_args.get_dslink_args(run_config))
+        .arg(config.path_nds())
+        .args(run_args.build_args.passthrough.exe_args());
+    command
+}
+
+/// Reset the device using the `loader.reset` command from `nds.toml`, before
+/// sending a new executable to it.
+/// `dslink` can't reset the device by itself, so this fails if the command isn't configured.
+pub fn reset_device(run_config: &RunConfig) -> Result<(), NdsError> {
+    let mut command = reset_command(run_config)?;
+    let tool = command.get_program().to_string_lossy().into_owned();
+    run_tool(&mut command, &tool)
+}
+
+/// The `loader.reset` command, with the `{address}` of the device.
+pub(crate) fn reset_command(run_config: &RunConfig) -> Result<Command, NdsError> {
+    let Some(reset) = run_config.reset.clone().filter(|cmd| !cmd.is_empty()) else {
+        return Err(NdsError::Config(format!(
+            "`--reset` is not supported by {} on its own. \
+            Set `loader.reset` in {CONFIG_FILE_NAME} to the command used to reset the device.",
+            run_config.loader
+        )));
+    };
+
+    let address = run_config.address.as_ref().map(ToString::to_string);
+    let args = reset
+        .iter()
+        .map(|arg| {
+            if !arg.contains("{address}") {
+                return Ok(arg.clone());
+            }
+            match &address {
+                Some(address) => Ok(arg.replace("{address}", address)),
+                None => Err(NdsError::Config(String::from(
+                    "The configured `loader.reset` command requires `--address`",
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    Ok(command)
+}
+
+/// Emulators looked for in $PATH when none is configured, in order of preference.
+pub(crate) const KNOWN_EMULATORS: &[&str] = &["melonDS", "desmume", "NO$GBA"];
+
+/// Emulators looked for in $PATH to run tests, preferring the headless DeSmuME.
+pub(crate) const KNOWN_TEST_EMULATORS: &[&str] = &["desmume-cli", "melonDS", "desmume", "NO$GBA"];
+
+/// Find the emulator to use: the configured one (see [`RunConfig`]), or the
+/// first of the `known` emulators found in $PATH.
+pub fn find_emulator(run_config: &RunConfig, known: &[&str]) -> Option<EmulatorConfig> {
+    run_config.emulator.clone().or_else(|| {
+        known
+            .iter()
+            .find(|emulator| doctor::find_in_path(emulator).is_some())
+            .map(|emulator| EmulatorConfig::new(emulator.to_string()))
+    })
+}
+
+/// Find the emulator to use, failing if there is none.
+pub(crate) fn require_emulator(
+    run_config: &RunConfig,
+    known: &[&str],
+) -> Result<EmulatorConfig, NdsError> {
+    find_emulator(run_config, known).ok_or_else(|| {
+        NdsError::Config(format!(
+            "No emulator found in $PATH. Pass one with `--emulator=<path>`, or set \
+            {EMULATOR_ENV} or `emulator` in {CONFIG_FILE_NAME}"
+        ))
+    })
+}
+
+/// Run the generated nds in an emulator.
+pub fn run_emulator(config: &NDSConfig, run_config: &RunConfig) -> Result<(), NdsError> {
+    let emulator = require_emulator(run_config, KNOWN_EMULATORS)?;
+    run_tool(
+        &mut emulator.command_for(&config.path_nds()),
+        &emulator.command,
+    )
+}
+
+/// Run the built tests in an emulator, copying its output to stdout. Emulators
+/// keep running once the program returns, so the emulator is stopped as soon as
+/// the test harness prints its `test result:` line, which gives the result.
+/// Otherwise, the emulator's exit status is the result.
+pub fn run_tests_in_emulator(config: &NDSConfig, run_config: &RunConfig) -> Result<(), NdsError> {
+    let emulator = require_emulator(run_config, KNOWN_TEST_EMULATORS)?;
+
+    let mut command = emulator.command_for(&config.path_nds());
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+
+    print_command(&command);
+
+    let mut process = command
+        .spawn()
+        .map_err(|e| NdsError::spawn(&emulator.command, e))?;
+    let stdout = process
+        .stdout
+        .take()
+        .expect("the emulator's stdout is piped");
+
+    let mut harness_result = None;
+    for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else { break };
+        println!("{line}");
+        harness_result = test_result(&line);
+        if harness_result.is_some() {
+            break;
+        }
+    }
+
+    let code = match harness_result {
+        Some(passed) => {
+            // The emulator may already have exited by itself
+            let _ = process.kill();
+            let _ = process.wait();
+            if passed {
+                0
+            } else {
+                TEST_FAILURE_CODE
+            }
+        }
+        None => {
+            let status = process
+                .wait()
+                .map_err(|e| NdsError::Spawn(emulator.command.clone(), e))?;
+            exit_code(status)
+        }
+    };
+
+    if code != 0 {
+        return Err(NdsError::TestsFailed {
+            emulator: emulator.command,
+            code,
+        });
+    }
+    info!("The tests passed in `{}`", emulator.command);
+    Ok(())
+}
+
+/// The exit code of `cargo test` when tests fail.
+const TEST_FAILURE_CODE: i32 = 101;
+
+/// Whether the tests passed, if the line is the summary printed by the test
+/// harness, e.g. `test result: ok. 3 passed; 0 failed; ...`.
+fn test_result(line: &str) -> Option<bool> {
+    let result = line.trim().strip_prefix("test result: ")?;
+    if result.starts_with("ok") {
+        Some(true)
+    } else if result.starts_with("FAILED") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Link the generated nds to a ds to execute and test using `dslink`, or the
+/// configured loader.
+/// The loader is run again, up to `--retries` times, when it fails, e.g. because
+/// it didn't find the device in time. Fails with its status after the last try.
+/// This will fail if the loader is not within the running directory or in a directory found in $PATH
+pub fn link(config: &NDSConfig, run_args: &Run, run_config: &RunConfig) -> Result<(), NdsError> {
+    let retries = run_args.retries.unwrap_or(0);
+
+    for attempt in 0..=retries {
+        let mut command = loader_command(config, run_args, run_config);
+        command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+
+        print_command(&command);
+
+        let status = command
+            .status()
+            .map_err(|e| NdsError::spawn(&run_config.loader, e))?;
+
+        if status.success() {
+            return Ok(());
+        }
+        if attempt == retries {
+            return Err(NdsError::Failed {
+                tool: run_config.loader.clone(),
+                status,
+                details: None,
+            });
+        }
+
+        let delay = retry_delay(attempt);
+        eprintln!(
+            "`{}` failed, retrying in {}ms ({}/{retries})",
+            run_config.loader,
+            delay.as_millis(),
+            attempt + 1
+        );
+        std::thread::sleep(delay);
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// How long to wait before retrying the loader after the given failed attempt,
+/// starting at half a second and doubling up to 4 seconds.
+fn retry_delay(attempt: usize) -> Duration {
+    Duration::from_millis(500 << attempt.min(3))
+}
+
+/// Resolve the `include_dirs` from `nds.toml` relative to the package directory.
+/// Fails if any of them isn't an existing directory.
+pub fn get_include_dirs(config: &NDSConfig, nds_config: &Config) -> Result<Vec<PathBuf>, NdsError> {
+    let package_dir = config.cargo_manifest_path.parent().unwrap();
+
+    include_dir_paths(package_dir, nds_config)
+        .into_iter()
+        .map(|dir| {
+            if !dir.is_dir() {
+                return Err(NdsError::Input(format!(
+                    "Could not find configured include dir: {}",
+                    absolute_path(&dir).display()
+                )));
+            }
+            Ok(dir)
+        })
+        .collect()
+}
+
+/// Find the RomFS source directories of the package in `package_dir`: its
+/// `romfs` (the `romfs` directory by default), each in the package directory
+/// or else in the first of the `include_dirs` which has it. Fails with the path
+/// in the package directory of the first one missing.
+pub(crate) fn find_romfs_dirs(
+    package_dir: &Path,
+    nds_config: &Config,
+    include_dirs: &[PathBuf],
+) -> Result<Vec<PathBuf>, PathBuf> {
+    let default = [PathBuf::from("romfs")];
+    let sources = nds_config.romfs.as_deref().unwrap_or(&default);
+
+    sources
+        .iter()
+        .map(|source| {
+            let path = package_dir.join(source);
+            if path.is_dir() {
+                return Ok(path);
+            }
+            include_dirs
+                .iter()
+                .map(|dir| dir.join(source))
+                .find(|candidate| candidate.is_dir())
+                .ok_or(path)
+        })
+        .collect()
+}
+
+/// Get the directory to pass to ndstool as the RomFS, if any.
+///
+/// The sources are the `romfs` from the package's config (see [`load_config`]),
+/// otherwise the `romfs` directory (which is skipped if missing).
+/// Each is relative to the package directory or one of the `include_dirs`.
+/// Several sources, or a source with filtered out files, are copied into a
+/// single directory next to the executable.
+pub fn get_romfs_path(
+    config: &NDSConfig,
+    nds_config: &Config,
+    include_dirs: &[PathBuf],
+) -> Result<Option<PathBuf>, NdsError> {
+    let dirs = romfs_sources(config, nds_config, include_dirs)?;
+    if dirs.is_empty() {
+        return Ok(None);
+    }
+    stage_romfs(config, nds_config, dirs)
+        .map(Some)
+        .map_err(NdsError::Input)
+}
+
+/// The RomFS source directories of [`get_romfs_path`], or none if the ROM has
+/// no RomFS.
+pub(crate) fn romfs_sources(
+    config: &NDSConfig,
+    nds_config: &Config,
+    include_dirs: &[PathBuf],
+) -> Result<Vec<PathBuf>, NdsError> {
+    let package_dir = config.cargo_manifest_path.parent().unwrap();
+    let path = match find_romfs_dirs(package_dir, nds_config, include_dirs) {
+        Ok(dirs) => return Ok(dirs),
+        Err(path) => path,
+    };
+
+    let is_default = nds_config.romfs.is_none();
+    if is_default && !nds_config.require_romfs {
+        if romfs::uses_nitrofs(&package_dir.join("src")) {
+            eprintln!(
+                "warning: the code seems to read files from the RomFS, but there is no \
+                {} directory, so the ROM has no RomFS",
+                path.display()
+            );
+        }
+        Ok(Vec::new())
+    } else if is_default {
+        Err(NdsError::Input(format!(
+            "Could not find RomFS dir {}, which is required by `require_romfs`",
+            absolute_path(&path).display()
+        )))
+    } else {
+        Err(NdsError::Input(format!(
+            "Could not find configured RomFS dir: {}",
+            absolute_path(&path).display()
+        )))
+    }
+}
+
+/// Merge the RomFS source `dirs` and filter their files, into a single
+/// directory next to the executable if needed, and return the directory to
+/// pass to ndstool.
+pub(crate) fn stage_romfs(
+    config: &NDSConfig,
+    nds_config: &Config,
+    mut dirs: Vec<PathBuf>,
+) -> Result<PathBuf, String> {
+    let (files, excluded) = filter_romfs(nds_config, &dirs)?;
+    debug!("RomFS: {} files included, {excluded} excluded", files.len());
+
+    if !romfs_needs_staging(&dirs, excluded) {
+        return Ok(dirs.pop().unwrap());
+    }
+
+    let staged = config.path_romfs();
+    romfs::stage(&files, &staged).map_err(|e| format!("Could not stage the RomFS: {e}"))?;
+    Ok(staged)
+}
+
+/// Whether the RomFS source `dirs` have to be staged into a single directory,
+/// because there are several of them or `excluded` files were filtered out.
+pub(crate) fn romfs_needs_staging(dirs: &[PathBuf], excluded: usize) -> bool {
+    dirs.len() != 1 || excluded != 0
+}
+
+/// The files of the RomFS source `dirs` which pass the `romfs_include` and
+/// `romfs_exclude` filters, and how many were filtered out.
+pub(crate) fn filter_romfs(
+    nds_config: &Config,
+    dirs: &[PathBuf],
+) -> Result<(romfs::RomfsFiles, usize), String> {
+    let default_excludes: Vec<String>;
+    let filter = romfs::Filter {
+        include: &nds_config.romfs_include,
+        exclude: match &nds_config.romfs_exclude {
+            Some(exclude) => exclude,
+            None => {
+                default_excludes = romfs::DEFAULT_EXCLUDES
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                &default_excludes
+            }
+        },
+    };
+
+    let mut files = romfs::collect_files(dirs)
+        .map_err(|e| format!("Could not merge the RomFS directories: {e}"))?;
+    let excluded = filter.apply(&mut files);
+    Ok((files, excluded))
+}
+
+/// Read the package name from `[package] name` in the Cargo manifest, used as
+/// the default banner title. Falls back to "No Name" if it's unset.
+pub fn get_name(config: &NDSConfig) -> Result<String, NdsError> {
+    let manifest_data = config::read_manifest(&config.cargo_manifest_path)
+        .map_err(|e| NdsError::Config(format!("Could not read the Cargo manifest: {e}")))?;
+
+    Ok(manifest_data
+        .get("package")
+        .and_then(toml::Value::as_table)
+        .and_then(|table| table.get("name"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or("No Name")
+        .to_string())
+}
+
+/// Information about the built executable and its package, as needed to build
+/// and run the `.nds` file.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct NDSConfig {
+    pub name: String,
+    /// The first of the package's authors, or a placeholder if it has none.
+    pub author: String,
+    /// All the package's authors.
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub description: String,
+    pub icon: String,
+    pub target_path: PathBuf,
+    pub cargo_manifest_path: PathBuf,
+    /// The package's version, used as the ROM version in the header.
+    pub version: Option<Version>,
+    /// Whether to build a DSi-enhanced ROM.
+    pub dsi: bool,
+    /// Whether to also write a copy of the ROM named after the package version.
+    pub versioned_copy: bool,
+    /// The ARM7 executable configured in `nds.toml`, if any.
+    pub arm7: Option<PathBuf>,
+    /// Where the ROM is written, if not next to the executable.
+    pub output: Option<PathBuf>,
+}
+
+impl NDSConfig {
+    pub fn path_nds(&self) -> PathBuf {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+        self.default_path_nds()
+    }
+    fn default_path_nds(&self) -> PathBuf {
+        let extension = if self.dsi { "dsi" } else { "nds" };
+        self.target_path
+            .with_extension("")
+            .with_extension(extension)
+    }
+    pub fn path_arm9(&self) -> PathBuf {
+        self.target_path
+            .with_extension("")
+            .with_extension("arm9.elf")
+    }
+    /// Where the RomFS is staged when it has to be, see [`stage_romfs`].
+    pub(crate) fn path_romfs(&self) -> PathBuf {
+        self.target_path.with_extension("").with_extension("romfs")
+    }
+    /// Where the banner of an animated icon is built.
+    pub(crate) fn path_banner(&self) -> PathBuf {
+        self.target_path
+            .with_extension("")
+            .with_extension("banner.bin")
+    }
+    pub fn path_arm7(&self) -> PathBuf {
+        if let Some(arm7) = &self.arm7 {
+            return arm7.clone();
+        }
+        let arm7 = self
+            .target_path
+            .with_extension("")
+            .with_extension("arm7.elf");
+        if arm7.exists() {
+            return arm7;
+        }
+        // An invalid BlocksDS is reported when checking the executables
+        let blocksds = blocksds_root().unwrap_or_else(|_| find_blocksds());
+        blocksds.join("sys").join("default_arm7").join("arm7.elf")
+    }
+}
+
+#[derive(Ord, PartialOrd, PartialEq, Eq, Debug)]
+pub struct CommitDate {
+    year: i32,
     month: i32,
     day: i32,
 }
@@ -485,3 +2757,996 @@ const MINIMUM_COMMIT_DATE: CommitDate = CommitDate {
     day: 31,
 };
 const MINIMUM_RUSTC_VERSION: Version = Version::new(1, 70, 0);
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn default_banner_with_semicolons() {
+        let config = NDSConfig {
+            description: String::from("fast; small; fun"),
+            author: String::from("Me;Myself"),
+            ..Default::default()
+        };
+
+        let banner = default_banner_text("game", &config, &Config::default()).to_ndstool_arg();
+        assert_eq!(banner.split(';').count(), 3);
+        assert_eq!(banner, "game;fast, small, fun;Me,Myself");
+    }
+
+    #[test]
+    fn banner_authors() {
+        let config = NDSConfig {
+            author: String::from("Alice"),
+            authors: vec![String::from("Alice"), String::from("Bob")],
+            ..Default::default()
+        };
+        let manufacturer = |toml: &str| {
+            let nds_config: Config = toml::from_str(toml).unwrap();
+            default_banner_text("game", &config, &nds_config)
+                .manufacturer
+                .unwrap()
+        };
+
+        assert_eq!(manufacturer(""), "Alice");
+        assert_eq!(manufacturer(r#"authors = "first""#), "Alice");
+        assert_eq!(manufacturer(r#"authors = "all""#), "Alice, Bob");
+        assert_eq!(
+            manufacturer("authors = \"all\"\nauthor = \"Studio\""),
+            "Studio"
+        );
+        assert!(toml::from_str::<Config>(r#"authors = "last""#).is_err());
+
+        // Without authors, the placeholder is kept
+        let config = NDSConfig {
+            author: String::from("Unspecified Author"),
+            ..Default::default()
+        };
+        let nds_config: Config = toml::from_str(r#"authors = "all""#).unwrap();
+        assert_eq!(
+            default_banner_text("game", &config, &nds_config)
+                .manufacturer
+                .unwrap(),
+            "Unspecified Author"
+        );
+    }
+
+    #[test]
+    fn banner_author_and_description_override() {
+        let config = NDSConfig {
+            description: String::from("A crate"),
+            author: String::from("Me <me@example.com>"),
+            ..Default::default()
+        };
+        let nds_config: Config = toml::from_str(
+            r#"
+            author = "Studio"
+            description = "A polished game"
+            "#,
+        )
+        .unwrap();
+
+        let banner = default_banner_text("game", &config, &nds_config);
+        assert_eq!(banner.to_ndstool_arg(), "game;A polished game;Studio");
+
+        // `name` still takes precedence over both
+        let nds_config: Config = toml::from_str(
+            r#"
+            author = "Studio"
+            [name.english]
+            manufacturer = "Publisher"
+            "#,
+        )
+        .unwrap();
+        let [_, english, ..] =
+            nds_config
+                .name
+                .resolve(&default_banner_text("game", &config, &nds_config));
+        assert_eq!(english.to_ndstool_arg(), "game;A crate;Publisher");
+    }
+
+    #[test]
+    fn banner_title_from_package_name() {
+        let dir = TempDir::new("name");
+        let config = NDSConfig {
+            description: String::from("A game"),
+            author: String::from("Me"),
+            cargo_manifest_path: dir.join("Cargo.toml"),
+            ..Default::default()
+        };
+
+        // Names which look like paths are used as they are
+        for name in ["space-game", "space.game", "../space/game"] {
+            fs::write(
+                &config.cargo_manifest_path,
+                format!("[package]\nname = \"{name}\"\n"),
+            )
+            .unwrap();
+            assert_eq!(get_name(&config).unwrap(), name);
+            let banner =
+                default_banner_text(&get_name(&config).unwrap(), &config, &Config::default());
+            assert_eq!(banner.to_ndstool_arg(), format!("{name};A game;Me"));
+        }
+
+        fs::write(&config.cargo_manifest_path, "[workspace]\n").unwrap();
+        assert_eq!(get_name(&config).unwrap(), "No Name");
+    }
+
+    #[test]
+    fn blocksds_install() {
+        let dir = TempDir::new("blocksds");
+        let err = check_blocksds(&dir.join("missing")).unwrap_err();
+        assert!(
+            err.contains("doesn't exist") && err.contains("BLOCKSDS"),
+            "{err}"
+        );
+
+        fs::create_dir_all(dir.join("sys/crts")).unwrap();
+        let err = check_blocksds(&dir).unwrap_err();
+        assert!(err.contains("ds_arm9.specs"), "{err}");
+
+        fs::write(blocksds_specs(&dir), "").unwrap();
+        assert_eq!(check_blocksds(&dir), Ok(()));
+    }
+
+    #[test]
+    fn output_paths() {
+        let config = NDSConfig {
+            target_path: PathBuf::from("/game/target/armv5te-nintendo-ds/release/game.arm9.elf"),
+            cargo_manifest_path: PathBuf::from("/game/Cargo.toml"),
+            ..Default::default()
+        };
+        let output = Path::new("dist/MyGame.nds");
+
+        assert_eq!(output_path(&config, None, None), None);
+        assert_eq!(
+            output_path(&config, Some(output), None),
+            Some(PathBuf::from("/game/dist/MyGame.nds"))
+        );
+        assert_eq!(
+            output_path(&config, Some(output), Some(Path::new("/out"))),
+            Some(PathBuf::from("/out/MyGame.nds"))
+        );
+        assert_eq!(
+            output_path(&config, None, Some(Path::new("/out"))),
+            Some(PathBuf::from("/out/game.nds"))
+        );
+    }
+
+    #[test]
+    fn unwind_profiles() {
+        let manifest: toml::Value = toml::from_str(
+            r#"
+            [profile.dev]
+            panic = "unwind"
+
+            [profile.release]
+            panic = "abort"
+
+            [profile.custom]
+            inherits = "release"
+
+            [profile.unused]
+            inherits = "release"
+            panic = "unwind"
+            "#,
+        )
+        .unwrap();
+        let layers = [manifest["profile"].clone()];
+        let panic = |profile| {
+            profile_panic(&layers, profile).map(|(source, panic)| format!("{source}: {panic}"))
+        };
+
+        assert_eq!(panic("dev").as_deref(), Some("dev: unwind"));
+        // An unused profile which unwinds doesn't matter
+        assert_eq!(panic("release").as_deref(), Some("release: abort"));
+        assert_eq!(panic("custom").as_deref(), Some("release: abort"));
+        assert_eq!(panic("test").as_deref(), Some("dev: unwind"));
+        assert_eq!(panic("bench").as_deref(), Some("release: abort"));
+        assert_eq!(panic("missing"), None);
+
+        // Overrides come first
+        let config: toml::Value = toml::from_str(r#"profile.release.panic = "unwind""#).unwrap();
+        let layers = [config["profile"].clone(), manifest["profile"].clone()];
+        assert_eq!(
+            profile_panic(&layers, "custom"),
+            Some(("release".into(), "unwind".into()))
+        );
+
+        let cycle: toml::Value = toml::from_str("a.inherits = \"b\"\nb.inherits = \"a\"").unwrap();
+        assert_eq!(profile_panic(&[cycle], "a"), None);
+    }
+
+    #[test]
+    fn selected_profiles() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(selected_profile(&args(&[])), "dev");
+        assert_eq!(selected_profile(&args(&["-r"])), "release");
+        assert_eq!(selected_profile(&args(&["--profile", "custom"])), "custom");
+        assert_eq!(selected_profile(&args(&["--profile=dev"])), "dev");
+    }
+
+    #[test]
+    fn truncated_nds_output() {
+        let dir = TempDir::new("truncated");
+        let path = dir.join("game.nds");
+
+        std::fs::write(&path, [0; 100]).unwrap();
+        let err = check_nds_output(&path).unwrap_err();
+        assert!(err.contains("only 100 bytes"), "{err}");
+
+        std::fs::write(&path, [0; MINIMUM_NDS_SIZE as usize]).unwrap();
+        assert_eq!(check_nds_output(&path), Ok(()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(check_nds_output(&path).is_err());
+    }
+
+    fn artifact(name: &str, kind: &str) -> String {
+        format!(
+            r#"{{"reason":"compiler-artifact","package_id":"game 0.1.0 (path+file:///game)","manifest_path":"/game/Cargo.toml","target":{{"kind":["{kind}"],"crate_types":["bin"],"name":"{name}","src_path":"/game/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true}},"profile":{{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false}},"features":[],"filenames":["/game/target/{kind}/{name}.arm9.elf"],"executable":"/game/target/{kind}/{name}.arm9.elf","fresh":false}}"#
+        )
+    }
+
+    fn parse_messages(lines: &[String]) -> Vec<Message> {
+        Message::parse_stream(lines.join("\n").as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn keep_going() {
+        let error = |name: &str| {
+            format!(
+                r#"{{"reason":"compiler-message","package_id":"game 0.1.0 (path+file:///game)","manifest_path":"/game/Cargo.toml","target":{{"kind":["bin"],"crate_types":["bin"],"name":"{name}","src_path":"/game/src/bin/{name}.rs","edition":"2021","doc":true,"doctest":false,"test":true}},"message":{{"rendered":"error: oops\n","children":[],"code":null,"level":"error","message":"oops","spans":[]}}}}"#
+            )
+        };
+        let messages = parse_messages(&[
+            artifact("game", "bin"),
+            error("tool"),
+            error("tool"),
+            artifact("demo", "example"),
+        ]);
+
+        assert_eq!(
+            keep_going_summary(&messages),
+            "note: with `--keep-going`, no ROM is built unless every target builds\n    \
+            built: game (bin), demo (example)\n    failed: tool (bin)"
+        );
+        assert!(keep_going_summary(&[]).ends_with("built: none\n    failed: none"));
+    }
+
+    #[test]
+    fn message_formats() {
+        let build = CargoCmd::Passthrough(vec!["check".to_string()]);
+        let format = |format: &str| Some(format.to_string());
+
+        assert_eq!(rendered_format(&format("short"), &build), Some("short"));
+        assert_eq!(rendered_format(&format("json"), &build), None);
+        assert_eq!(rendered_format(&None, &build), None);
+
+        assert_eq!(
+            cargo_message_format(&format("short"), &build, false),
+            "json-diagnostic-short"
+        );
+        assert_eq!(
+            cargo_message_format(&format("human"), &build, true),
+            "json,json-diagnostic-rendered-ansi"
+        );
+        assert_eq!(
+            cargo_message_format(&None, &build, true),
+            CargoCmd::DEFAULT_MESSAGE_FORMAT
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn cargo_exit_codes() {
+        use std::os::unix::process::ExitStatusExt;
+
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(ExitStatus::from_raw(101 << 8)), 101);
+        // Killed by SIGINT
+        assert_eq!(exit_code(ExitStatus::from_raw(2)), 130);
+    }
+
+    #[test]
+    fn retained_messages() {
+        let library = artifact("game", "lib").replace(
+            r#""executable":"/game/target/lib/game.arm9.elf""#,
+            r#""executable":null"#,
+        );
+        let messages = parse_messages(&[
+            artifact("game", "bin"),
+            library,
+            r#"{"reason":"build-finished","success":true}"#.to_string(),
+        ]);
+
+        let retained: Vec<bool> = messages
+            .iter()
+            .map(|message| is_retained(message, false))
+            .collect();
+        assert_eq!(retained, [true, false, false]);
+    }
+
+    #[test]
+    fn multiple_executables() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let single = parse_messages(&[artifact("game", "bin")]);
+        assert_eq!(find_executable(&single, &[]).unwrap().target.name, "game");
+        assert!(find_executable(&[], &[]).is_err());
+
+        let multiple = parse_messages(&[artifact("game", "bin"), artifact("demo", "example")]);
+        let err = find_executable(&multiple, &args(&["--release"])).unwrap_err();
+        assert!(
+            err.contains("game (bin)") && err.contains("demo (example)"),
+            "{err}"
+        );
+
+        // Building all the examples doesn't select one
+        assert!(find_executable(&multiple, &args(&["--examples"])).is_err());
+
+        // Nor does `--lib`, with several library test executables in a workspace
+        let libs = parse_messages(&[artifact("game", "lib"), artifact("engine", "lib")]);
+        let err = find_executable(&libs, &args(&["--lib"])).unwrap_err();
+        assert!(
+            err.contains("game (lib)") && err.contains("engine (lib)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn select_executable() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let multiple = parse_messages(&[
+            artifact("demo", "example"),
+            artifact("game", "bin"),
+            artifact("game", "example"),
+        ]);
+
+        // The selected target is used, whatever the build order
+        let exe = find_executable(&multiple, &args(&["--example", "demo"])).unwrap();
+        assert_eq!(exe.target.name, "demo");
+        let exe = find_executable(&multiple, &args(&["--bin=game", "--release"])).unwrap();
+        assert_eq!(exe.target.kind, ["bin"]);
+        let exe = find_executable(&multiple, &args(&["--example", "game"])).unwrap();
+        assert_eq!(exe.target.kind, ["example"]);
+
+        let err = find_executable(&multiple, &args(&["--bin", "demo"])).unwrap_err();
+        assert_eq!(err, "`--bin demo` did not produce an executable");
+
+        assert_eq!(
+            target_selections(&args(&["--lib", "--test", "integration"])),
+            [
+                TargetSelection {
+                    flag: "--lib",
+                    kind: None,
+                    name: None
+                },
+                TargetSelection {
+                    flag: "--test",
+                    kind: Some("test"),
+                    name: Some("integration")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn package_icon() {
+        let dir = TempDir::new("default-icon");
+        let blocksds = Path::new("/blocksds");
+        assert_eq!(
+            default_icon(&dir, blocksds),
+            blocksds_icon(blocksds).to_string_lossy()
+        );
+
+        fs::write(dir.join("icon.png"), "").unwrap();
+        assert_eq!(
+            default_icon(&dir, blocksds),
+            dir.join("icon.png").to_string_lossy()
+        );
+        fs::write(dir.join("icon.bmp"), "").unwrap();
+        assert_eq!(
+            default_icon(&dir, blocksds),
+            dir.join("icon.bmp").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn rom_version() {
+        let version = |v| Version::parse(v).unwrap();
+
+        assert_eq!(header_version(None), Ok(0));
+        assert_eq!(header_version(Some(&version("0.1.0"))), Ok(0x01));
+        assert_eq!(header_version(Some(&version("1.2.3"))), Ok(0x12));
+        assert_eq!(header_version(Some(&version("15.15.99"))), Ok(0xff));
+        assert!(header_version(Some(&version("16.0.0"))).is_err());
+        assert!(header_version(Some(&version("0.16.0"))).is_err());
+
+        assert_eq!(header_title("my-game"), "MY GAME");
+        assert_eq!(header_title("a very long name"), "A VERY LONG");
+    }
+
+    #[test]
+    fn ndstool_args() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        assert_eq!(
+            extra_ndstool_args(&args(&["-y9", "overlays.bin", "-y", "overlays"])),
+            args(&["-y9", "overlays.bin", "-y", "overlays"])
+        );
+        assert_eq!(
+            extra_ndstool_args(&args(&["-9", "other.elf", "-y", "overlays", "-c"])),
+            args(&["-y", "overlays"])
+        );
+    }
+
+    #[test]
+    fn find_spec_in_parents() {
+        let dir = TempDir::new("spec");
+        let member = dir.join("crates/game");
+        fs::create_dir_all(&member).unwrap();
+        assert_eq!(find_target_spec(&member), None);
+
+        fs::write(dir.join(DEFAULT_TARGET_SPEC), "{}").unwrap();
+        assert_eq!(
+            find_target_spec(&member),
+            Some(dir.join(DEFAULT_TARGET_SPEC))
+        );
+        fs::write(member.join(DEFAULT_TARGET_SPEC), "{}").unwrap();
+        assert_eq!(
+            find_target_spec(&member),
+            Some(member.join(DEFAULT_TARGET_SPEC))
+        );
+    }
+
+    #[test]
+    fn config_from_manifest_path() {
+        let dir = TempDir::new("manifest-path");
+        fs::create_dir_all(dir.join("game")).unwrap();
+        fs::write(dir.join("game/Cargo.toml"), "[package]\nname = \"game\"\n").unwrap();
+        fs::write(
+            dir.join("game/nds.toml"),
+            "target_spec = \"specs/ds.json\"\nbuild_std = [\"core\"]\n",
+        )
+        .unwrap();
+
+        let manifest_path = dir.join("game/Cargo.toml");
+        let command::Cargo::Input(input) = <command::Cargo as clap::Parser>::try_parse_from([
+            "cargo",
+            "nds",
+            "build",
+            "--manifest-path",
+            manifest_path.to_str().unwrap(),
+        ])
+        .unwrap();
+
+        assert_eq!(package_dir(&input.cargo_flags), dir.join("game"));
+        let nds_config = load_command_config(&input).unwrap();
+        assert_eq!(nds_config.build_std, Some(vec![String::from("core")]));
+        let target = find_target(&input, &nds_config).unwrap();
+        assert_eq!(
+            target.spec,
+            dir.join("game/specs/ds.json").to_string_lossy()
+        );
+        assert_eq!(target.source, TargetSource::Config);
+    }
+
+    #[test]
+    fn offline_note() {
+        let note = offline_build_std_note(Path::new("/toolchains/nightly"));
+        assert!(note.contains("rustup component add rust-src"));
+        assert!(note.contains("cargo fetch --manifest-path /toolchains/nightly/lib/rustlib/src/rust/library/Cargo.toml"));
+    }
+
+    #[test]
+    fn prebuilt_std() {
+        let sysroot = TempDir::new("sysroot");
+        let lib = sysroot.join("lib/rustlib/armv5te-nintendo-ds/lib");
+        assert!(!has_prebuilt_std(
+            &sysroot,
+            "armv5te-nintendo-ds",
+            DEFAULT_BUILD_STD
+        ));
+
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(lib.join("libcore-0123456789abcdef.rlib"), "").unwrap();
+        fs::write(lib.join("libcompiler_builtins-0123456789abcdef.rlib"), "").unwrap();
+        assert!(!has_prebuilt_std(
+            &sysroot,
+            "armv5te-nintendo-ds",
+            DEFAULT_BUILD_STD
+        ));
+
+        fs::write(lib.join("liballoc-0123456789abcdef.rlib"), "").unwrap();
+        assert!(has_prebuilt_std(
+            &sysroot,
+            "armv5te-nintendo-ds",
+            DEFAULT_BUILD_STD
+        ));
+        assert!(!has_prebuilt_std(
+            &sysroot,
+            "armv5te-none-eabi",
+            DEFAULT_BUILD_STD
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn ndstool_failure_context() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut command = Command::new("ndstool");
+        command.args(["-c", "/game/game.nds", "-b", "/game/my icon.bmp"]);
+        let output = BuildOutput {
+            nds: PathBuf::from("/game/game.nds"),
+            arm9: PathBuf::from("/game/game.arm9.elf"),
+            arm7: PathBuf::from("/blocksds/sys/default_arm7/arm7.elf"),
+            icon: Some(PathBuf::from("/game/my icon.bmp")),
+            banner: None,
+            banner_text: BannerText {
+                title: Some("Game".to_string()),
+                ..Default::default()
+            },
+            romfs: None,
+            versioned: None,
+        };
+
+        let report = ndstool_failure(&command, ExitStatus::from_raw(1 << 8), &output);
+        assert!(
+            report.starts_with("ndstool failed (exit status: 1) to build /game/game.nds\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("  command:     ndstool -c /game/game.nds -b \"/game/my icon.bmp\"\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("  ARM7:        /blocksds/sys/default_arm7/arm7.elf\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("  banner:      none\n  banner text: Game;;\n"),
+            "{report}"
+        );
+        assert!(report.contains("\nhint: "), "{report}");
+    }
+
+    #[test]
+    fn missing_executables() {
+        let dir = TempDir::new("executables");
+        let mut config = NDSConfig {
+            target_path: dir.join("game.arm9.elf"),
+            arm7: Some(dir.join("arm7/game.arm7.elf")),
+            ..Default::default()
+        };
+
+        let err = check_executables(&config).unwrap_err();
+        assert!(
+            err.starts_with(&format!(
+                "Could not read the ARM9 executable {}",
+                config.path_arm9().display()
+            )),
+            "{err}"
+        );
+        assert!(
+            err.contains(&format!(
+                "executable built by cargo, {}",
+                config.target_path.display()
+            )),
+            "{err}"
+        );
+
+        fs::write(&config.target_path, "").unwrap();
+        let err = check_executables(&config).unwrap_err();
+        assert!(
+            err.starts_with("Could not read the ARM7 executable"),
+            "{err}"
+        );
+        assert!(err.contains("`arm7` or `arm7_crate`"), "{err}");
+
+        // The ARM7 next to the ARM9 executable is used if it exists
+        config.arm7 = None;
+        fs::write(dir.join("game.arm7.elf"), "").unwrap();
+        assert_eq!(check_executables(&config), Ok(()));
+    }
+
+    #[test]
+    fn build_cache() {
+        let dir = TempDir::new("build-cache");
+        let cargo_flags = CargoFlags {
+            target_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let no_args: &[String] = &[];
+        let err = load_build_cache(&cargo_flags, no_args).unwrap_err();
+        assert!(err.starts_with("No ROM was built yet"), "{err}");
+
+        let config = NDSConfig {
+            name: "game".to_string(),
+            target_path: dir.join("armv5te-nintendo-ds/debug/game"),
+            version: Some(Version::new(1, 2, 0)),
+            dsi: true,
+            ..Default::default()
+        };
+        write_build_cache(&config, &cargo_flags);
+        let err = load_build_cache(&cargo_flags, no_args).unwrap_err();
+        assert!(err.contains("does not exist anymore"), "{err}");
+
+        fs::create_dir_all(dir.join("armv5te-nintendo-ds/debug")).unwrap();
+        fs::write(dir.join("armv5te-nintendo-ds/debug/game.dsi"), "").unwrap();
+        assert_eq!(load_build_cache(&cargo_flags, no_args).unwrap(), config);
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert!(load_build_cache(&cargo_flags, &args(&["--bin", "game", "--profile=dev"])).is_ok());
+
+        // Another executable is requested
+        for (args, message) in [
+            (
+                args(&["--release"]),
+                "with the `release` profile, but the `debug` one",
+            ),
+            (args(&["--bin", "other"]), "for the `other` executable"),
+            (args(&["--example", "game"]), "for the `game` example"),
+        ] {
+            let err = load_build_cache(&cargo_flags, &args).unwrap_err();
+            assert!(err.contains(message), "{err}");
+        }
+
+        // The executable is rebuilt
+        let executable = fs::File::create(&config.target_path).unwrap();
+        executable
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let err = load_build_cache(&cargo_flags, no_args).unwrap_err();
+        assert!(
+            err.contains("was rebuilt since the last ROM build"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn link_args() {
+        let flags = arm9_rustflags(
+            Path::new("/blocksds/ds_arm9.specs"),
+            Path::new("/my game/target/game.map"),
+            &[
+                "-Wl,--wrap=malloc".to_string(),
+                "-Wl,--defsym,NAME=a value".to_string(),
+            ],
+        );
+        assert_eq!(
+            flags,
+            [
+                "-C",
+                "link-arg=-specs=/blocksds/ds_arm9.specs",
+                "-C",
+                "link-arg=-Wl,-Map,/my game/target/game.map",
+                "-C",
+                "link-arg=-Wl,--wrap=malloc",
+                "-C",
+                "link-arg=-Wl,--defsym,NAME=a value",
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_src() {
+        let sysroot = TempDir::new("rust-src");
+        fs::create_dir_all(sysroot.join("lib/rustlib/src/rust")).unwrap();
+        assert!(!has_rust_src(&sysroot));
+
+        fs::create_dir_all(sysroot.join("lib/rustlib/src/rust/library")).unwrap();
+        assert!(has_rust_src(&sysroot));
+    }
+
+    #[test]
+    fn explicit_target_dir() {
+        let flags = |dir: &str| CargoFlags {
+            target_dir: Some(PathBuf::from(dir)),
+            ..Default::default()
+        };
+        assert_eq!(target_dir(&flags("/tmp/out")), Path::new("/tmp/out"));
+        assert_eq!(
+            target_dir(&flags("out")),
+            env::current_dir().unwrap().join("out")
+        );
+    }
+
+    #[test]
+    fn cargo_flags_in_commands() {
+        let args = |command: &Command| -> Vec<String> {
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        let parse = |cli: &[&str]| {
+            let command::Cargo::Input(mut input) =
+                <command::Cargo as clap::Parser>::try_parse_from(
+                    ["cargo", "nds"].iter().chain(cli),
+                )
+                .unwrap();
+            input.extract_cargo_flags();
+            input
+        };
+
+        let input = parse(&[
+            "clean",
+            "--release",
+            "--target-dir",
+            "/tmp/out",
+            "--manifest-path=game/Cargo.toml",
+            "--frozen",
+        ]);
+        let clean = args(&make_cargo_command(&input, &Config::default(), &None).unwrap());
+        assert_eq!(
+            clean,
+            [
+                "clean",
+                "--target-dir",
+                "/tmp/out",
+                "--manifest-path",
+                "game/Cargo.toml",
+                "--frozen",
+                "--release"
+            ]
+        );
+
+        let input = parse(&["--offline", "new", "game", "--target-dir", "/tmp/out"]);
+        let new = args(&make_cargo_command(&input, &Config::default(), &None).unwrap());
+        assert!(new.contains(&"--offline".to_string()));
+        assert!(!new.contains(&"--target-dir".to_string()));
+
+        let arm7 = arm7_cargo_command(
+            Path::new("arm7/Cargo.toml"),
+            Path::new(ARM7_TARGET_SPEC),
+            &["--release".to_string()],
+            &input.cargo_flags,
+        );
+        let arm7 = args(&arm7);
+        assert!(arm7.ends_with(&["--release".to_string(), "--offline".to_string()]));
+        assert!(arm7
+            .windows(2)
+            .any(|pair| pair == ["--target-dir", "/tmp/out"]));
+        assert!(arm7
+            .windows(2)
+            .any(|pair| pair == ["--manifest-path", "arm7/Cargo.toml"]));
+    }
+
+    #[test]
+    fn arm7_profile() {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        assert_eq!(profile_args(&args(&["--bin", "game"])), args(&[]));
+        assert_eq!(
+            profile_args(&args(&["-r", "--bin", "game"])),
+            args(&["--release"])
+        );
+        assert_eq!(
+            profile_args(&args(&["--profile=small"])),
+            args(&["--profile", "small"])
+        );
+    }
+
+    #[test]
+    fn test_results() {
+        assert_eq!(
+            test_result("test result: ok. 3 passed; 0 failed; 0 ignored"),
+            Some(true)
+        );
+        assert_eq!(
+            test_result("  test result: FAILED. 2 passed; 1 failed; 0 ignored\r"),
+            Some(false)
+        );
+        assert_eq!(test_result("test tests::it_works ... ok"), None);
+        assert_eq!(test_result("running 3 tests"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn emulator_stopped_after_tests() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("test-emulator");
+        let emulator = dir.join("emulator");
+        // Like a real emulator, it doesn't exit when the tests are done
+        fs::write(
+            &emulator,
+            "#!/bin/sh\necho 'running 1 test'\necho 'test result: ok. 1 passed'\nexec sleep 30\n",
+        )
+        .unwrap();
+        fs::set_permissions(&emulator, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = NDSConfig {
+            target_path: dir.join("tests.elf"),
+            ..Default::default()
+        };
+        let run_config = RunConfig {
+            address: None,
+            argv0: None,
+            server: false,
+            emulator: Some(EmulatorConfig::new(emulator.to_string_lossy().into_owned())),
+            loader: String::new(),
+            reset: None,
+        };
+
+        let start = std::time::Instant::now();
+        run_tests_in_emulator(&config, &run_config).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn loader_argv() {
+        let args = [
+            "cargo",
+            "nds",
+            "run",
+            "--argv0",
+            "sd:/game.nds",
+            "--",
+            "--release",
+            "--",
+            "xyz",
+            "--level=2",
+        ];
+        let command::Cargo::Input(input) =
+            <command::Cargo as clap::Parser>::try_parse_from(args).unwrap();
+        let CargoCmd::Run(run) = input.cmd else {
+            panic!("expected run")
+        };
+        let config = NDSConfig {
+            target_path: PathBuf::from("game.arm9.elf"),
+            ..Default::default()
+        };
+        let run_config = RunConfig {
+            address: None,
+            argv0: run.argv0.clone(),
+            server: false,
+            emulator: None,
+            loader: String::from("dslink"),
+            reset: None,
+        };
+
+        let command = loader_command(&config, &run, &run_config);
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            ["--argv0", "sd:/game.nds", "game.nds", "xyz", "--level=2"]
+        );
+    }
+
+    #[test]
+    fn error_variants() {
+        let dir = TempDir::new("errors");
+
+        let err = run_tool(&mut Command::new(dir.join("ndstool")), "ndstool").unwrap_err();
+        assert!(
+            matches!(&err, NdsError::ToolNotFound(tool) if tool == "ndstool"),
+            "{err:?}"
+        );
+
+        // cargo built no executable
+        let err = get_metadata(&[], &[], &CargoFlags::default()).unwrap_err();
+        assert!(matches!(err, NdsError::Executable(_)), "{err:?}");
+
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"game\"\n").unwrap();
+        fs::write(dir.join(CONFIG_FILE_NAME), "romfs = 3\n").unwrap();
+        let config = NDSConfig {
+            cargo_manifest_path: dir.join("Cargo.toml"),
+            ..Default::default()
+        };
+        let err = load_config(&config).unwrap_err();
+        assert!(matches!(err, NdsError::Config(_)), "{err:?}");
+        assert_eq!(err.exit_code(), 1);
+    }
+
+    #[test]
+    fn link_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(4));
+        assert_eq!(retry_delay(10), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn target_names() {
+        assert_eq!(target_name(target_spec(&None)), "armv5te-nintendo-ds");
+        assert_eq!(
+            target_name("specs/armv5te-nintendo-dsi.json"),
+            "armv5te-nintendo-dsi"
+        );
+        assert_eq!(target_name("armv5te-none-eabi"), "armv5te-none-eabi");
+    }
+
+    #[test]
+    fn sysroot_output() {
+        assert_eq!(
+            sysroot_from_output(b"/home/me/.rustup/toolchains/nightly\n".to_vec()),
+            Path::new("/home/me/.rustup/toolchains/nightly")
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let sysroot = sysroot_from_output(b"/opt/t\xe9l\xe9/nightly\r\n".to_vec());
+            assert_eq!(sysroot.as_os_str().as_bytes(), b"/opt/t\xe9l\xe9/nightly");
+        }
+    }
+
+    #[test]
+    fn versioned_paths() {
+        let version = Version::parse("1.2.0").unwrap();
+        assert_eq!(
+            versioned_path(Path::new("/game/target/release/game.nds"), &version),
+            Path::new("/game/target/release/game-1.2.0.nds")
+        );
+        assert_eq!(
+            versioned_path(
+                Path::new("dist/My.Game.dsi"),
+                &Version::parse("0.1.0-beta.2").unwrap()
+            ),
+            Path::new("dist/My.Game-0.1.0-beta.2.dsi")
+        );
+    }
+
+    #[test]
+    fn build_output_json() {
+        let output = BuildOutput {
+            nds: PathBuf::from("/game/target/armv5te-nintendo-ds/release/game.nds"),
+            arm9: PathBuf::from("/game/target/armv5te-nintendo-ds/release/game.elf"),
+            arm7: PathBuf::from(
+                "/opt/wonderful/thirdparty/blocksds/core/sys/default_arm7/arm7.elf",
+            ),
+            icon: Some(PathBuf::from("/game/icon.bmp")),
+            banner: None,
+            banner_text: BannerText {
+                title: Some(String::from("game")),
+                subtitle: None,
+                manufacturer: Some(String::from("Me")),
+            },
+            romfs: Some(PathBuf::from("/game/romfs")),
+            versioned: None,
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&output).unwrap();
+        assert_eq!(
+            json["nds"],
+            "/game/target/armv5te-nintendo-ds/release/game.nds"
+        );
+        assert_eq!(json["icon"], "/game/icon.bmp");
+        assert!(json["banner"].is_null());
+        assert_eq!(json["banner_text"]["title"], "game");
+        assert!(json["banner_text"]["subtitle"].is_null());
+        assert_eq!(json["romfs"], "/game/romfs");
+    }
+
+    #[test]
+    fn unusable_rustc() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        let msg = check_version_meta(Err(rustc_version::Error::CouldNotExecuteCommand(missing)))
+            .unwrap_err();
+        assert!(msg.contains("No such file or directory"));
+        assert!(msg.contains("rustup toolchain install nightly"));
+        assert!(msg.contains("cargo +nightly nds"));
+
+        let stable = rustc_version::version_meta_for(
+            "rustc 1.75.0 (82e1608df 2023-12-21)\nbinary: rustc\ncommit-hash: 82e1608df\ncommit-date: 2023-12-21\nhost: x86_64-unknown-linux-gnu\nrelease: 1.75.0",
+        );
+        assert!(check_version_meta(stable)
+            .unwrap_err()
+            .contains("rustup override set nightly"));
+
+        let bad_date = rustc_version::version_meta_for(
+            "rustc 1.80.0-nightly (1a2b3c4d5 someday)\nbinary: rustc\ncommit-hash: 1a2b3c4d5\ncommit-date: someday\nhost: x86_64-unknown-linux-gnu\nrelease: 1.80.0-nightly",
+        );
+        let msg = check_version_meta(bad_date).unwrap_err();
+        assert!(msg.contains("`someday`"), "{msg}");
+    }
+}
@@ -0,0 +1,149 @@
+//! cargo-nds' own progress messages, printed to stderr depending on the
+//! verbosity chosen with `--quiet` and `--verbose`. Errors and warnings are
+//! always printed, and don't go through here.
+//!
+//! Whether anything cargo-nds prints to stderr is colored is also decided
+//! here, so that `NO_COLOR` and redirected output are respected everywhere.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// How much cargo-nds prints about what it's doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Only errors and warnings.
+    Quiet,
+    /// Progress messages, such as the `.nds` file being built.
+    Normal,
+    /// Progress messages and the commands being run.
+    Verbose,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+/// Set the verbosity for the rest of the process.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// The current verbosity.
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Quiet,
+        1 => Level::Normal,
+        _ => Level::Verbose,
+    }
+}
+
+thread_local! {
+    static SILENCED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Print nothing from the current thread, whatever the verbosity, e.g. for
+/// work done in the background whose output would interleave with cargo's.
+pub fn silence_thread() {
+    SILENCED.with(|silenced| silenced.set(true));
+}
+
+/// Whether messages of this level are printed.
+pub fn enabled(level: Level) -> bool {
+    self::level() >= level && !SILENCED.with(Cell::get)
+}
+
+/// ANSI escape to make text bold.
+pub const BOLD: &str = "\x1b[1m";
+/// ANSI escape for red text.
+pub const RED: &str = "\x1b[31m";
+/// ANSI escape for green text.
+pub const GREEN: &str = "\x1b[32m";
+/// ANSI escape for yellow text.
+pub const YELLOW: &str = "\x1b[33m";
+/// ANSI escape for cyan text.
+pub const CYAN: &str = "\x1b[36m";
+/// ANSI escape to go back to plain text.
+pub const RESET: &str = "\x1b[0m";
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Set whether to color the output on stderr for the rest of the process, as
+/// given by [`CargoFlags::use_color`](crate::command::CargoFlags::use_color).
+pub fn set_color(color: bool) {
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// Whether the output on stderr is colored.
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// `text` with the ANSI `style`, or as is if the output isn't colored.
+pub fn paint(style: &str, text: &str) -> String {
+    if color() {
+        format!("{style}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Print a progress message to stderr, unless `--quiet` was given.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Normal) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Print a message to stderr only with `--verbose`.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {debug, info};
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::lock_color;
+
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert!(Level::Quiet < Level::Normal && Level::Normal < Level::Verbose);
+
+        set_level(Level::Quiet);
+        assert!(!enabled(Level::Normal));
+        set_level(Level::Verbose);
+        assert!(enabled(Level::Normal) && enabled(Level::Verbose));
+        set_level(Level::Normal);
+        assert_eq!(level(), Level::Normal);
+        assert!(!enabled(Level::Verbose));
+    }
+
+    #[test]
+    fn silenced_thread() {
+        std::thread::spawn(|| {
+            silence_thread();
+            assert!(!enabled(Level::Quiet));
+        })
+        .join()
+        .unwrap();
+        assert!(enabled(Level::Quiet));
+    }
+
+    #[test]
+    fn colors() {
+        let _lock = lock_color();
+        set_color(true);
+        assert_eq!(
+            paint(BOLD, "Running command:"),
+            "\x1b[1mRunning command:\x1b[0m"
+        );
+        set_color(false);
+        assert_eq!(paint(BOLD, "Running command:"), "Running command:");
+    }
+}
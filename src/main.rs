@@ -1,26 +1,116 @@
-use std::{env, process};
+use std::process;
 
-use cargo_nds::command::Cargo;
-use cargo_nds::{check_rust_version, run_cargo};
+use cargo_nds::command::{Cargo, CargoCmd, Test};
+use cargo_nds::doctor::run_doctor;
+use cargo_nds::dry_run;
+use cargo_nds::log;
+use cargo_nds::prepare;
+use cargo_nds::sarif::write_sarif;
+use cargo_nds::watch::{args_without, current_args, watch, watch_command_args};
+use cargo_nds::{
+    check_panic_strategy, check_rust_version, check_target, exit_code, explain_keep_going_failure,
+    explain_offline_failure, load_command_config, prevent_network_access, print_target,
+    resolve_target, run_cargo, NdsError,
+};
 use clap::Parser;
 
 fn main() {
-    check_rust_version();
     let Cargo::Input(mut input) = Cargo::parse();
+    log::set_level(input.log_level());
+    input.extract_cargo_flags();
+    log::set_color(input.cargo_flags.use_color());
+    prevent_network_access(&input.cargo_flags);
 
-    let message_format = match input.cmd.extract_message_format() {
-        Ok(fmt) => fmt,
-        Err(msg) => {
-            eprintln!("{msg}");
-            process::exit(1)
+    // The doctor reports on the rust version itself, and doesn't run cargo
+    if let CargoCmd::Doctor = input.cmd {
+        process::exit(if run_doctor() { 0 } else { 1 });
+    }
+
+    let nds_config = load_command_config(&input).unwrap_or_else(|e| e.exit());
+
+    if input.print_target {
+        print_target(&input, &nds_config).unwrap_or_else(|e| e.exit());
+        process::exit(0);
+    }
+
+    if input.cmd.no_build() {
+        if let CargoCmd::Test(_) = input.cmd {
+            NdsError::Input(String::from(
+                "--no-build is not supported by `cargo nds test`",
+            ))
+            .exit();
         }
+        let result = if input.dry_run {
+            dry_run::print_commands(&input, &nds_config, None)
+        } else {
+            input.cmd.run_cached_callback(&input.cargo_flags)
+        };
+        result.unwrap_or_else(|e| e.exit());
+        process::exit(0);
+    }
+
+    check_rust_version().unwrap_or_else(|e| e.exit());
+
+    // A dry run prints the commands of a single run instead of watching
+    let watched = match &input.cmd {
+        _ if input.dry_run => None,
+        CargoCmd::Test(Test { watch: true, .. }) => Some(watch(
+            &input.cargo_flags,
+            &args_without(&current_args(), "--watch"),
+            false,
+        )),
+        CargoCmd::Watch(options) => {
+            let args = watch_command_args(&current_args(), options.runs());
+            Some(watch(&input.cargo_flags, &args, options.clear))
+        }
+        _ => None,
+    };
+    if let Some(watched) = watched {
+        watched.unwrap_or_else(|e| e.exit());
+        process::exit(0);
+    }
+
+    let message_format = input
+        .cmd
+        .extract_message_format()
+        .unwrap_or_else(|msg| NdsError::Input(msg).exit());
+
+    let checked = resolve_target(&mut input, &nds_config)
+        .and_then(|()| check_target(&input))
+        .and_then(|()| check_panic_strategy(&input));
+    if let Err(e) = checked {
+        e.exit();
+    }
+
+    if input.dry_run {
+        dry_run::print_commands(&input, &nds_config, message_format).unwrap_or_else(|e| e.exit());
+        process::exit(0);
+    }
+
+    // The assets don't depend on the executable, so they're prepared meanwhile
+    let preparing = prepare::start(&input, &nds_config);
+
+    let (status, messages) = match run_cargo(&input, &nds_config, message_format) {
+        Ok(result) => result,
+        Err(e) => e.exit(),
     };
 
-    let (status, messages) = run_cargo(&input, message_format);
+    if let Some(path) = &input.emit_sarif {
+        write_sarif(&messages, path).unwrap_or_else(|e| NdsError::Write(path.clone(), e).exit());
+    }
 
+    // The build failed, so there is nothing for the callbacks to use, and the
+    // compiler errors were already printed
     if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+        explain_offline_failure(&input, &nds_config);
+        explain_keep_going_failure(&input, &messages);
+        process::exit(exit_code(status));
     }
 
-    input.cmd.run_callback(&messages);
+    if let Err(e) = input
+        .cmd
+        .run_callback(&messages, &input.cargo_flags, preparing)
+    {
+        e.exit();
+    }
 }
@@ -0,0 +1,234 @@
+//! The assets of the ROM (its RomFS and banner icons) don't depend on the
+//! executable, so they're prepared on a background thread while cargo builds
+//! it. [`build_nds_with_assets`](crate::build_nds_with_assets) is given them if
+//! none of their sources changed since, and prepares them itself otherwise,
+//! reporting any errors.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use crate::checksum::short_sha256;
+use crate::command::{CargoFlags, Input};
+use crate::config::{Config, CONFIG_FILE_NAME};
+use crate::log;
+use crate::watch::{take_snapshot, Snapshot};
+use crate::{
+    default_icon, find_blocksds, find_romfs_dirs, get_icons, include_dir_paths, package_dir,
+    stage_romfs, target_dir, Icons, NDSConfig,
+};
+
+/// Assets being prepared in the background, see [`start`].
+pub struct Preparing(JoinHandle<Option<Preparation>>);
+
+/// Assets prepared while the executable was built.
+#[derive(Default)]
+pub struct Prepared {
+    /// The directory to pass to ndstool as the RomFS, if it was prepared.
+    pub romfs: Option<PathBuf>,
+    /// The banner icons, if they were prepared.
+    pub icons: Option<Icons>,
+}
+
+/// Prepared assets, and what they were prepared from.
+struct Preparation {
+    /// The manifest of the package the assets belong to.
+    manifest_path: PathBuf,
+    /// The RomFS directories.
+    dirs: Vec<PathBuf>,
+    /// The icons and package config.
+    files: Vec<PathBuf>,
+    /// The modification times of the sources before preparing the assets.
+    snapshot: Snapshot,
+    prepared: Prepared,
+}
+
+impl Preparation {
+    fn snapshot(&self) -> Snapshot {
+        sources_snapshot(&self.dirs, &self.files)
+    }
+}
+
+/// Start preparing the assets of the package cargo builds, whose config is
+/// `nds_config`, if the command builds a ROM.
+pub fn start(input: &Input, nds_config: &Config) -> Option<Preparing> {
+    if !input.cmd.builds_rom() {
+        return None;
+    }
+
+    let cargo_flags = input.cargo_flags.clone();
+    let nds_config = nds_config.clone();
+    Some(Preparing(thread::spawn(move || {
+        // Errors and progress are reported if the assets are prepared again
+        log::silence_thread();
+        prepare(&cargo_flags, &nds_config)
+    })))
+}
+
+impl Preparing {
+    /// Wait for the assets. Returns `None` if nothing was prepared, if they
+    /// belong to another package than the one of `config` (e.g. with
+    /// `--package`), or if any of their sources changed since.
+    pub fn take(self, config: &NDSConfig) -> Option<Prepared> {
+        let preparation = self.0.join().ok()??;
+
+        if preparation.manifest_path != config.cargo_manifest_path
+            || preparation.snapshot() != preparation.snapshot
+        {
+            return None;
+        }
+        Some(preparation.prepared)
+    }
+}
+
+/// Prepare what can be of the assets, without reporting errors: the assets
+/// are prepared again when building the ROM, and the errors reported then.
+fn prepare(cargo_flags: &CargoFlags, nds_config: &Config) -> Option<Preparation> {
+    let package_dir = package_dir(cargo_flags);
+    let manifest_path = package_dir.join("Cargo.toml");
+
+    let name = package_dir.file_name().unwrap_or_default();
+    let config = NDSConfig {
+        icon: default_icon(&package_dir, &find_blocksds()),
+        target_path: assets_dir(cargo_flags, &manifest_path).join(name),
+        cargo_manifest_path: manifest_path.clone(),
+        ..Default::default()
+    };
+
+    // Missing RomFS directories are left for `build_nds` to report
+    let include_dirs = include_dir_paths(&package_dir, nds_config);
+    let dirs = find_romfs_dirs(&package_dir, nds_config, &include_dirs)
+        .ok()
+        .filter(|dirs| !dirs.is_empty());
+    let mut files: Vec<PathBuf> = nds_config
+        .ds_icon()
+        .into_iter()
+        .chain(nds_config.icons.animated.iter().map(PathBuf::as_path))
+        .map(|icon| package_dir.join(icon))
+        .collect();
+    if nds_config.ds_icon().is_none() {
+        files.push(PathBuf::from(&config.icon));
+    }
+    files.extend([manifest_path.clone(), package_dir.join(CONFIG_FILE_NAME)]);
+
+    let snapshot = sources_snapshot(dirs.as_deref().unwrap_or_default(), &files);
+    let prepared = Prepared {
+        romfs: dirs
+            .clone()
+            .and_then(|dirs| stage_romfs(&config, nds_config, dirs).ok()),
+        icons: get_icons(&config, nds_config).ok(),
+    };
+
+    let preparation = Preparation {
+        manifest_path,
+        dirs: dirs.unwrap_or_default(),
+        files,
+        snapshot,
+        prepared,
+    };
+    // A source was modified while it was being copied or converted
+    if preparation.snapshot() != preparation.snapshot {
+        return None;
+    }
+    Some(preparation)
+}
+
+/// The directory the assets of the package with the given manifest are
+/// prepared in, since the executable's isn't known yet. It's named after the
+/// package directory and a hash of the manifest path, so that packages in
+/// directories of the same name don't share it.
+fn assets_dir(cargo_flags: &CargoFlags, manifest_path: &Path) -> PathBuf {
+    let name = manifest_path
+        .parent()
+        .and_then(Path::file_name)
+        .unwrap_or_default();
+    target_dir(cargo_flags).join("nds-assets").join(format!(
+        "{}-{}",
+        name.to_string_lossy(),
+        short_sha256(manifest_path.as_os_str().as_encoded_bytes())
+    ))
+}
+
+/// The modification times of the files in `dirs`, and of `files`.
+fn sources_snapshot(dirs: &[PathBuf], files: &[PathBuf]) -> Snapshot {
+    let mut snapshot = take_snapshot(dirs, &[]);
+    for file in files {
+        if let Ok(modified) = fs::metadata(file).and_then(|metadata| metadata.modified()) {
+            snapshot.insert(file.clone(), modified);
+        }
+    }
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::load_package_config;
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn prepared_romfs() {
+        let dir = TempDir::new("prepare");
+        fs::create_dir_all(dir.join("game/romfs")).unwrap();
+        fs::create_dir_all(dir.join("game/shared")).unwrap();
+        fs::write(dir.join("game/Cargo.toml"), "[package]\nname = \"game\"\n").unwrap();
+        fs::write(
+            dir.join("game/nds.toml"),
+            "romfs = [\"romfs\", \"shared\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("game/romfs/level.bin"), "level").unwrap();
+        fs::write(dir.join("game/shared/font.bin"), "font").unwrap();
+
+        let cargo_flags = CargoFlags {
+            manifest_path: Some(dir.join("game/Cargo.toml")),
+            target_dir: Some(dir.join("target")),
+            ..Default::default()
+        };
+        let nds_config = load_package_config(&cargo_flags).unwrap();
+        let preparation = prepare(&cargo_flags, &nds_config).unwrap();
+        let romfs = preparation.prepared.romfs.as_ref().unwrap();
+        let assets = assets_dir(&cargo_flags, &dir.join("game/Cargo.toml"));
+        assert!(assets.starts_with(dir.join("target/nds-assets")));
+        assert_eq!(romfs, &assets.join("game.romfs"));
+        assert_eq!(fs::read_to_string(romfs.join("font.bin")).unwrap(), "font");
+        assert_eq!(preparation.snapshot(), preparation.snapshot);
+
+        // Modified sources make the prepared assets stale
+        let level = fs::File::create(dir.join("game/romfs/level.bin")).unwrap();
+        level
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_ne!(preparation.snapshot(), preparation.snapshot);
+        fs::write(dir.join("game/romfs/new.bin"), "").unwrap();
+        assert!(!preparation
+            .snapshot()
+            .keys()
+            .all(|path| preparation.snapshot.contains_key(path)));
+
+        // Packages in directories of the same name get their own assets
+        let other = assets_dir(&cargo_flags, &dir.join("other/game/Cargo.toml"));
+        assert_ne!(other, assets);
+        assert!(other
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("game-"));
+
+        // Missing RomFS directories are left for `build_nds` to report
+        fs::write(
+            dir.join("game/nds.toml"),
+            "romfs = [\"romfs\", \"missing\"]\n",
+        )
+        .unwrap();
+        let nds_config = load_package_config(&cargo_flags).unwrap();
+        assert!(prepare(&cargo_flags, &nds_config)
+            .unwrap()
+            .prepared
+            .romfs
+            .is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The files of the RomFS, by their path in the RomFS, with their source path.
+pub type RomfsFiles = BTreeMap<PathBuf, PathBuf>;
+
+/// Files left out of the RomFS unless `romfs_exclude` is set: OS metadata,
+/// editor backups and swap files, and version control directories.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "desktop.ini",
+    "*~",
+    "*.swp",
+    "*.swo",
+    "*.tmp",
+    "*.bak",
+    ".git",
+    ".svn",
+];
+
+/// Which RomFS files to embed, from glob patterns matched against their path
+/// in the RomFS. `*` and `?` don't match `/`, and `**` matches any number of
+/// directories. A pattern without `/` matches any file or directory name.
+pub struct Filter<'a> {
+    pub include: &'a [String],
+    pub exclude: &'a [String],
+}
+
+impl Filter<'_> {
+    /// Whether the file at `path` in the RomFS is embedded.
+    pub fn matches(&self, path: &Path) -> bool {
+        let path: Vec<String> = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| glob_match(pattern, &path)))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| glob_match(pattern, &path))
+    }
+
+    /// Remove the files which aren't embedded, returning how many were removed.
+    pub fn apply(&self, files: &mut RomfsFiles) -> usize {
+        let before = files.len();
+        files.retain(|path, _| self.matches(path));
+        before - files.len()
+    }
+}
+
+fn glob_match(pattern: &str, path: &[&str]) -> bool {
+    if pattern.contains('/') {
+        let pattern: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+        match_components(&pattern, path)
+    } else {
+        path.iter()
+            .any(|name| match_name(pattern.as_bytes(), name.as_bytes()))
+    }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                match_name(first.as_bytes(), name.as_bytes()) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_name(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_name(rest, &name[1..]),
+    }
+}
+
+/// Strings in Rust sources which hint at files being read from the RomFS.
+const NITROFS_HINTS: &[&str] = &["nitroFSInit", "nitro_fs_init", "nitro:/"];
+
+/// Whether any Rust source in `src_dir` seems to read files from the RomFS.
+pub fn uses_nitrofs(src_dir: &Path) -> bool {
+    let mut files = Vec::new();
+    if walk(src_dir, &mut files).is_err() {
+        return false;
+    }
+
+    files
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .any(|source| NITROFS_HINTS.iter().any(|hint| source.contains(hint)))
+}
+
+/// Collect the files of every source directory, merged into a single tree.
+/// A file at the same path in two sources is an error, listing both.
+pub fn collect_files(sources: &[PathBuf]) -> Result<RomfsFiles, String> {
+    let mut files = RomfsFiles::new();
+
+    for source in sources {
+        let mut source_files = Vec::new();
+        walk(source, &mut source_files)
+            .map_err(|e| format!("could not read {}: {e}", source.display()))?;
+
+        for file in source_files {
+            let relative = file.strip_prefix(source).unwrap().to_path_buf();
+            if let Some(existing) = files.get(&relative) {
+                return Err(format!(
+                    "{} is in several RomFS directories: {} and {}",
+                    relative.display(),
+                    existing.display(),
+                    file.display()
+                ));
+            }
+            files.insert(relative, file);
+        }
+    }
+
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Copy the files into a fresh `dst` directory, for ndstool to use as the RomFS.
+pub fn stage(files: &RomfsFiles, dst: &Path) -> Result<(), String> {
+    match fs::remove_dir_all(dst) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("could not remove {}: {e}", dst.display()));
+        }
+        _ => {}
+    }
+    fs::create_dir_all(dst).map_err(|e| format!("could not create {}: {e}", dst.display()))?;
+
+    for (relative, source) in files {
+        let target = dst.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("could not create {}: {e}", parent.display()))?;
+        }
+        fs::copy(source, &target).map_err(|e| {
+            format!(
+                "could not copy {} to {}: {e}",
+                source.display(),
+                target.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn merge_sources() {
+        let dir = TempDir::new("romfs");
+        let (assets, shared) = (dir.join("assets"), dir.join("shared"));
+        fs::create_dir_all(assets.join("sprites")).unwrap();
+        fs::create_dir_all(shared.join("sprites")).unwrap();
+        fs::write(assets.join("sprites/player.bin"), "player").unwrap();
+        fs::write(shared.join("sprites/enemy.bin"), "enemy").unwrap();
+        fs::write(shared.join("font.bin"), "font").unwrap();
+
+        let files = collect_files(&[assets.clone(), shared.clone()]).unwrap();
+        assert_eq!(files.len(), 3);
+
+        let staged = dir.join("staged");
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join("stale.bin"), "").unwrap();
+        stage(&files, &staged).unwrap();
+        assert_eq!(
+            fs::read_to_string(staged.join("sprites/player.bin")).unwrap(),
+            "player"
+        );
+        assert_eq!(
+            fs::read_to_string(staged.join("sprites/enemy.bin")).unwrap(),
+            "enemy"
+        );
+        assert!(staged.join("font.bin").is_file());
+        assert!(!staged.join("stale.bin").exists());
+
+        fs::write(assets.join("font.bin"), "other font").unwrap();
+        let err = collect_files(&[assets.clone(), shared.clone()]).unwrap_err();
+        assert!(
+            err.contains(&assets.join("font.bin").display().to_string()),
+            "{err}"
+        );
+        assert!(
+            err.contains(&shared.join("font.bin").display().to_string()),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn detect_nitrofs() {
+        let dir = TempDir::new("nitrofs");
+        fs::create_dir_all(dir.join("level")).unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+        assert!(!uses_nitrofs(&dir));
+
+        fs::write(
+            dir.join("level/load.rs"),
+            r#"let file = File::open("nitro:/level.bin");"#,
+        )
+        .unwrap();
+        assert!(uses_nitrofs(&dir));
+        assert!(!uses_nitrofs(&dir.join("missing")));
+    }
+
+    #[test]
+    fn filter_files() {
+        let defaults: Vec<String> = DEFAULT_EXCLUDES.iter().map(ToString::to_string).collect();
+        let filter = Filter {
+            include: &[],
+            exclude: &defaults,
+        };
+        assert!(filter.matches(Path::new("sprites/player.bin")));
+        assert!(!filter.matches(Path::new("sprites/.DS_Store")));
+        assert!(!filter.matches(Path::new("level.txt~")));
+        assert!(!filter.matches(Path::new(".git/HEAD")));
+
+        let include = ["**/*.bin".to_string(), "font.png".to_string()];
+        let exclude = ["drafts/**".to_string()];
+        let filter = Filter {
+            include: &include,
+            exclude: &exclude,
+        };
+        assert!(filter.matches(Path::new("player.bin")));
+        assert!(filter.matches(Path::new("sprites/player.bin")));
+        assert!(filter.matches(Path::new("fonts/font.png")));
+        assert!(!filter.matches(Path::new("sprites/player.png")));
+        assert!(!filter.matches(Path::new("drafts/level.bin")));
+
+        let mut files: RomfsFiles = ["a.bin", "b.png", "drafts/c.bin"]
+            .into_iter()
+            .map(|path| (PathBuf::from(path), PathBuf::from(path)))
+            .collect();
+        assert_eq!(filter.apply(&mut files), 2);
+        assert_eq!(files.len(), 1);
+    }
+}
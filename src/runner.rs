@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Whether `target.<target>.runner` is set in cargo's configuration, read
+/// directly instead of running cargo. `target` is the name cargo uses for the
+/// target, i.e. the file stem of a target spec.
+///
+/// The sources are, as in cargo, the `--config` arguments (inline TOML or
+/// paths of config files), the `CARGO_TARGET_<TARGET>_RUNNER` environment
+/// variable and cargo's config files. `target.'cfg(..)'.runner` tables are not
+/// evaluated.
+pub fn runner_configured(cwd: &Path, config: &[String], target: &str) -> bool {
+    let env_var = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        target.to_uppercase().replace(['-', '.'], "_")
+    );
+    if env::var_os(env_var).is_some() {
+        return true;
+    }
+
+    config
+        .iter()
+        .filter_map(|arg| {
+            arg.parse::<toml::Table>()
+                .ok()
+                .or_else(|| read_config(&cwd.join(arg)))
+        })
+        .chain(
+            config_files(cwd)
+                .iter()
+                .filter_map(|path| read_config(path)),
+        )
+        .any(|table| has_runner(&table, target))
+}
+
+fn read_config(path: &Path) -> Option<toml::Table> {
+    fs::read_to_string(path).ok()?.parse().ok()
+}
+
+fn has_runner(config: &toml::Table, target: &str) -> bool {
+    config
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .and_then(|targets| targets.get(target))
+        .and_then(toml::Value::as_table)
+        .is_some_and(|target| target.contains_key("runner"))
+}
+
+/// Every file cargo may read its configuration from, whether it exists or not,
+/// see <https://doc.rust-lang.org/cargo/reference/config.html#hierarchical-structure>.
+fn config_files(cwd: &Path) -> Vec<PathBuf> {
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+
+    cwd.ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(cargo_home)
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn read_runner_from_config() {
+        let dir = TempDir::new("runner");
+        let project = dir.join("game");
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        let target = "armv5te-nintendo-ds-test";
+
+        assert!(!runner_configured(&project, &[], target));
+        assert!(runner_configured(
+            &project,
+            &[format!("target.{target}.runner='melonDS'")],
+            target
+        ));
+
+        fs::write(
+            dir.join("runner.toml"),
+            format!("[target.{target}]\nrunner = 'y'\n"),
+        )
+        .unwrap();
+        assert!(runner_configured(
+            &project,
+            &["../runner.toml".to_string()],
+            target
+        ));
+
+        fs::write(
+            project.join(".cargo/config.toml"),
+            "[target.armv5te-none-eabi]\nrunner = 'y'\n",
+        )
+        .unwrap();
+        assert!(!runner_configured(&project, &[], target));
+
+        fs::create_dir_all(dir.join(".cargo")).unwrap();
+        fs::write(
+            dir.join(".cargo/config"),
+            format!("[target.{target}]\nrunner = 'y'\n"),
+        )
+        .unwrap();
+        assert!(runner_configured(&project, &[], target));
+    }
+}
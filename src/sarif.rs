@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use cargo_metadata::diagnostic::{Diagnostic, DiagnosticLevel};
+use cargo_metadata::Message;
+use serde_json::{json, Value};
+
+/// Convert the compiler diagnostics among cargo's messages to a
+/// [SARIF](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html) log,
+/// as used by code scanning tools.
+pub fn to_sarif(messages: &[Message]) -> Value {
+    let diagnostics: Vec<&Diagnostic> = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::CompilerMessage(msg) => Some(&msg.message),
+            _ => None,
+        })
+        .collect();
+
+    let rules: BTreeSet<&str> = diagnostics.iter().map(|diag| rule_id(diag)).collect();
+    let results: Vec<Value> = diagnostics.iter().map(|diag| result(diag)).collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rustc",
+                    "informationUri": "https://doc.rust-lang.org/rustc/",
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// Write the SARIF log for the given messages to a file.
+pub fn write_sarif(messages: &[Message], path: &Path) -> std::io::Result<()> {
+    let sarif = serde_json::to_string_pretty(&to_sarif(messages))?;
+    fs::write(path, sarif)
+}
+
+fn rule_id(diag: &Diagnostic) -> &str {
+    diag.code
+        .as_ref()
+        .map_or("rustc", |code| code.code.as_str())
+}
+
+fn result(diag: &Diagnostic) -> Value {
+    let level = match diag.level {
+        DiagnosticLevel::Error | DiagnosticLevel::Ice => "error",
+        DiagnosticLevel::Warning => "warning",
+        DiagnosticLevel::Note | DiagnosticLevel::Help => "note",
+        _ => "none",
+    };
+
+    let locations: Vec<Value> = diag
+        .spans
+        .iter()
+        .filter(|span| span.is_primary)
+        .map(|span| {
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": span.file_name },
+                    "region": {
+                        "startLine": span.line_start,
+                        "startColumn": span.column_start,
+                        "endLine": span.line_end,
+                        "endColumn": span.column_end,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    json!({
+        "ruleId": rule_id(diag),
+        "level": level,
+        "message": { "text": diag.rendered.as_deref().unwrap_or(&diag.message) },
+        "locations": locations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARNING: &str = r#"{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///foo)","manifest_path":"/foo/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"foo","src_path":"/foo/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"warning: unused variable: `x`\n","children":[],"code":{"code":"unused_variables","explanation":null},"level":"warning","message":"unused variable: `x`","spans":[{"byte_end":10,"byte_start":9,"column_end":10,"column_start":9,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":null,"line_end":2,"line_start":2,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}]}}"#;
+
+    #[test]
+    fn convert_warning() {
+        let messages: Vec<Message> = Message::parse_stream(WARNING.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let sarif = to_sarif(&messages);
+        let run = &sarif["runs"][0];
+
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "unused_variables");
+
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "unused_variables");
+        assert_eq!(result["level"], "warning");
+        assert_eq!(result["message"]["text"], "warning: unused variable: `x`\n");
+
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/main.rs");
+        assert_eq!(location["region"]["startLine"], 2);
+        assert_eq!(location["region"]["startColumn"], 9);
+    }
+}
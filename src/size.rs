@@ -0,0 +1,180 @@
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+
+use crate::{print_command, NDSConfig, NdsError};
+
+/// Size of a single section of an ELF file, as reported by `arm-none-eabi-size`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub size: u64,
+    pub addr: u64,
+}
+
+/// Sizes of the sections of an ELF file.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ElfSize {
+    pub sections: Vec<Section>,
+    pub total: u64,
+}
+
+/// Sizes of everything that goes into the `.nds` file.
+#[derive(Serialize, Debug)]
+pub struct SizeReport {
+    pub arm9: ElfSize,
+    pub arm7: ElfSize,
+    pub rom_size: u64,
+}
+
+impl SizeReport {
+    /// Measure the ELF files and `.nds` file described by `config`.
+    pub fn new(config: &NDSConfig) -> Result<Self, NdsError> {
+        let rom_size = std::fs::metadata(config.path_nds())
+            .map(|metadata| metadata.len())
+            .map_err(|e| NdsError::Read(config.path_nds(), e))?;
+
+        Ok(Self {
+            arm9: elf_size(&config.path_arm9())?,
+            arm7: elf_size(&config.path_arm7())?,
+            rom_size,
+        })
+    }
+
+    /// Print the report as a table, one per ELF file.
+    pub fn print(&self) {
+        println!("{self}");
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, elf) in [("ARM9", &self.arm9), ("ARM7", &self.arm7)] {
+            writeln!(f, "{name}:")?;
+            writeln!(f, "  {:<24} {:>10} {:>10}", "section", "size", "addr")?;
+            for section in &elf.sections {
+                writeln!(
+                    f,
+                    "  {:<24} {:>10} {:>#10x}",
+                    section.name, section.size, section.addr
+                )?;
+            }
+            writeln!(f, "  {:<24} {:>10}\n", "Total", elf.total)?;
+        }
+
+        write!(f, "ROM size: {} bytes", self.rom_size)
+    }
+}
+
+/// Get the section sizes of an ELF file using `arm-none-eabi-size`.
+/// This will fail if `arm-none-eabi-size` is not in $PATH.
+pub(crate) fn elf_size(path: &Path) -> Result<ElfSize, NdsError> {
+    let mut command = Command::new("arm-none-eabi-size");
+    command
+        .args(["-A", "-d"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+
+    print_command(&command);
+
+    let output = command
+        .output()
+        .map_err(|e| NdsError::spawn("arm-none-eabi-size", e))?;
+
+    if !output.status.success() {
+        return Err(NdsError::Failed {
+            tool: String::from("arm-none-eabi-size"),
+            status: output.status,
+            details: None,
+        });
+    }
+
+    Ok(parse_sysv(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the "System V" format output of `size -A -d`.
+fn parse_sysv(output: &str) -> ElfSize {
+    let mut sections = Vec::new();
+    let mut total = 0;
+
+    for line in output.lines() {
+        let mut columns = line.split_whitespace();
+        let (Some(name), Some(size)) = (columns.next(), columns.next()) else {
+            continue;
+        };
+        let Ok(size) = size.parse() else {
+            // Header lines, e.g. "section size addr"
+            continue;
+        };
+
+        if name == "Total" {
+            total = size;
+        } else if let Some(Ok(addr)) = columns.next().map(str::parse) {
+            sections.push(Section {
+                name: name.to_string(),
+                size,
+                addr,
+            });
+        }
+    }
+
+    ElfSize { sections, total }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_output() {
+        let output = "\
+target/armv5te-nintendo-ds/debug/hello.arm9.elf  :
+section             size       addr
+.crt0                512   33554432
+.text              74520   33554944
+.data               1024   33629464
+.bss                2048   33630488
+.comment              18          0
+Total              78122
+
+
+";
+
+        assert_eq!(
+            parse_sysv(output),
+            ElfSize {
+                sections: vec![
+                    Section {
+                        name: ".crt0".to_string(),
+                        size: 512,
+                        addr: 0x200_0000,
+                    },
+                    Section {
+                        name: ".text".to_string(),
+                        size: 74520,
+                        addr: 33554944,
+                    },
+                    Section {
+                        name: ".data".to_string(),
+                        size: 1024,
+                        addr: 33629464,
+                    },
+                    Section {
+                        name: ".bss".to_string(),
+                        size: 2048,
+                        addr: 33630488,
+                    },
+                    Section {
+                        name: ".comment".to_string(),
+                        size: 18,
+                        addr: 0,
+                    },
+                ],
+                total: 78122,
+            }
+        );
+    }
+}
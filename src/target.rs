@@ -0,0 +1,272 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// The target spec used when no `--target` is given, as written by `cargo nds new`.
+pub const DEFAULT_TARGET_SPEC: &str = "armv5te-nintendo-ds.json";
+
+/// The target passed to cargo: the `--target` override, or the default spec.
+pub fn target_spec(target: &Option<String>) -> &str {
+    target.as_deref().unwrap_or(DEFAULT_TARGET_SPEC)
+}
+
+/// The name cargo uses for the target's output directory: the file stem of a
+/// target spec JSON file, or the target name itself.
+pub fn target_name(target_spec: &str) -> String {
+    if target_spec.ends_with(".json") {
+        Path::new(target_spec)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        target_spec.to_string()
+    }
+}
+
+/// The target spec of the ARM7 executable built from `arm7_crate`.
+pub const ARM7_TARGET_SPEC: &str = "armv4t-nintendo-ds-arm7.json";
+
+/// Where `cargo nds new` writes [`CARGO_CONFIG`] in the project.
+pub const CARGO_CONFIG_PATH: &str = ".cargo/config.toml";
+
+/// The files added to a project by `cargo nds new` and `cargo nds init`, other
+/// than its sources, by their path in the project.
+pub(crate) const SCAFFOLD_FILES: &[(&str, &str)] = &[
+    (DEFAULT_TARGET_SPEC, TARGET_JSON),
+    (CARGO_CONFIG_PATH, CARGO_CONFIG),
+];
+
+/// Find the default target spec in `dir` or one of its parents.
+pub(crate) fn find_target_spec(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(DEFAULT_TARGET_SPEC))
+        .find(|path| path.is_file())
+}
+
+/// Write a built-in target spec to `nds/` in the target directory, returning
+/// its path. The file is only rewritten when it differs, so that cargo doesn't
+/// rebuild everything.
+pub(crate) fn write_builtin_target_spec(
+    target_dir: &Path,
+    name: &str,
+    spec: &str,
+) -> io::Result<PathBuf> {
+    let path = target_dir.join("nds").join(name);
+    if fs::read_to_string(&path).is_ok_and(|existing| existing == spec) {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, spec)?;
+    Ok(path)
+}
+
+/// The key settings of a target spec JSON file, as `(name, value)` pairs: the
+/// LLVM target, CPU features, floating point ABI, atomics and panic strategy.
+pub fn describe_spec(json: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let spec: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let field = |key: &str| match &spec[key] {
+        serde_json::Value::Null => "(not set)".to_string(),
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+
+    let features = field("features");
+    let float = if features.split(',').any(|feature| feature == "+soft-float") {
+        "soft"
+    } else {
+        "hard"
+    };
+
+    Ok(vec![
+        ("llvm-target", field("llvm-target")),
+        ("os", field("os")),
+        ("features", features),
+        ("float", float.to_string()),
+        ("max-atomic-width", field("max-atomic-width")),
+        ("panic-strategy", field("panic-strategy")),
+    ])
+}
+
+/// Whether a target spec has the same settings as [`TARGET_JSON`], ignoring
+/// formatting.
+pub fn is_builtin_spec(json: &str) -> bool {
+    let parse = |json| serde_json::from_str::<serde_json::Value>(json).ok();
+    parse(json).is_some() && parse(json) == parse(TARGET_JSON)
+}
+
+/// Target spec of the ARM9 executable, written as [`DEFAULT_TARGET_SPEC`] by
+/// `cargo nds new`, or to the target directory when a package has none.
+pub(crate) const TARGET_JSON: &str = r#"{
+    "abi": "eabi",
+    "arch": "arm",
+    "data-layout": "e-m:e-p:32:32-Fi8-i64:64-v128:64:128-a:0:32-n32-S64",
+    "env" : "picolibc",
+    "exe-suffix" : ".arm9.elf",
+    "is-builtin": false,
+    "linker": "arm-none-eabi-gcc",
+    "llvm-target": "armv5te-none-gnu",
+    "relocation-model": "static",
+    "target-endian": "little",
+    "target-pointer-width": "32",
+    "target-c-int-width": "32",
+    "executables": true,
+    "linker-flavor": "gcc",
+    "max-atomic-width": 32,
+    "disable-redzone": true,
+    "emit-debug-gdb-scripts": false,
+    "features" : "+soft-float,+strict-align,+atomics-32",
+    "panic-strategy" : "abort",
+    "linker-is-gnu": true,
+    "target-family": [
+        "unix"
+      ],
+    "no-default-libraries": false,
+    "main-needs-argc-argv":"false",
+    "pre-link-args": {
+        "gcc": [
+          "--data-sections",
+          "-march=armv5te",
+          "-mthumb",
+          "-mcpu=arm946e-s+nofp",
+          "-mthumb-interwork",
+          "-Wl,--gc-sections"
+        ]
+      },
+      "post-link-args" : {
+        "gcc": [
+          "-Wl,--no-warn-rwx-segments",
+          "-Wl,--allow-multiple-definition"
+        ]
+      },
+      "late-link-args": {
+        "gcc": [
+            "-lgcc"
+        ]
+    },
+    "vendor" : "nintendo",
+    "os" : "nintendo_ds_arm9"
+  }  
+"#;
+
+/// Target spec of the ARM7 executable built from `arm7_crate`, see [`TARGET_JSON`].
+pub(crate) const ARM7_TARGET_JSON: &str = r#"{
+    "abi": "eabi",
+    "arch": "arm",
+    "data-layout": "e-m:e-p:32:32-Fi8-i64:64-v128:64:128-a:0:32-n32-S64",
+    "env" : "picolibc",
+    "exe-suffix" : ".arm7.elf",
+    "is-builtin": false,
+    "linker": "arm-none-eabi-gcc",
+    "llvm-target": "armv4t-none-eabi",
+    "relocation-model": "static",
+    "target-endian": "little",
+    "target-pointer-width": "32",
+    "target-c-int-width": "32",
+    "executables": true,
+    "linker-flavor": "gcc",
+    "max-atomic-width": 32,
+    "disable-redzone": true,
+    "emit-debug-gdb-scripts": false,
+    "features" : "+soft-float,+strict-align,+atomics-32",
+    "panic-strategy" : "abort",
+    "linker-is-gnu": true,
+    "target-family": [
+        "unix"
+      ],
+    "no-default-libraries": false,
+    "main-needs-argc-argv":"false",
+    "pre-link-args": {
+        "gcc": [
+          "--data-sections",
+          "-march=armv4t",
+          "-mthumb",
+          "-mcpu=arm7tdmi",
+          "-mthumb-interwork",
+          "-Wl,--gc-sections"
+        ]
+      },
+      "post-link-args" : {
+        "gcc": [
+          "-Wl,--no-warn-rwx-segments",
+          "-Wl,--allow-multiple-definition"
+        ]
+      },
+      "late-link-args": {
+        "gcc": [
+            "-lgcc"
+        ]
+    },
+    "vendor" : "nintendo",
+    "os" : "nintendo_ds_arm7"
+  }
+"#;
+
+pub(crate) const CARGO_CONFIG: &str = r#"[profile.release]
+codegen-units = 1
+opt-level=3
+debug-assertions=false
+strip = "debuginfo"
+lto = true
+overflow-checks=false
+
+[profile.dev]
+codegen-units = 1
+debug=2
+opt-level=3
+debug-assertions=false
+lto = true
+overflow-checks=false
+strip = false
+"#;
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn builtin_specs() {
+        for (spec, os, suffix) in [
+            (TARGET_JSON, "nintendo_ds_arm9", ".arm9.elf"),
+            (ARM7_TARGET_JSON, "nintendo_ds_arm7", ".arm7.elf"),
+        ] {
+            let spec: serde_json::Value = serde_json::from_str(spec).unwrap();
+            assert_eq!(spec["os"], os);
+            assert_eq!(spec["exe-suffix"], suffix);
+            assert_eq!(spec["panic-strategy"], "abort");
+        }
+
+        let config: toml::Table = CARGO_CONFIG.parse().unwrap();
+        assert!(config["profile"].get("release").is_some());
+        assert_eq!(target_name(DEFAULT_TARGET_SPEC), "armv5te-nintendo-ds");
+        assert_eq!(target_name(ARM7_TARGET_SPEC), "armv4t-nintendo-ds-arm7");
+    }
+
+    #[test]
+    fn builtin_spec_path() {
+        let dir = TempDir::new("builtin-spec");
+
+        let path = write_builtin_target_spec(&dir, DEFAULT_TARGET_SPEC, TARGET_JSON).unwrap();
+        assert_eq!(path, dir.join("nds").join(DEFAULT_TARGET_SPEC));
+        assert_eq!(fs::read_to_string(&path).unwrap(), TARGET_JSON);
+    }
+
+    #[test]
+    fn describe_specs() {
+        let settings = describe_spec(TARGET_JSON).unwrap();
+        assert!(settings.contains(&("float", "soft".to_string())));
+        assert!(settings.contains(&("max-atomic-width", "32".to_string())));
+        assert!(settings.contains(&("llvm-target", "armv5te-none-gnu".to_string())));
+
+        let stale = TARGET_JSON.replace("+soft-float,", "");
+        assert!(describe_spec(&stale)
+            .unwrap()
+            .contains(&("float", "hard".to_string())));
+        assert!(is_builtin_spec(&TARGET_JSON.replace("    ", "  ")));
+        assert!(!is_builtin_spec(&stale));
+        assert!(!is_builtin_spec("not json"));
+        assert!(describe_spec("{").is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::romfs::{self, Filter};
+
+/// The `src/main.rs` of the default template: a console printing "Hello World!".
+pub const CONSOLE_MAIN_RS: &str = r#"#![no_main]
+#![no_std]
+extern crate alloc;
+use libnds_sys::arm9_bindings::*;
+use libnds_sys::*;
+use core::ffi::*;
+#[no_mangle]
+extern "C" fn main() -> c_int
+{
+    unsafe
+    {
+        consoleDemoInit();       
+        println!("Hello World!");
+        loop {
+            swiWaitForVBlank();
+            scanKeys();
+            let keys = keysHeld();
+            if (keys & KEY_START) > 0
+            {
+                break;
+            }
+        }
+    }
+    return 0;
+}
+"#;
+
+const GRAPHICS_MAIN_RS: &str = r#"#![no_main]
+#![no_std]
+extern crate alloc;
+use libnds_sys::arm9_bindings::*;
+use core::ffi::*;
+
+/// Display control register of the main engine.
+const REG_DISPCNT: *mut u32 = 0x0400_0000 as *mut u32;
+/// First entry of the main engine's background palette, used as backdrop color.
+const BACKDROP: *mut u16 = 0x0500_0000 as *mut u16;
+
+/// A 15-bit BGR color.
+const fn rgb15(r: u16, g: u16, b: u16) -> u16 {
+    r | (g << 5) | (b << 10)
+}
+
+#[no_mangle]
+extern "C" fn main() -> c_int
+{
+    let colors = [rgb15(31, 0, 0), rgb15(0, 31, 0), rgb15(0, 0, 31), rgb15(31, 31, 0)];
+    let mut current = 0;
+
+    unsafe
+    {
+        // Mode 0 with every layer off shows the backdrop color
+        REG_DISPCNT.write_volatile(0x0001_0000);
+        loop {
+            swiWaitForVBlank();
+            BACKDROP.write_volatile(colors[current]);
+
+            scanKeys();
+            let keys = keysDown();
+            if (keys & KEY_A) > 0
+            {
+                current = (current + 1) % colors.len();
+            }
+            if (keys & KEY_START) > 0
+            {
+                break;
+            }
+        }
+    }
+    return 0;
+}
+"#;
+
+const AUDIO_MAIN_RS: &str = r#"#![no_main]
+#![no_std]
+extern crate alloc;
+use libnds_sys::arm9_bindings::*;
+use libnds_sys::*;
+use core::ffi::*;
+
+/// `DutyCycle_50` in libnds: a square wave.
+const SQUARE_WAVE: u32 = 3;
+
+#[no_mangle]
+extern "C" fn main() -> c_int
+{
+    unsafe
+    {
+        consoleDemoInit();
+        soundEnable();
+        println!("Hold A to play a tone");
+        println!("Press START to exit");
+
+        let mut channel = None;
+        loop {
+            swiWaitForVBlank();
+            scanKeys();
+            let keys = keysHeld();
+            match channel {
+                None if (keys & KEY_A) > 0 => channel = Some(soundPlayPSG(SQUARE_WAVE, 440, 64, 64)),
+                Some(id) if (keys & KEY_A) == 0 => {
+                    soundKill(id);
+                    channel = None;
+                }
+                _ => {}
+            }
+            if (keys & KEY_START) > 0
+            {
+                break;
+            }
+        }
+    }
+    return 0;
+}
+"#;
+
+/// The built-in templates of `cargo nds new --template`, with their `src/main.rs`.
+pub const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("console", CONSOLE_MAIN_RS),
+    ("graphics", GRAPHICS_MAIN_RS),
+    ("audio", AUDIO_MAIN_RS),
+];
+
+/// Files of a template directory which aren't copied to the project.
+const TEMPLATE_EXCLUDES: &[&str] = &["target/**", ".git", "Cargo.lock"];
+
+/// The scaffold of a new project.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Template {
+    /// A built-in template, with the contents of its `src/main.rs`.
+    Builtin(&'static str),
+    /// A local directory whose files are copied to the project, and whose
+    /// `Cargo.toml` is merged into the project's.
+    Dir(PathBuf),
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::Builtin(CONSOLE_MAIN_RS)
+    }
+}
+
+/// Parse the `--template` argument: the name of a built-in template, or the
+/// path of a template directory.
+pub fn parse_template(template: &str) -> Result<Template, String> {
+    if let Some((_, main_rs)) = BUILTIN_TEMPLATES.iter().find(|(name, _)| *name == template) {
+        return Ok(Template::Builtin(main_rs));
+    }
+
+    let path = Path::new(template);
+    if path.is_dir() {
+        return fs::canonicalize(path)
+            .map(Template::Dir)
+            .map_err(|e| format!("could not read {template}: {e}"));
+    }
+
+    let names: Vec<&str> = BUILTIN_TEMPLATES.iter().map(|(name, _)| *name).collect();
+    Err(format!(
+        "`{template}` is neither a template directory nor one of the built-in templates: {}",
+        names.join(", ")
+    ))
+}
+
+/// The files of a template.
+#[derive(Debug, Default)]
+pub struct TemplateFiles {
+    /// Text files, by their path in the project, with their contents.
+    pub text: Vec<(PathBuf, String)>,
+    /// Other files, by their path in the project, with their source path.
+    pub binary: Vec<(PathBuf, PathBuf)>,
+    /// The template's `Cargo.toml`, to merge into the project's.
+    pub manifest: Option<DocumentMut>,
+}
+
+impl Template {
+    /// Read the files of the template, with paths relative to the project.
+    pub fn files(&self) -> Result<TemplateFiles, String> {
+        let dir = match self {
+            Template::Builtin(main_rs) => {
+                return Ok(TemplateFiles {
+                    text: vec![(PathBuf::from("src/main.rs"), main_rs.to_string())],
+                    ..Default::default()
+                })
+            }
+            Template::Dir(dir) => dir,
+        };
+
+        let mut files = romfs::collect_files(std::slice::from_ref(dir))?;
+        let excludes: Vec<String> = TEMPLATE_EXCLUDES.iter().map(ToString::to_string).collect();
+        Filter {
+            include: &[],
+            exclude: &excludes,
+        }
+        .apply(&mut files);
+
+        let mut template = TemplateFiles::default();
+        if let Some(manifest) = files.remove(Path::new("Cargo.toml")) {
+            let manifest = fs::read_to_string(&manifest)
+                .map_err(|e| format!("could not read {}: {e}", manifest.display()))?;
+            template.manifest =
+                Some(manifest.parse().map_err(|e| {
+                    format!("invalid Cargo.toml in template {}: {e}", dir.display())
+                })?);
+        }
+
+        for (relative, source) in files {
+            match fs::read_to_string(&source) {
+                Ok(contents) => template.text.push((relative, contents)),
+                Err(_) => template.binary.push((relative, source)),
+            }
+        }
+
+        Ok(template)
+    }
+}
+
+/// Merge a template's `Cargo.toml` into the project's, in place so that its
+/// comments and formatting are kept. Tables are merged recursively and the
+/// template's values replace the project's, except for the fields of
+/// `[package]` other than `metadata`, which stay the project's own.
+pub fn merge_manifest(manifest: &mut DocumentMut, template: &DocumentMut) {
+    for (key, value) in template.iter() {
+        match (key, value.get("metadata")) {
+            ("package", Some(metadata)) => {
+                let mut metadata_only = toml_edit::Table::new();
+                metadata_only.set_implicit(true);
+                metadata_only.insert("metadata", metadata.clone());
+                merge_item(
+                    manifest.as_table_mut(),
+                    "package",
+                    &Item::Table(metadata_only),
+                );
+            }
+            ("package", None) => {}
+            _ => merge_item(manifest.as_table_mut(), key, value),
+        }
+    }
+}
+
+fn merge_item(table: &mut dyn TableLike, key: &str, value: &Item) {
+    if let (Some(existing), Some(value)) = (
+        table.get_mut(key).and_then(Item::as_table_like_mut),
+        value.as_table_like(),
+    ) {
+        for (key, value) in value.iter() {
+            merge_item(existing, key, value);
+        }
+        return;
+    }
+    table.insert(key, value.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn template_dir() {
+        let dir = TempDir::new("template");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("romfs")).unwrap();
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::write(dir.join("src/main.rs"), "// demo\n").unwrap();
+        fs::write(dir.join("romfs/tiles.bin"), [0xff, 0xfe, 0x00]).unwrap();
+        fs::write(dir.join("target/debug/demo.elf"), "").unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\n\n[package.metadata.nds]\nromfs = \"romfs\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            parse_template("audio"),
+            Ok(Template::Builtin(AUDIO_MAIN_RS))
+        );
+        assert!(parse_template("no-such-template")
+            .unwrap_err()
+            .contains("graphics"));
+
+        let template = parse_template(dir.to_str().unwrap()).unwrap();
+        let files = template.files().unwrap();
+        assert_eq!(
+            files.text,
+            [(PathBuf::from("src/main.rs"), "// demo\n".to_string())]
+        );
+        assert_eq!(
+            files.binary,
+            [(
+                PathBuf::from("romfs/tiles.bin"),
+                dir.join("romfs/tiles.bin")
+            )]
+        );
+        assert!(files.manifest.is_some());
+    }
+
+    #[test]
+    fn merge_manifests() {
+        let mut manifest: DocumentMut =
+            "[package]\nname = \"game\" # ours\n\n[dependencies]\nlog = \"0.4\"\n"
+                .parse()
+                .unwrap();
+        let template: DocumentMut = r#"
+            [package]
+            name = "demo"
+            [package.metadata.nds]
+            romfs = "assets"
+            [dependencies]
+            log = "0.4.20"
+            micromath = "2"
+            "#
+        .parse()
+        .unwrap();
+
+        merge_manifest(&mut manifest, &template);
+        // The project's manifest is edited in place
+        assert!(manifest
+            .to_string()
+            .starts_with("[package]\nname = \"game\" # ours\n"));
+        assert_eq!(manifest["package"]["name"].as_str(), Some("game"));
+        assert_eq!(
+            manifest["package"]["metadata"]["nds"]["romfs"].as_str(),
+            Some("assets")
+        );
+        assert_eq!(manifest["dependencies"]["log"].as_str(), Some("0.4.20"));
+        assert_eq!(manifest["dependencies"]["micromath"].as_str(), Some("2"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Helpers shared by the unit tests.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::{env, fs, process};
+
+/// A temporary directory, removed when dropped so that a failed assertion
+/// doesn't leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty `cargo-nds-<name>-<pid>` directory in the system's
+    /// temporary directory, replacing any left over by an earlier run.
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("cargo-nds-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Hold while a test depends on whether the output is colored, as set by
+/// [`log::set_color`](crate::log::set_color) for the whole process.
+pub fn lock_color() -> MutexGuard<'static, ()> {
+    static COLOR: Mutex<()> = Mutex::new(());
+    COLOR.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+
+use crate::command::CargoFlags;
+use crate::log::info;
+use crate::{asset_dirs, NdsError};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the files have to stay unchanged before rebuilding, so that saving
+/// several files at once only triggers a single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The modification time of every watched file.
+pub(crate) type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/// The arguments of the current `cargo nds` invocation.
+pub fn current_args() -> Vec<OsString> {
+    env::args_os().skip(1).collect()
+}
+
+/// `args` without `flag`, e.g. to run the same command again without
+/// `--watch`. The executable's arguments, after `--`, are kept as they are.
+pub fn args_without(args: &[OsString], flag: &str) -> Vec<OsString> {
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let (cargo_args, exe_args) = args.split_at(end);
+
+    cargo_args
+        .iter()
+        .filter(|arg| *arg != flag)
+        .chain(exe_args)
+        .cloned()
+        .collect()
+}
+
+/// The arguments to run for `cargo nds watch`: the same invocation, with
+/// `watch` replaced by `build`, or `run` to also run the ROM. The options of
+/// `watch` itself are removed, except `--emulator` which `run` takes too.
+pub fn watch_command_args(args: &[OsString], run: bool) -> Vec<OsString> {
+    let mut args = args.iter();
+    let mut command_args = Vec::new();
+    let mut replaced = false;
+
+    for arg in args.by_ref() {
+        if arg == "--" {
+            command_args.push(arg.clone());
+            break;
+        }
+        if !replaced && arg == "watch" {
+            replaced = true;
+            command_args.push(OsString::from(if run { "run" } else { "build" }));
+        } else if !(replaced && (arg == "--run" || arg == "--clear")) {
+            command_args.push(arg.clone());
+        }
+    }
+
+    command_args.extend(args.cloned());
+    command_args
+}
+
+/// Set when Ctrl-C is pressed during [`watch`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Set the Ctrl-C handler of [`watch`], unless an earlier call already did,
+/// and reset [`INTERRUPTED`]. Fails if another handler was set, e.g. by a
+/// program embedding cargo-nds.
+fn set_interrupt_handler() -> Result<(), ctrlc::Error> {
+    static HANDLER_SET: Mutex<bool> = Mutex::new(false);
+
+    let mut handler_set = HANDLER_SET.lock().unwrap();
+    if !*handler_set {
+        ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst))?;
+        *handler_set = true;
+    }
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Re-run cargo-nds with `args` every time a file of the workspace or of its
+/// RomFS and include directories changes, reporting whether each run
+/// succeeded, until Ctrl-C is pressed. With `clear`, the terminal is cleared
+/// before each run.
+///
+/// Each run is a separate process, so failures (which exit the process) don't
+/// stop the watcher. Returns once Ctrl-C is pressed.
+pub fn watch(cargo_flags: &CargoFlags, args: &[OsString], clear: bool) -> Result<(), NdsError> {
+    let metadata = cargo_flags.metadata_command().exec()?;
+    let root = metadata.workspace_root.into_std_path_buf();
+    let ignored = [
+        metadata.target_directory.into_std_path_buf(),
+        root.join(".git"),
+    ];
+    // Assets in the workspace are already watched
+    let mut dirs = vec![root.clone()];
+    dirs.extend(
+        asset_dirs(cargo_flags)?
+            .into_iter()
+            .filter(|dir| !dir.starts_with(&root)),
+    );
+
+    set_interrupt_handler().map_err(NdsError::CtrlCHandler)?;
+
+    let exe = env::current_exe().map_err(|e| NdsError::spawn("cargo-nds", e))?;
+    let mut snapshot = take_snapshot(&dirs, &ignored);
+    let mut cycle = 0;
+    let mut passed = 0;
+
+    loop {
+        cycle += 1;
+        if clear && io::stdout().is_terminal() {
+            print!("\x1b[2J\x1b[H");
+            let _ = io::stdout().flush();
+        }
+        info!("[watch] Run #{cycle}");
+
+        let status = Command::new(&exe)
+            .args(args)
+            .status()
+            .map_err(|e| NdsError::spawn("cargo-nds", e))?;
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if status.success() {
+            passed += 1;
+            info!("[watch] Run #{cycle} passed");
+        } else {
+            eprintln!(
+                "[watch] Run #{cycle} failed (exit code {})",
+                status
+                    .code()
+                    .map_or_else(|| "none".to_string(), |c| c.to_string())
+            );
+        }
+        info!("[watch] Waiting for changes...");
+
+        // Wait for a change, then for the files to settle
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let new_snapshot = take_snapshot(&dirs, &ignored);
+            if new_snapshot != snapshot {
+                snapshot = settle(new_snapshot, &dirs, &ignored);
+                break;
+            }
+        }
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+
+    info!("[watch] Stopped after {cycle} runs, {passed} passed");
+    Ok(())
+}
+
+/// Wait until the files stop changing for [`DEBOUNCE`].
+fn settle(mut snapshot: Snapshot, dirs: &[PathBuf], ignored: &[PathBuf]) -> Snapshot {
+    loop {
+        thread::sleep(DEBOUNCE);
+        let new_snapshot = take_snapshot(dirs, ignored);
+        if new_snapshot == snapshot {
+            return snapshot;
+        }
+        snapshot = new_snapshot;
+    }
+}
+
+/// Collect the modification times of all the files in the directories,
+/// recursively, skipping the `ignored` directories.
+pub(crate) fn take_snapshot(dirs: &[PathBuf], ignored: &[PathBuf]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for dir in dirs {
+        collect_mtimes(dir, ignored, &mut snapshot);
+    }
+    snapshot
+}
+
+fn collect_mtimes(dir: &Path, ignored: &[PathBuf], snapshot: &mut Snapshot) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            if !ignored.contains(&path) {
+                collect_mtimes(&path, ignored, snapshot);
+            }
+        } else if let Ok(modified) = metadata.modified() {
+            snapshot.insert(path, modified);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn snapshot_changes() {
+        let dir = TempDir::new("watch");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+
+        let dirs = [dir.to_path_buf()];
+        let ignored = [dir.join("target")];
+        let snapshot = take_snapshot(&dirs, &ignored);
+        assert_eq!(snapshot.len(), 1);
+
+        // Changes in ignored directories don't count
+        fs::write(dir.join("target/game.nds"), "").unwrap();
+        assert_eq!(take_snapshot(&dirs, &ignored), snapshot);
+
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+        assert_ne!(take_snapshot(&dirs, &ignored), snapshot);
+    }
+
+    #[test]
+    fn interrupt_handler() {
+        // The handler is only set once, so watching again doesn't fail
+        set_interrupt_handler().unwrap();
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        set_interrupt_handler().unwrap();
+        assert!(!INTERRUPTED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn watch_args() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            watch_command_args(
+                &args(&["nds", "-v", "watch", "--clear", "--release"]),
+                false
+            ),
+            args(&["nds", "-v", "build", "--release"])
+        );
+        assert_eq!(
+            watch_command_args(
+                &args(&["nds", "watch", "--run", "--emulator=melonDS", "--", "--run"]),
+                true
+            ),
+            args(&["nds", "run", "--emulator=melonDS", "--", "--run"])
+        );
+
+        assert_eq!(
+            args_without(
+                &args(&["nds", "test", "--watch", "--", "--watch"]),
+                "--watch"
+            ),
+            args(&["nds", "test", "--", "--watch"])
+        );
+    }
+}